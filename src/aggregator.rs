@@ -0,0 +1,162 @@
+use starcoin_types::system_events::SealEvent;
+use std::time::{Duration, Instant};
+use usbderive::derive::SubmitPolicy;
+
+/// Collects solutions found within `window` of the first one and submits one of them.
+///
+/// Without a window every solution is submitted as soon as it arrives.
+pub struct SolutionAggregator {
+    window: Option<Duration>,
+    policy: SubmitPolicy,
+    // first solution received, the chosen one and its device
+    pending: Option<(Instant, SealEvent, String)>,
+    submitted_by: Option<String>,
+}
+
+impl SolutionAggregator {
+    pub fn new(window: Option<Duration>, policy: SubmitPolicy) -> Self {
+        Self {
+            window,
+            policy,
+            pending: None,
+            submitted_by: None,
+        }
+    }
+
+    /// Offer a solution of `device`, returns the solution to submit if the window is
+    /// disabled or already expired.
+    pub fn push(&mut self, seal: SealEvent, device: &str, now: Instant) -> Option<SealEvent> {
+        if self.window.is_none() {
+            self.submitted_by = Some(device.to_string());
+            return Some(seal);
+        }
+        self.pending = match self.pending.take() {
+            None => Some((now, seal, device.to_string())),
+            Some((first_at, current, current_device)) => {
+                if self.prefer(&seal, device, &current, &current_device) {
+                    Some((first_at, seal, device.to_string()))
+                } else {
+                    Some((first_at, current, current_device))
+                }
+            }
+        };
+        self.poll(now)
+    }
+
+    /// The chosen solution once the window after the first one has elapsed.
+    pub fn poll(&mut self, now: Instant) -> Option<SealEvent> {
+        let window = self.window?;
+        match &self.pending {
+            Some((first_at, _, _)) if now.saturating_duration_since(*first_at) >= window => {
+                self.flush()
+            }
+            _ => None,
+        }
+    }
+
+    /// The chosen solution without waiting for the window to end.
+    pub fn flush(&mut self) -> Option<SealEvent> {
+        let (_, seal, device) = self.pending.take()?;
+        self.submitted_by = Some(device);
+        Some(seal)
+    }
+
+    /// Device of the solution handed out last.
+    pub fn submitted_by(&self) -> Option<&str> {
+        self.submitted_by.as_deref()
+    }
+
+    // whether the candidate beats the current choice, ties keep the earlier one
+    fn prefer(
+        &self,
+        candidate: &SealEvent,
+        device: &str,
+        current: &SealEvent,
+        current_device: &str,
+    ) -> bool {
+        match &self.policy {
+            SubmitPolicy::First => false,
+            // Same length hex strings compare like the big endian hashes they encode.
+            SubmitPolicy::LowestHash => candidate.hash_result < current.hash_result,
+            SubmitPolicy::DevicePriority(devices) => {
+                let rank = |device: &str| {
+                    devices
+                        .iter()
+                        .position(|listed| listed == device)
+                        .unwrap_or(devices.len())
+                };
+                rank(device) < rank(current_device)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seal(nonce: u32, hash: u8) -> SealEvent {
+        SealEvent {
+            minting_blob: vec![0u8; 76],
+            nonce,
+            extra: None,
+            hash_result: hex::encode([hash; 32]),
+        }
+    }
+
+    #[test]
+    fn test_no_window_submits_immediately() {
+        let mut aggregator = SolutionAggregator::new(None, SubmitPolicy::LowestHash);
+        let submitted = aggregator.push(seal(1, 0x20), "A1", Instant::now());
+        assert_eq!(submitted.map(|s| s.nonce), Some(1));
+        assert_eq!(aggregator.submitted_by(), Some("A1"));
+    }
+
+    #[test]
+    fn test_first_policy() {
+        let start = Instant::now();
+        let window = Duration::from_millis(100);
+        let mut aggregator = SolutionAggregator::new(Some(window), SubmitPolicy::First);
+        assert!(aggregator.push(seal(1, 0x20), "A1", start).is_none());
+        assert!(aggregator
+            .push(seal(2, 0x10), "B2", start + Duration::from_millis(5))
+            .is_none());
+        assert!(aggregator.poll(start + Duration::from_millis(50)).is_none());
+        let submitted = aggregator.poll(start + window);
+        assert_eq!(submitted.map(|s| s.nonce), Some(1));
+        assert!(aggregator.poll(start + window * 2).is_none());
+    }
+
+    #[test]
+    fn test_lowest_hash_policy() {
+        let start = Instant::now();
+        let window = Duration::from_millis(100);
+        let mut aggregator = SolutionAggregator::new(Some(window), SubmitPolicy::LowestHash);
+        assert!(aggregator.push(seal(1, 0x20), "A1", start).is_none());
+        assert!(aggregator
+            .push(seal(2, 0x10), "B2", start + Duration::from_millis(5))
+            .is_none());
+        let submitted = aggregator.poll(start + window);
+        assert_eq!(submitted.map(|s| s.nonce), Some(2));
+    }
+
+    #[test]
+    fn test_device_priority_policy() {
+        let start = Instant::now();
+        let window = Duration::from_millis(100);
+        let policy = SubmitPolicy::DevicePriority(vec!["C3".to_string(), "B2".to_string()]);
+        let mut aggregator = SolutionAggregator::new(Some(window), policy);
+        let later = |ms| start + Duration::from_millis(ms);
+        assert!(aggregator.push(seal(1, 0x10), "A1", start).is_none());
+        assert!(aggregator.push(seal(2, 0x30), "B2", later(5)).is_none());
+        assert!(aggregator.push(seal(3, 0x20), "D4", later(10)).is_none());
+        let submitted = aggregator.poll(start + window);
+        assert_eq!(submitted.map(|s| s.nonce), Some(2));
+        assert_eq!(aggregator.submitted_by(), Some("B2"));
+
+        // unlisted devices keep the first received
+        assert!(aggregator.push(seal(4, 0x10), "A1", later(200)).is_none());
+        assert!(aggregator.push(seal(5, 0x00), "D4", later(201)).is_none());
+        assert_eq!(aggregator.flush().map(|s| s.nonce), Some(4));
+    }
+}
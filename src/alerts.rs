@@ -0,0 +1,356 @@
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use usbderive::{Config, State};
+
+// Shares needed before the reject rate means anything.
+const MIN_SHARES: u64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+/// A device crossed a health boundary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alert {
+    pub severity: Severity,
+    pub serial: String,
+    pub message: String,
+}
+
+/// Turns device health readings into alerts, identical alerts are sent at most once per interval.
+#[derive(Clone)]
+pub struct AlertMonitor {
+    alerts_tx: Option<UnboundedSender<Alert>>,
+    interval: Duration,
+    last_sent: HashMap<(String, String), Instant>,
+    // by device serial
+    consecutive_naks: HashMap<String, u32>,
+    // accepted and rejected shares by device serial
+    shares: HashMap<String, (u64, u64)>,
+}
+
+impl AlertMonitor {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            alerts_tx: None,
+            interval,
+            last_sent: HashMap::new(),
+            consecutive_naks: HashMap::new(),
+            shares: HashMap::new(),
+        }
+    }
+
+    /// Start sending alerts, a new call replaces the previous receiver.
+    pub fn subscribe(&mut self) -> UnboundedReceiver<Alert> {
+        let (alerts_tx, alerts_rx) = mpsc::unbounded();
+        self.alerts_tx = Some(alerts_tx);
+        alerts_rx
+    }
+
+    pub fn check_state(&mut self, serial: &str, state: &State, config: &Config, now: Instant) {
+        if config.should_throttle(state) {
+            let message = match state.temperature() {
+                Some(temp) => format!("Overheat, temperature {}", temp),
+                None => "Temperature unknown".to_string(),
+            };
+            self.emit(Severity::Critical, serial, message, now);
+        }
+        if config.voltage_off(state) {
+            let deviation = state.voltage_deviation(config.target_voltage);
+            let kind = if deviation > 0 {
+                "Over-voltage"
+            } else {
+                "Under-voltage"
+            };
+            let message = format!(
+                "{}, {}mV against target {}mV",
+                kind, state.voltage, config.target_voltage
+            );
+            // the reading drifts, debounce on the kind of alert
+            self.emit_keyed(Severity::Warning, serial, kind, message, now);
+        }
+        if state.goodcores < state.cores {
+            let message = format!("Core loss, {}/{} cores good", state.goodcores, state.cores);
+            self.emit(Severity::Warning, serial, message, now);
+        }
+        if let Some((min, max)) = state
+            .core_freq_range
+            .filter(|_| config.freq_spread_off(state))
+        {
+            let message = format!("Core frequencies spread from {} to {}MHz", min, max);
+            self.emit_keyed(Severity::Warning, serial, "freq spread", message, now);
+        }
+    }
+
+    /// A reply of the device could not be parsed, alerts once `limit` happen in a row.
+    pub fn record_nak(&mut self, serial: &str, limit: u32, now: Instant) {
+        let naks = self.consecutive_naks.entry(serial.to_string()).or_insert(0);
+        *naks += 1;
+        if *naks >= limit {
+            let message = format!("{} consecutive bad replies", limit);
+            self.emit(Severity::Warning, serial, message, now);
+        }
+    }
+
+    /// The device sent a good reply, its run of bad ones is over.
+    pub fn reset_naks(&mut self, serial: &str) {
+        self.consecutive_naks.remove(serial);
+    }
+
+    pub fn record_share(
+        &mut self,
+        serial: &str,
+        accepted: bool,
+        max_reject_rate: f64,
+        now: Instant,
+    ) {
+        let shares = self.shares.entry(serial.to_string()).or_insert((0, 0));
+        if accepted {
+            shares.0 += 1;
+        } else {
+            shares.1 += 1;
+        }
+        let (accepted, rejected) = *shares;
+        let total = accepted + rejected;
+        if total < MIN_SHARES {
+            return;
+        }
+        let rate = rejected as f64 / total as f64;
+        if rate > max_reject_rate {
+            let message = format!("High reject rate, {}/{} shares rejected", rejected, total);
+            // the counts change with every share, debounce on the kind of alert
+            self.emit_keyed(Severity::Warning, serial, "reject rate", message, now);
+        }
+    }
+
+    /// A device tripped its NAK breaker and is left alone for `cooldown`.
+    pub fn record_breaker_trip(
+        &mut self,
+        serial: &str,
+        naks: u32,
+        cooldown: Duration,
+        now: Instant,
+    ) {
+        let message = format!(
+            "Unhealthy after {} consecutive bad replies, retry in {}s",
+            naks,
+            cooldown.as_secs()
+        );
+        self.emit_keyed(Severity::Critical, serial, "nak breaker", message, now);
+    }
+
+    /// Bad frames and solutions for foreign job ids, as when two processes drive a device.
+    pub fn record_contention(&mut self, serial: &str, now: Instant) {
+        let message =
+            "Bad frames and foreign jobs, another process may be using the device".to_string();
+        self.emit_keyed(Severity::Critical, serial, "contention", message, now);
+    }
+
+    /// The device is still listed on the bus but did not answer a ping.
+    pub fn record_unresponsive(&mut self, serial: &str, now: Instant) {
+        let message = "Not answering a ping".to_string();
+        self.emit_keyed(Severity::Critical, serial, "unresponsive", message, now);
+    }
+
+    pub fn record_unknown_responses(&mut self, serial: &str, count: u64, now: Instant) {
+        let message = format!("{} responses of unknown type", count);
+        self.emit_keyed(Severity::Warning, serial, "unknown response", message, now);
+    }
+
+    fn emit(&mut self, severity: Severity, serial: &str, message: String, now: Instant) {
+        let key = message.clone();
+        self.emit_keyed(severity, serial, &key, message, now);
+    }
+
+    fn emit_keyed(
+        &mut self,
+        severity: Severity,
+        serial: &str,
+        key: &str,
+        message: String,
+        now: Instant,
+    ) {
+        let alerts_tx = match &self.alerts_tx {
+            Some(alerts_tx) => alerts_tx,
+            None => return,
+        };
+        let key = (serial.to_string(), key.to_string());
+        if let Some(at) = self.last_sent.get(&key) {
+            if now.saturating_duration_since(*at) < self.interval {
+                return;
+            }
+        }
+        let _ = alerts_tx.unbounded_send(Alert {
+            severity,
+            serial: serial.to_string(),
+            message,
+        });
+        self.last_sent.insert(key, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(temp: u8, cores: u8, goodcores: u8) -> State {
+        state_with_voltage(temp, cores, goodcores, 750)
+    }
+
+    fn state_with_voltage(temp: u8, cores: u8, goodcores: u8, voltage: u16) -> State {
+        let mut raw_data = vec![0u8; 29];
+        raw_data[10] = cores;
+        raw_data[11] = goodcores;
+        raw_data[15..17].copy_from_slice(&voltage.to_le_bytes());
+        raw_data[23] = temp;
+        State::new(&raw_data).unwrap()
+    }
+
+    fn drain(alerts_rx: &mut UnboundedReceiver<Alert>) -> Vec<Alert> {
+        let mut alerts = vec![];
+        while let Ok(Some(alert)) = alerts_rx.try_next() {
+            alerts.push(alert);
+        }
+        alerts
+    }
+
+    #[test]
+    fn test_state_alerts() {
+        let now = Instant::now();
+        let config = Config::default();
+        let mut monitor = AlertMonitor::new(Duration::from_secs(60));
+        let mut alerts_rx = monitor.subscribe();
+
+        monitor.check_state("A1", &state(60, 8, 8), &config, now);
+        assert!(drain(&mut alerts_rx).is_empty());
+
+        monitor.check_state("A1", &state(90, 8, 8), &config, now);
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, Severity::Critical);
+        assert_eq!(alerts[0].serial, "A1");
+        assert!(alerts[0].message.contains("Overheat"));
+
+        monitor.check_state("A1", &state(60, 8, 6), &config, now);
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].message.contains("Core loss"));
+    }
+
+    #[test]
+    fn test_voltage_alerts() {
+        let now = Instant::now();
+        let config = Config::default();
+        let mut monitor = AlertMonitor::new(Duration::from_secs(60));
+        let mut alerts_rx = monitor.subscribe();
+
+        monitor.check_state("A1", &state_with_voltage(60, 8, 8, 790), &config, now);
+        assert!(drain(&mut alerts_rx).is_empty());
+
+        monitor.check_state("A1", &state_with_voltage(60, 8, 8, 820), &config, now);
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            alerts[0].message,
+            "Over-voltage, 820mV against target 750mV"
+        );
+
+        monitor.check_state("A1", &state_with_voltage(60, 8, 8, 680), &config, now);
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert!(alerts[0].message.starts_with("Under-voltage"));
+    }
+
+    #[test]
+    fn test_freq_spread_alert() {
+        let now = Instant::now();
+        let config = Config::default();
+        let mut monitor = AlertMonitor::new(Duration::from_secs(60));
+        let mut alerts_rx = monitor.subscribe();
+
+        let mut spread = state(60, 8, 8);
+        spread.core_freq_range = Some((590, 600));
+        monitor.check_state("A1", &spread, &config, now);
+        assert!(drain(&mut alerts_rx).is_empty());
+
+        spread.core_freq_range = Some((540, 600));
+        monitor.check_state("A1", &spread, &config, now);
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            alerts[0].message,
+            "Core frequencies spread from 540 to 600MHz"
+        );
+    }
+
+    #[test]
+    fn test_nak_and_reject_alerts() {
+        let now = Instant::now();
+        let mut monitor = AlertMonitor::new(Duration::from_secs(60));
+        let mut alerts_rx = monitor.subscribe();
+
+        monitor.record_nak("A1", 3, now);
+        monitor.record_nak("A1", 3, now);
+        monitor.reset_naks("A1");
+        monitor.record_nak("A1", 3, now);
+        assert!(drain(&mut alerts_rx).is_empty());
+        // good replies of another device do not end the run
+        monitor.record_nak("A1", 3, now);
+        monitor.reset_naks("B2");
+        monitor.record_nak("B2", 3, now);
+        monitor.record_nak("A1", 3, now);
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].serial, "A1");
+        assert!(alerts[0].message.contains("bad replies"));
+
+        // the rejects of one device are not spread over the shares of another
+        for _ in 0..MIN_SHARES {
+            monitor.record_share("B2", true, 0.1, now);
+        }
+        for i in 0..MIN_SHARES {
+            monitor.record_share("A1", i % 2 == 0, 0.1, now);
+        }
+        let alerts = drain(&mut alerts_rx);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].serial, "A1");
+        assert!(alerts[0].message.contains("reject rate"));
+    }
+
+    #[test]
+    fn test_repeated_alerts_debounced() {
+        let now = Instant::now();
+        let config = Config::default();
+        let mut monitor = AlertMonitor::new(Duration::from_secs(60));
+        let mut alerts_rx = monitor.subscribe();
+
+        monitor.check_state("A1", &state(90, 8, 8), &config, now);
+        monitor.check_state(
+            "A1",
+            &state(90, 8, 8),
+            &config,
+            now + Duration::from_secs(10),
+        );
+        assert_eq!(drain(&mut alerts_rx).len(), 1);
+
+        // another device is not held back
+        monitor.check_state(
+            "B2",
+            &state(90, 8, 8),
+            &config,
+            now + Duration::from_secs(10),
+        );
+        assert_eq!(drain(&mut alerts_rx).len(), 1);
+
+        monitor.check_state(
+            "A1",
+            &state(90, 8, 8),
+            &config,
+            now + Duration::from_secs(61),
+        );
+        assert_eq!(drain(&mut alerts_rx).len(), 1);
+    }
+}
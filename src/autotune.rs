@@ -0,0 +1,222 @@
+//! Frequency autotune of several boards at once.
+//!
+//! Every board is tuned on its own thread that owns its `UsbDerive`, so no two
+//! boards share a serial port and a board that misbehaves cannot skew the
+//! measurements of another or hold it up. A sweep that panics leaves its board
+//! at the last settings it ran stable at.
+
+use starcoin_logger::prelude::*;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use usbderive::{Config, UsbDerive};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TuneResult {
+    pub freq: u16,
+    pub voltage: u16,
+    /// False if no candidate frequency was stable, the board is left at the safe defaults.
+    pub stable: bool,
+}
+
+/// Tune each board to the highest of `freqs` (ascending) it runs stable at.
+/// Returns the boards and the result of each, keyed by serial.
+pub fn autotune(
+    derives: Vec<UsbDerive>,
+    freqs: &[u16],
+    voltage: u16,
+) -> (Vec<UsbDerive>, HashMap<String, TuneResult>) {
+    autotune_with(derives, freqs, voltage, is_stable)
+}
+
+fn autotune_with(
+    derives: Vec<UsbDerive>,
+    freqs: &[u16],
+    voltage: u16,
+    stable: fn(&mut UsbDerive) -> bool,
+) -> (Vec<UsbDerive>, HashMap<String, TuneResult>) {
+    let handles: Vec<_> = derives
+        .into_iter()
+        .map(|derive| {
+            let freqs = freqs.to_vec();
+            thread::spawn(move || tune_device(derive, &freqs, voltage, stable))
+        })
+        .collect();
+    let mut tuned = vec![];
+    let mut results = HashMap::new();
+    for handle in handles {
+        match handle.join() {
+            Ok((derive, result)) => {
+                results.insert(derive.id(), result);
+                tuned.push(derive);
+            }
+            Err(_) => error!("Autotune thread panicked, board dropped"),
+        }
+    }
+    (tuned, results)
+}
+
+fn tune_device(
+    mut derive: UsbDerive,
+    freqs: &[u16],
+    voltage: u16,
+    stable: fn(&mut UsbDerive) -> bool,
+) -> (UsbDerive, TuneResult) {
+    let mut best = None;
+    let swept = panic::catch_unwind(AssertUnwindSafe(|| {
+        for freq in freqs {
+            // never tune past the ceiling the board reports
+            if derive.max_freq().map_or(false, |max_freq| *freq > max_freq) {
+                break;
+            }
+            if derive.set_freq_voltage(*freq, voltage).is_err() {
+                break;
+            }
+            thread::sleep(derive.config().warmup);
+            if !stable(&mut derive) {
+                break;
+            }
+            best = Some(*freq);
+        }
+    }));
+    if swept.is_err() {
+        error!(
+            "Autotune of {} panicked, keep the last stable settings",
+            derive.id()
+        );
+    }
+    let result = match best {
+        Some(freq) => {
+            if derive.config().target_freq != freq {
+                let _ = derive.set_freq_voltage(freq, voltage);
+            }
+            TuneResult {
+                freq,
+                voltage,
+                stable: true,
+            }
+        }
+        None => {
+            let defaults = Config::default();
+            warn!("Board {} is unstable, left at defaults", derive.id());
+            let _ = derive.set_freq_voltage(defaults.target_freq, defaults.target_voltage);
+            TuneResult {
+                freq: defaults.target_freq,
+                voltage: defaults.target_voltage,
+                stable: false,
+            }
+        }
+    };
+    (derive, result)
+}
+
+fn is_stable(derive: &mut UsbDerive) -> bool {
+    match derive.get_state() {
+        Ok(state) => {
+            let config = derive.config();
+            state.goodcores == state.cores
+                && !config.should_throttle(&state)
+                && !config.freq_spread_off(&state)
+        }
+        Err(e) => {
+            debug!(
+                "Board {} state failed during autotune: {:?}",
+                derive.id(),
+                e
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use usbderive::mock::MockPort;
+
+    const ACK: [u8; 12] = [
+        0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+    ];
+
+    fn state_frame(goodcores: u8) -> Vec<u8> {
+        let mut frame = vec![0u8; 29];
+        frame[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        frame[10] = 8;
+        frame[11] = goodcores;
+        frame[23] = 60;
+        frame[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        frame
+    }
+
+    fn mock_derive(serial: &str, goodcores: &[u8]) -> UsbDerive {
+        let port = MockPort::new();
+        for cores in goodcores {
+            port.push_response(&ACK);
+            port.push_response(&state_frame(*cores));
+        }
+        let mut config = Config::default();
+        config.command_timeouts.set_hw_params = std::time::Duration::from_millis(10);
+        config.warmup = std::time::Duration::from_millis(0);
+        UsbDerive::from_port(port.boxed(), Some(serial.to_string()), config)
+    }
+
+    #[test]
+    fn test_autotune_panic() {
+        // B panics checking 650, after 600 was stable
+        fn stable(derive: &mut UsbDerive) -> bool {
+            if derive.id() == "B" && derive.config().target_freq == 650 {
+                panic!("state parser bug");
+            }
+            is_stable(derive)
+        }
+        let derives = vec![mock_derive("A", &[8, 8]), mock_derive("B", &[8, 8])];
+        let (tuned, results) = autotune_with(derives, &[600, 650], 800, stable);
+
+        assert_eq!(tuned.len(), 2);
+        assert_eq!(results["A"].freq, 650);
+        assert_eq!(
+            results["B"],
+            TuneResult {
+                freq: 600,
+                voltage: 800,
+                stable: true,
+            }
+        );
+        let b = tuned.iter().find(|derive| derive.id() == "B").unwrap();
+        assert_eq!(b.config().target_freq, 600);
+    }
+
+    #[test]
+    fn test_autotune_isolated() {
+        // A holds all cores up to 700, B loses cores at 650, C never stabilizes
+        let derives = vec![
+            mock_derive("A", &[8, 8, 8]),
+            mock_derive("B", &[8, 7]),
+            mock_derive("C", &[6]),
+        ];
+        let (tuned, results) = autotune(derives, &[600, 650, 700], 800);
+
+        assert_eq!(tuned.len(), 3);
+        assert_eq!(
+            results["A"],
+            TuneResult {
+                freq: 700,
+                voltage: 800,
+                stable: true,
+            }
+        );
+        assert_eq!(results["B"].freq, 600);
+        assert!(results["B"].stable);
+        assert_eq!(
+            results["C"],
+            TuneResult {
+                freq: 600,
+                voltage: 750,
+                stable: false,
+            }
+        );
+        for derive in &tuned {
+            assert_eq!(derive.config().target_freq, results[&derive.id()].freq);
+        }
+    }
+}
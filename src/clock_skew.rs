@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+use usbderive::ErrorLogEntry;
+
+/// Drift of the device clock against the host clock, measured from pairs of
+/// device reported time and the host time the report was read at.
+///
+/// Device time going backwards means the device rebooted and restarts the calibration.
+#[derive(Clone, Debug, Default)]
+pub struct ClockSkew {
+    anchor: Option<(Duration, Instant)>,
+    latest: Option<(Duration, Instant)>,
+    // newest entry time of the last error log read
+    log_newest: Option<u32>,
+}
+
+impl ClockSkew {
+    pub fn sample(&mut self, device_time: Duration, host_time: Instant) {
+        match self.anchor {
+            Some((anchor_device, _)) if device_time >= anchor_device => {
+                self.latest = Some((device_time, host_time));
+            }
+            _ => {
+                self.anchor = Some((device_time, host_time));
+                self.latest = None;
+            }
+        }
+    }
+
+    /// Sample the newest entry of an error log read at `host_time`. The firmware
+    /// reports no clock of its own, an entry the previous read did not have was
+    /// logged since then, so the more often the log is read the closer its time
+    /// is to `host_time`. The first read only tells what is old.
+    pub fn sample_error_log(&mut self, entries: &[ErrorLogEntry], host_time: Instant) {
+        let newest = match entries.iter().map(|entry| entry.timestamp).max() {
+            Some(newest) => newest,
+            None => return,
+        };
+        let fresh = self.log_newest.map_or(false, |seen| newest != seen);
+        self.log_newest = Some(newest);
+        if fresh {
+            self.sample(Duration::from_secs(u64::from(newest)), host_time);
+        }
+    }
+
+    /// Device seconds per host second minus one, `None` until two samples are apart in host time.
+    pub fn skew(&self) -> Option<f64> {
+        let (anchor_device, anchor_host) = self.anchor?;
+        let (latest_device, latest_host) = self.latest?;
+        let host = latest_host
+            .saturating_duration_since(anchor_host)
+            .as_secs_f64();
+        if host <= 0.0 {
+            return None;
+        }
+        let device = (latest_device - anchor_device).as_secs_f64();
+        Some(device / host - 1.0)
+    }
+
+    /// Skew in parts per million, for diagnostics.
+    pub fn skew_ppm(&self) -> Option<f64> {
+        self.skew().map(|skew| skew * 1_000_000.0)
+    }
+
+    /// Host time elapsed while the device clock measured `device`.
+    pub fn to_host(&self, device: Duration) -> Duration {
+        match self.skew() {
+            Some(skew) => Duration::from_secs_f64(device.as_secs_f64() / (1.0 + skew)),
+            None => device,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compensate_known_skew() {
+        let start = Instant::now();
+        let mut clock = ClockSkew::default();
+        assert!(clock.skew().is_none());
+        assert_eq!(
+            clock.to_host(Duration::from_secs(10)),
+            Duration::from_secs(10)
+        );
+
+        // the device clock runs 1% fast
+        clock.sample(Duration::from_secs(100), start);
+        clock.sample(Duration::from_secs(200), start + Duration::from_secs(99));
+        clock.sample(Duration::from_secs(302), start + Duration::from_secs(200));
+        let ppm = clock.skew_ppm().unwrap();
+        assert!((ppm - 10_000.0).abs() < 1.0, "skew {} ppm", ppm);
+        let host = clock.to_host(Duration::from_secs(101));
+        assert!((host.as_secs_f64() - 100.0).abs() < 1e-6);
+    }
+
+    fn error_log(timestamps: &[u32]) -> Vec<ErrorLogEntry> {
+        timestamps
+            .iter()
+            .map(|&timestamp| ErrorLogEntry { code: 7, timestamp })
+            .collect()
+    }
+
+    #[test]
+    fn test_calibrate_from_error_log() {
+        let start = Instant::now();
+        let mut clock = ClockSkew::default();
+        // entries from before the first read may be of any age
+        clock.sample_error_log(&error_log(&[3, 40]), start);
+        clock.sample_error_log(&error_log(&[3, 40]), start + Duration::from_secs(60));
+        assert!(clock.skew().is_none());
+
+        // the device clock runs 0.5% slow, new entries are read as they are logged
+        clock.sample_error_log(
+            &error_log(&[3, 40, 1040]),
+            start + Duration::from_secs(1100),
+        );
+        clock.sample_error_log(
+            &error_log(&[40, 1040, 3030]),
+            start + Duration::from_secs(3100),
+        );
+        let ppm = clock.skew_ppm().unwrap();
+        assert!((ppm + 5_000.0).abs() < 1.0, "skew {} ppm", ppm);
+        let host = clock.to_host(Duration::from_secs(199));
+        assert!((host.as_secs_f64() - 200.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_device_reboot_restarts_calibration() {
+        let start = Instant::now();
+        let mut clock = ClockSkew::default();
+        clock.sample(Duration::from_secs(100), start);
+        clock.sample(Duration::from_secs(202), start + Duration::from_secs(100));
+        assert!(clock.skew().is_some());
+
+        clock.sample(Duration::from_secs(5), start + Duration::from_secs(110));
+        assert!(clock.skew().is_none());
+        clock.sample(Duration::from_secs(105), start + Duration::from_secs(210));
+        assert!(clock.skew_ppm().unwrap().abs() < 1.0);
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Spots a device driven by two processes at once, e.g. a second solver started
+/// on the same port. The other process's jobs show up as solutions for job ids
+/// this one never issued, and the two reading the same stream tear frames apart.
+/// Either alone happens on a healthy link, both within `window` are suspicious.
+#[derive(Clone, Debug)]
+pub struct ContentionDetector {
+    window: Duration,
+    limit: usize,
+    bad_frames: VecDeque<Instant>,
+    foreign_solutions: VecDeque<Instant>,
+}
+
+impl ContentionDetector {
+    pub fn new(window: Duration, limit: u32) -> Self {
+        Self {
+            window,
+            limit: limit as usize,
+            bad_frames: VecDeque::new(),
+            foreign_solutions: VecDeque::new(),
+        }
+    }
+
+    /// Count a frame that could not be parsed, true if contention is suspected.
+    pub fn record_bad_frame(&mut self, now: Instant) -> bool {
+        self.bad_frames.push_back(now);
+        self.check(now)
+    }
+
+    /// Count a solution for a job id never issued, true if contention is suspected.
+    pub fn record_foreign_solution(&mut self, now: Instant) -> bool {
+        self.foreign_solutions.push_back(now);
+        self.check(now)
+    }
+
+    fn check(&mut self, now: Instant) -> bool {
+        let window = self.window;
+        for events in [&mut self.bad_frames, &mut self.foreign_solutions].iter_mut() {
+            while events
+                .front()
+                .map_or(false, |at| now.saturating_duration_since(*at) > window)
+            {
+                events.pop_front();
+            }
+        }
+        if self.bad_frames.len() < self.limit || self.foreign_solutions.len() < self.limit {
+            return false;
+        }
+        self.bad_frames.clear();
+        self.foreign_solutions.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contention_needs_both_signs() {
+        let start = Instant::now();
+        let mut detector = ContentionDetector::new(Duration::from_secs(60), 2);
+        // bad frames alone, and foreign solutions spread too far apart, do not count
+        assert!(!detector.record_bad_frame(start));
+        assert!(!detector.record_bad_frame(start));
+        assert!(!detector.record_bad_frame(start));
+        assert!(!detector.record_foreign_solution(start));
+        let later = start + Duration::from_secs(61);
+        assert!(!detector.record_foreign_solution(later));
+        assert!(!detector.record_bad_frame(later));
+        assert!(!detector.record_bad_frame(later));
+
+        assert!(detector.record_foreign_solution(later));
+        // counting starts over once reported
+        assert!(!detector.record_bad_frame(later));
+    }
+}
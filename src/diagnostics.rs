@@ -0,0 +1,26 @@
+use crate::telemetry::SolverStats;
+use serde::Serialize;
+use usbderive::{Config, DeriveStats, DeviceMetadata, ErrorLogEntry, State};
+
+/// Everything known about one device, a query that failed is `None`.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceReport {
+    pub serial: String,
+    pub metadata: DeviceMetadata,
+    pub config: Config,
+    pub state: Option<State>,
+    pub stats: DeriveStats,
+    pub error_log: Option<Vec<ErrorLogEntry>>,
+    /// Drift of the device clock against the host in parts per million, measured
+    /// from the error log reads of past reports. `None` until two fresh entries.
+    pub clock_skew_ppm: Option<f64>,
+    /// Last frames read in the solve loop, oldest first.
+    pub recent_frames: Vec<String>,
+}
+
+/// Snapshot of a solver and its devices to attach to bug reports.
+#[derive(Clone, Debug, Serialize)]
+pub struct DiagnosticReport {
+    pub solver: SolverStats,
+    pub devices: Vec<DeviceReport>,
+}
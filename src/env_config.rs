@@ -0,0 +1,143 @@
+use crate::usb_solver::{PID, VID};
+use starcoin_logger::prelude::*;
+use std::fmt::{Debug, Display};
+use std::num::ParseIntError;
+use std::path::PathBuf;
+use usbderive::Config;
+
+/// USB vendor id, decimal or hex with a `0x` prefix.
+pub const VID_VAR: &str = "USBSOLVER_VID";
+/// USB product id, decimal or hex with a `0x` prefix.
+pub const PID_VAR: &str = "USBSOLVER_PID";
+/// Target frequency in MHz.
+pub const FREQ_VAR: &str = "USBSOLVER_FREQ";
+/// Target voltage in mV.
+pub const VOLTAGE_VAR: &str = "USBSOLVER_VOLTAGE";
+pub const BAUD_VAR: &str = "USBSOLVER_BAUD";
+/// CSV file a row per device and job is appended to.
+pub const JOB_LOG_VAR: &str = "USBSOLVER_JOB_LOG";
+/// File the searched nonce positions are saved to, to resume a job after a restart.
+pub const NONCE_POSITIONS_VAR: &str = "USBSOLVER_NONCE_POSITIONS";
+
+/// Solver settings from the `USBSOLVER_*` environment variables. An unset
+/// variable keeps the default; one that is set but does not parse logs a
+/// warning and keeps the default too.
+#[derive(Clone, Debug)]
+pub struct EnvConfig {
+    pub vid: u16,
+    pub pid: u16,
+    pub config: Config,
+}
+
+impl EnvConfig {
+    pub fn from_env() -> Self {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let defaults = Config::default();
+        let config = Config {
+            target_freq: parse_var(&lookup, FREQ_VAR, defaults.target_freq, str::parse),
+            target_voltage: parse_var(&lookup, VOLTAGE_VAR, defaults.target_voltage, str::parse),
+            baud_rate: parse_var(&lookup, BAUD_VAR, defaults.baud_rate, str::parse),
+            job_log: lookup(JOB_LOG_VAR)
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+            nonce_positions: lookup(NONCE_POSITIONS_VAR)
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+            ..defaults
+        };
+        Self {
+            vid: parse_var(&lookup, VID_VAR, VID, parse_id),
+            pid: parse_var(&lookup, PID_VAR, PID, parse_id),
+            config,
+        }
+    }
+}
+
+fn parse_var<T, E, F, P>(lookup: &F, name: &str, default: T, parse: P) -> T
+where
+    T: Display,
+    E: Debug,
+    F: Fn(&str) -> Option<String>,
+    P: Fn(&str) -> Result<T, E>,
+{
+    let value = match lookup(name) {
+        Some(value) => value,
+        None => return default,
+    };
+    match parse(value.trim()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!(
+                "Ignore {}={:?}, use the default {}: {:?}",
+                name, value, default, e
+            );
+            default
+        }
+    }
+}
+
+fn parse_id(value: &str) -> Result<u16, ParseIntError> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_config(vars: &[(&str, &str)]) -> EnvConfig {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        EnvConfig::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_defaults_when_unset() {
+        let env = env_config(&[]);
+        assert_eq!((env.vid, env.pid), (VID, PID));
+        assert_eq!(env.config.target_freq, Config::default().target_freq);
+        assert_eq!(env.config.baud_rate, 115200);
+    }
+
+    #[test]
+    fn test_parse_vars() {
+        let env = env_config(&[
+            (VID_VAR, "0x1a86"),
+            (PID_VAR, "29987"),
+            (FREQ_VAR, " 650 "),
+            (BAUD_VAR, "230400"),
+            (JOB_LOG_VAR, "/var/log/usbsolver.csv"),
+            (NONCE_POSITIONS_VAR, "/var/lib/usbsolver/nonces"),
+        ]);
+        assert_eq!((env.vid, env.pid), (0x1a86, 29987));
+        assert_eq!(env.config.target_freq, 650);
+        assert_eq!(env.config.baud_rate, 230400);
+        assert_eq!(
+            env.config.job_log,
+            Some(PathBuf::from("/var/log/usbsolver.csv"))
+        );
+        assert_eq!(
+            env.config.nonce_positions,
+            Some(PathBuf::from("/var/lib/usbsolver/nonces"))
+        );
+    }
+
+    #[test]
+    fn test_unparsable_var_keeps_default() {
+        let env = env_config(&[(VID_VAR, "0xzz"), (FREQ_VAR, "fast"), (VOLTAGE_VAR, "800")]);
+        assert_eq!(env.vid, VID);
+        assert_eq!(env.config.target_freq, Config::default().target_freq);
+        assert_eq!(env.config.target_voltage, 800);
+    }
+}
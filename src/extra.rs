@@ -0,0 +1,229 @@
+//! Layout of the minting blob as seen by the device.
+//!
+//! The device hashes a fixed 76-byte header, the same size as the Monero pow
+//! input. It is the leading `HEADER_LEN` bytes of the minting blob, so the
+//! extra and nonce offsets below are valid both in the blob and in the header
+//! that gets uploaded. Any bytes past the header are never sent to the device.
+
+use anyhow::Result;
+use starcoin_types::block::BlockHeaderExtra;
+use starcoin_types::system_events::{MintEventExtra, SealEvent};
+
+/// Length of the header the device hashes.
+pub const HEADER_LEN: usize = 76;
+/// Offset of the block header extra inside the minting blob.
+pub const EXTRA_OFFSET: usize = 35;
+/// Length of the block header extra.
+pub const EXTRA_LEN: usize = 4;
+/// Offset of the little endian u32 nonce the device iterates.
+pub const NONCE_OFFSET: usize = 39;
+/// Offset of the blob version, which changes with the header layout.
+pub const VERSION_OFFSET: usize = 0;
+
+/// The region of the minting blob the device hashes.
+pub fn device_header(blob: &[u8]) -> Result<&[u8]> {
+    if blob.len() < HEADER_LEN {
+        anyhow::bail!(
+            "Minting blob too short for device header: len {}, need {}",
+            blob.len(),
+            HEADER_LEN
+        );
+    }
+    Ok(&blob[..HEADER_LEN])
+}
+
+/// Fail unless the version of the minting blob is one of `supported`.
+pub fn check_blob_version(blob: &[u8], supported: &[u8]) -> Result<()> {
+    let version = match blob.get(VERSION_OFFSET) {
+        Some(version) => *version,
+        None => anyhow::bail!("Minting blob is empty"),
+    };
+    if !supported.contains(&version) {
+        anyhow::bail!(
+            "Unsupported minting blob version {:#04x}, supported {:x?}",
+            version,
+            supported
+        );
+    }
+    Ok(())
+}
+
+/// Write `extra` into its slot of the minting blob, zeros if the node sent none.
+pub fn apply_extra(blob: &mut [u8], extra: Option<&BlockHeaderExtra>) -> Result<()> {
+    match extra {
+        Some(extra) => write_extra(blob, extra.as_slice()),
+        None => write_extra(blob, &[0u8; EXTRA_LEN]),
+    }
+}
+
+/// `extra` with a host nonce prefix added to it as a little endian u32, so the
+/// device searches a fresh nonce space. Prefix 0 is the extra unchanged.
+pub fn prefixed_extra(extra: Option<&BlockHeaderExtra>, prefix: u32) -> BlockHeaderExtra {
+    let base = extra.map_or([0u8; EXTRA_LEN], |extra| *extra.as_slice());
+    let prefixed = u32::from_le_bytes(base).wrapping_add(prefix);
+    BlockHeaderExtra::new(prefixed.to_le_bytes())
+}
+
+/// The extra to submit with a solution found under `prefix`. A job without an
+/// extra is never prefixed, its solutions go back without one too.
+pub fn prefixed_event_extra(extra: Option<&MintEventExtra>, prefix: u32) -> Option<MintEventExtra> {
+    let mut prefixed = extra?.clone();
+    prefixed.extra = prefixed_extra(Some(&prefixed.extra), prefix);
+    Some(prefixed)
+}
+
+/// The device header of `blob` with `extra` under a host nonce `prefix` in its slot.
+pub fn prefixed_header(
+    blob: &[u8],
+    extra: Option<&BlockHeaderExtra>,
+    prefix: u32,
+) -> Result<Vec<u8>> {
+    let mut header = device_header(blob)?.to_vec();
+    write_extra(&mut header, prefixed_extra(extra, prefix).as_slice())?;
+    Ok(header)
+}
+
+/// The header a solution was found for: the device header with the extra of the
+/// seal and its nonce filled in.
+pub fn solved_header(seal: &SealEvent) -> Result<Vec<u8>> {
+    let mut header = device_header(&seal.minting_blob)?.to_vec();
+    apply_extra(&mut header, seal.extra.as_ref().map(|extra| &extra.extra))?;
+    header[NONCE_OFFSET..NONCE_OFFSET + 4].copy_from_slice(&seal.nonce.to_le_bytes());
+    Ok(header)
+}
+
+fn write_extra(blob: &mut [u8], extra: &[u8]) -> Result<()> {
+    if extra.len() != EXTRA_LEN {
+        anyhow::bail!(
+            "Block header extra is {} bytes, the blob has room for {}",
+            extra.len(),
+            EXTRA_LEN
+        );
+    }
+    let end = EXTRA_OFFSET + EXTRA_LEN;
+    if blob.len() < end {
+        anyhow::bail!(
+            "Minting blob too short for extra: len {}, need at least {}",
+            blob.len(),
+            end
+        );
+    }
+    blob[EXTRA_OFFSET..end].copy_from_slice(extra);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_extra() {
+        let mut blob = vec![0u8; 76];
+        apply_extra(&mut blob, Some(&BlockHeaderExtra::new([1, 2, 3, 4]))).unwrap();
+        assert_eq!(&blob[35..39], &[1, 2, 3, 4]);
+        assert!(blob[..35].iter().all(|b| *b == 0));
+        assert!(blob[39..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_apply_absent_extra() {
+        let mut blob = vec![0xffu8; 76];
+        apply_extra(&mut blob, None).unwrap();
+        assert_eq!(&blob[35..39], &[0, 0, 0, 0]);
+        assert!(blob[39..].iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn test_prefixed_extra() {
+        let extra = BlockHeaderExtra::new([0xff, 0, 0, 7]);
+        assert_eq!(prefixed_extra(Some(&extra), 0), extra);
+        assert_eq!(prefixed_extra(Some(&extra), 1).as_slice(), &[0, 1, 0, 7]);
+        assert_eq!(prefixed_extra(None, 2).as_slice(), &[2, 0, 0, 0]);
+        assert_eq!(prefixed_event_extra(None, 0), None);
+        assert_eq!(prefixed_event_extra(None, 3), None);
+        let event_extra = MintEventExtra {
+            worker_id: "rig".to_string(),
+            job_id: "1".to_string(),
+            extra,
+        };
+        let prefixed = prefixed_event_extra(Some(&event_extra), 1).unwrap();
+        assert_eq!(prefixed.extra.as_slice(), &[0, 1, 0, 7]);
+        assert_eq!(prefixed.worker_id, "rig");
+
+        let blob: Vec<u8> = (0..100).collect();
+        let header = prefixed_header(&blob, Some(&extra), 1).unwrap();
+        assert_eq!(header.len(), HEADER_LEN);
+        assert_eq!(&header[EXTRA_OFFSET..NONCE_OFFSET], &[0, 1, 0, 7]);
+        assert_eq!(&header[NONCE_OFFSET..], &blob[NONCE_OFFSET..HEADER_LEN]);
+    }
+
+    #[test]
+    fn test_wrong_size_extra() {
+        let mut blob = vec![0u8; 76];
+        for extra in [&[1u8, 2, 3][..], &[1u8, 2, 3, 4, 5][..]].iter() {
+            let err = write_extra(&mut blob, extra).unwrap_err();
+            assert!(err.to_string().contains("room for 4"));
+        }
+        assert_eq!(blob, vec![0u8; 76]);
+    }
+
+    #[test]
+    fn test_device_header() {
+        let blob: Vec<u8> = (0..100).collect();
+        let header = device_header(&blob).unwrap();
+        assert_eq!(header.len(), HEADER_LEN);
+        assert_eq!(header, &blob[..HEADER_LEN]);
+        assert!(device_header(&blob[..75]).is_err());
+    }
+
+    #[test]
+    fn test_check_blob_version() {
+        let mut blob = vec![0u8; 76];
+        blob[..2].copy_from_slice(&[0x05, 0x05]);
+        assert!(check_blob_version(&blob, &[0x05]).is_ok());
+        blob[0] = 0x06;
+        let err = check_blob_version(&blob, &[0x04, 0x05]).unwrap_err();
+        assert!(err.to_string().contains("version 0x06"), "{}", err);
+        assert!(check_blob_version(&[], &[0x05]).is_err());
+    }
+
+    #[test]
+    fn test_apply_extra_short_blob() {
+        let mut blob = vec![0u8; 38];
+        let err = apply_extra(&mut blob, Some(&BlockHeaderExtra::new([1, 2, 3, 4]))).unwrap_err();
+        assert!(err.to_string().contains("too short"));
+        assert_eq!(blob, vec![0u8; 38]);
+    }
+
+    #[test]
+    fn test_solved_header() {
+        let blob: Vec<u8> = (0..100).collect();
+        let extra = Some(MintEventExtra {
+            worker_id: String::new(),
+            job_id: String::new(),
+            extra: BlockHeaderExtra::new([2, 0, 0, 0]),
+        });
+        let seal = SealEvent {
+            minting_blob: blob.clone(),
+            nonce: 0x0102_0304,
+            extra: extra.clone(),
+            hash_result: String::new(),
+        };
+        let header = solved_header(&seal).unwrap();
+        assert_eq!(header.len(), HEADER_LEN);
+        assert_eq!(&header[..EXTRA_OFFSET], &blob[..EXTRA_OFFSET]);
+        assert_eq!(&header[EXTRA_OFFSET..NONCE_OFFSET], &[2, 0, 0, 0]);
+        assert_eq!(&header[NONCE_OFFSET..NONCE_OFFSET + 4], &[4, 3, 2, 1]);
+        assert_eq!(
+            &header[NONCE_OFFSET + 4..],
+            &blob[NONCE_OFFSET + 4..HEADER_LEN]
+        );
+
+        // the header uploaded to the device, but for the nonce it iterates
+        let mut uploaded = blob;
+        apply_extra(&mut uploaded, extra.as_ref().map(|extra| &extra.extra)).unwrap();
+        let mut expect = device_header(&uploaded).unwrap().to_vec();
+        expect[NONCE_OFFSET..NONCE_OFFSET + 4].copy_from_slice(&[4, 3, 2, 1]);
+        assert_eq!(header, expect);
+    }
+}
@@ -0,0 +1,103 @@
+use starcoin_types::U256;
+use std::time::{Duration, Instant};
+
+/// Hashes a solution at `difficulty` takes on average, all 256 bits of it with
+/// the precision of an f64.
+pub fn difficulty_hashes(difficulty: U256) -> f64 {
+    let mut bytes = [0u8; 32];
+    difficulty.to_big_endian(&mut bytes);
+    bytes
+        .iter()
+        .fold(0.0, |hashes, b| hashes * 256.0 + f64::from(*b))
+}
+
+/// Hashrate estimated from the work of the solutions found.
+///
+/// Solutions found within `warmup` of a restart are not counted, the PLL and
+/// cores of the board are still settling after new hw params or the first job.
+#[derive(Clone, Debug)]
+pub struct HashrateMeter {
+    warmup: Duration,
+    started: Option<Instant>,
+    hashes: f64,
+}
+
+impl HashrateMeter {
+    pub fn new(warmup: Duration) -> Self {
+        Self {
+            warmup,
+            started: None,
+            hashes: 0.0,
+        }
+    }
+
+    /// Drop the readings so far and warm up again from `now`.
+    pub fn restart(&mut self, now: Instant) {
+        self.started = Some(now);
+        self.hashes = 0.0;
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn is_warming_up(&self, now: Instant) -> bool {
+        match self.started {
+            Some(started) => now.saturating_duration_since(started) < self.warmup,
+            None => true,
+        }
+    }
+
+    /// Count a solution worth `hashes`, returns false if it was found during warmup.
+    pub fn record(&mut self, hashes: f64, now: Instant) -> bool {
+        if self.is_warming_up(now) {
+            return false;
+        }
+        self.hashes += hashes;
+        true
+    }
+
+    /// Hashes per second since the warmup ended, `None` while warming up.
+    pub fn hashrate(&self, now: Instant) -> Option<f64> {
+        let warm_since = self.started? + self.warmup;
+        let elapsed = now.saturating_duration_since(warm_since).as_secs_f64();
+        if self.is_warming_up(now) || elapsed <= 0.0 {
+            return None;
+        }
+        Some(self.hashes / elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warmup_excluded() {
+        let start = Instant::now();
+        let mut meter = HashrateMeter::new(Duration::from_secs(10));
+        assert!(!meter.record(1000.0, start));
+        meter.restart(start);
+        assert!(!meter.record(1000.0, start + Duration::from_secs(5)));
+        assert!(meter.hashrate(start + Duration::from_secs(9)).is_none());
+
+        assert!(meter.record(200.0, start + Duration::from_secs(12)));
+        let hashrate = meter.hashrate(start + Duration::from_secs(20)).unwrap();
+        assert!((hashrate - 20.0).abs() < 1e-9);
+
+        meter.restart(start + Duration::from_secs(20));
+        assert!(meter.is_warming_up(start + Duration::from_secs(25)));
+        assert!(meter.hashrate(start + Duration::from_secs(25)).is_none());
+    }
+
+    #[test]
+    fn test_difficulty_hashes() {
+        assert!((difficulty_hashes(U256::from(1000u64)) - 1000.0).abs() < 1e-9);
+        // past the low 64 bits
+        let big = U256::from(u64::max_value()) * U256::from(16u64);
+        let hashes = difficulty_hashes(big) / (u64::max_value() as f64 * 16.0);
+        assert!((hashes - 1.0).abs() < 1e-12);
+        let max = difficulty_hashes(U256::max_value());
+        assert!((max / 2f64.powi(256) - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// Sleep between polls of an idle device that doubles while nothing arrives,
+/// up to `max`, and starts over once the device sends something.
+#[derive(Clone, Copy, Debug)]
+pub struct IdleBackoff {
+    max: Duration,
+    current: Option<Duration>,
+}
+
+impl IdleBackoff {
+    pub fn new(max: Duration) -> Self {
+        Self { max, current: None }
+    }
+
+    /// How long to sleep before the next poll of a still idle device.
+    pub fn next_sleep(&mut self) -> Duration {
+        let next = match self.current {
+            None => MIN_SLEEP,
+            Some(current) => current * 2,
+        }
+        .min(self.max);
+        self.current = Some(next);
+        next
+    }
+
+    /// The device is active, poll without sleeping.
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_adapts() {
+        let mut backoff = IdleBackoff::new(Duration::from_millis(5));
+        let sleeps: Vec<_> = (0..5).map(|_| backoff.next_sleep().as_millis()).collect();
+        assert_eq!(sleeps, vec![1, 2, 4, 5, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_sleep(), MIN_SLEEP);
+    }
+}
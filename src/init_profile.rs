@@ -0,0 +1,373 @@
+//! Init sequence sent to each device when it is opened, for firmware that needs
+//! other commands or params than the stock boards. A profile is a TOML file with
+//! one `[[step]]` table per command:
+//!
+//! ```toml
+//! [[step]]
+//! command = "hw_params"
+//! freq = 600
+//! voltage = 750
+//!
+//! [[step]]
+//! command = "opcode"
+//! ```
+//!
+//! Only that much TOML is read: `[[step]]` headers, `key = value` lines with an
+//! integer, decimal or `0x` hex, or a quoted string, and `#` comments. Steps name
+//! commands of the codec, raw bytes cannot be sent.
+
+use anyhow::Result;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use usbderive::UsbDerive;
+
+/// What the solver always sent: hw params from the config, then the opcode.
+pub const DEFAULT_PROFILE: &str = r#"
+[[step]]
+command = "hw_params"
+
+[[step]]
+command = "opcode"
+"#;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitStep {
+    /// Frequency and voltage, each taken from the config if not given. With
+    /// `Config::probe_freq` set the board is probed at it first.
+    HwParams {
+        freq: Option<u16>,
+        voltage: Option<u16>,
+    },
+    Opcode,
+    CoreMask(u32),
+    Wake,
+    /// Ask for the state, the reply is read with the solutions.
+    State,
+    Pause(Duration),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitProfile {
+    pub steps: Vec<InitStep>,
+}
+
+impl Default for InitProfile {
+    fn default() -> Self {
+        Self::parse(DEFAULT_PROFILE).expect("Default init profile is valid")
+    }
+}
+
+impl InitProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read init profile {:?}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Init profile {:?}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut tables: Vec<Vec<(String, Value)>> = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[[step]]" {
+                tables.push(vec![]);
+                continue;
+            }
+            let table = match tables.last_mut() {
+                Some(table) => table,
+                None => anyhow::bail!("Line {}: expect [[step]] first", index + 1),
+            };
+            let eq = match line.find('=') {
+                Some(eq) => eq,
+                None => anyhow::bail!("Line {}: expect key = value", index + 1),
+            };
+            let key = line[..eq].trim().to_string();
+            let value = Value::parse(line[eq + 1..].trim())
+                .map_err(|e| anyhow::anyhow!("Line {}: {}", index + 1, e))?;
+            table.push((key, value));
+        }
+        let steps = tables
+            .iter()
+            .enumerate()
+            .map(|(index, table)| {
+                InitStep::from_table(table)
+                    .map_err(|e| anyhow::anyhow!("Step {}: {}", index + 1, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { steps })
+    }
+
+    /// Run the steps on `derive` in order, stopping at the first that fails.
+    pub fn apply(&self, derive: &mut UsbDerive) -> Result<()> {
+        self.run(derive, false)
+    }
+
+    /// Run the steps again on a device that rebooted or was reopened. Hw params
+    /// steps send the ones in effect, so a retuned or probed device keeps them.
+    pub fn reapply(&self, derive: &mut UsbDerive) -> Result<()> {
+        self.run(derive, true)
+    }
+
+    fn run(&self, derive: &mut UsbDerive, again: bool) -> Result<()> {
+        for step in &self.steps {
+            match *step {
+                InitStep::HwParams { .. } if again => derive.set_hw_params()?,
+                InitStep::HwParams { freq, voltage } => {
+                    let config = derive.config();
+                    let freq = freq.unwrap_or(config.target_freq);
+                    let voltage = voltage.unwrap_or(config.target_voltage);
+                    match config.probe_freq {
+                        Some(probe_freq) if probe_freq < freq => {
+                            derive.probe_freq_voltage(probe_freq, freq, voltage)?
+                        }
+                        _ if (freq, voltage) == (config.target_freq, config.target_voltage) => {
+                            derive.set_hw_params()?
+                        }
+                        _ => derive.set_freq_voltage(freq, voltage)?,
+                    }
+                }
+                InitStep::Opcode => derive.set_opcode()?,
+                InitStep::CoreMask(mask) => derive.set_core_mask(mask)?,
+                InitStep::Wake => derive.wake()?,
+                InitStep::State => derive.write_state()?,
+                InitStep::Pause(duration) => thread::sleep(duration),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Int(u64),
+    Str(String),
+}
+
+impl Value {
+    fn parse(raw: &str) -> Result<Self> {
+        if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+            return Ok(Value::Str(raw[1..raw.len() - 1].to_string()));
+        }
+        let digits = raw.replace('_', "");
+        let parsed = match digits.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => digits.parse(),
+        };
+        parsed
+            .map(Value::Int)
+            .map_err(|_| anyhow::anyhow!("Invalid value {}", raw))
+    }
+}
+
+impl InitStep {
+    fn from_table(table: &[(String, Value)]) -> Result<Self> {
+        let get = |name: &str| table.iter().find(|(key, _)| key == name).map(|(_, v)| v);
+        let int = |name: &str| -> Result<Option<u64>> {
+            match get(name) {
+                None => Ok(None),
+                Some(Value::Int(int)) => Ok(Some(*int)),
+                Some(value) => anyhow::bail!("{} must be an integer, got {:?}", name, value),
+            }
+        };
+        let u16_param = |name: &str| -> Result<Option<u16>> {
+            match int(name)? {
+                None => Ok(None),
+                Some(int) if int <= u64::from(u16::MAX) => Ok(Some(int as u16)),
+                Some(int) => anyhow::bail!("{} {} is out of range", name, int),
+            }
+        };
+        let required = |name: &str| -> Result<u64> {
+            int(name)?.ok_or_else(|| anyhow::anyhow!("Missing {}", name))
+        };
+        let step = match get("command") {
+            Some(Value::Str(command)) => match command.as_str() {
+                "hw_params" => InitStep::HwParams {
+                    freq: u16_param("freq")?,
+                    voltage: u16_param("voltage")?,
+                },
+                "opcode" => InitStep::Opcode,
+                "core_mask" => match required("mask")? {
+                    mask if mask <= u64::from(u32::MAX) => InitStep::CoreMask(mask as u32),
+                    mask => anyhow::bail!("mask {:#x} is out of range", mask),
+                },
+                "wake" => InitStep::Wake,
+                "state" => InitStep::State,
+                "pause" => InitStep::Pause(Duration::from_millis(required("ms")?)),
+                command => anyhow::bail!("Unknown command {}", command),
+            },
+            _ => anyhow::bail!("Missing command"),
+        };
+        Ok(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use usbderive::mock::MockPort;
+    use usbderive::{Config, Message};
+
+    #[test]
+    fn test_default_profile() {
+        assert_eq!(
+            InitProfile::default().steps,
+            vec![
+                InitStep::HwParams {
+                    freq: None,
+                    voltage: None
+                },
+                InitStep::Opcode
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let profile = InitProfile::parse(
+            r#"
+            # custom firmware wakes up asleep and masks off core 3
+            [[step]]
+            command = "wake"
+
+            [[step]]
+            command = "core_mask"
+            mask = 0xfffffff7
+
+            [[step]]
+            command = "hw_params"
+            freq = 650
+            voltage = 780
+
+            [[step]]
+            command = "opcode"
+            "#,
+        )
+        .unwrap();
+        let port = MockPort::new();
+        let config = Config {
+            power_mode: true,
+            core_mask_command: true,
+            ..Config::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        profile.apply(&mut derive).unwrap();
+
+        assert_eq!(
+            port.written(),
+            vec![
+                Message::wake_msg(),
+                Message::core_mask_msg(0xffff_fff7),
+                Message::set_hw_params_msg(650, 780),
+                Message::opcode_msg(),
+            ]
+        );
+        assert_eq!(derive.config().target_freq, 650);
+
+        // set up again after a reboot, with the frequency retuned meanwhile
+        derive.set_freq_voltage(620, 780).unwrap();
+        let written = port.written().len();
+        profile.reapply(&mut derive).unwrap();
+        assert_eq!(
+            port.written()[written..].to_vec(),
+            vec![
+                Message::wake_msg(),
+                Message::core_mask_msg(0xffff_fff7),
+                Message::set_hw_params_msg(620, 780),
+                Message::opcode_msg(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_probe_freq() {
+        let ack = [
+            0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+        ];
+        let state = |goodcores: u8| {
+            let mut frame = vec![0u8; 29];
+            frame[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+            frame[10] = 8;
+            frame[11] = goodcores;
+            frame[23] = 60;
+            frame[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+            frame
+        };
+        let config = Config {
+            probe_freq: Some(400),
+            probe_time: Duration::from_millis(0),
+            ..Config::default()
+        };
+        let profile = InitProfile::default();
+
+        let port = MockPort::new();
+        port.push_response(&ack);
+        port.push_response(&state(8));
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        profile.apply(&mut derive).unwrap();
+        assert_eq!(
+            port.written(),
+            vec![
+                Message::set_hw_params_msg(400, 750),
+                Message::get_state_msg(),
+                Message::set_hw_params_msg(600, 750),
+                Message::opcode_msg(),
+            ]
+        );
+        assert_eq!(derive.config().target_freq, 600);
+
+        // set up again after a reopen, the board goes back to what the probe raised it to
+        derive.set_init_hook(Arc::new(move |derive| profile.reapply(derive)));
+        let written = port.written().len();
+        port.push_response(&ack);
+        derive.reinit().unwrap();
+        assert_eq!(
+            port.written()[written..].to_vec(),
+            vec![Message::set_hw_params_msg(600, 750), Message::opcode_msg()]
+        );
+
+        // a core lost at the probe frequency stops the ramp and the init, the hw
+        // params from before the probe are set again
+        let config = Config {
+            target_freq: 500,
+            ..config
+        };
+        let port = MockPort::new();
+        port.push_response(&ack);
+        port.push_response(&state(7));
+        port.push_response(&ack);
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        let err = InitProfile::parse("[[step]]\ncommand = \"hw_params\"\nfreq = 600")
+            .unwrap()
+            .apply(&mut derive)
+            .unwrap_err();
+        assert!(err.to_string().contains("7/8 cores good"), "{}", err);
+        assert_eq!(
+            port.written(),
+            vec![
+                Message::set_hw_params_msg(400, 750),
+                Message::get_state_msg(),
+                Message::set_hw_params_msg(500, 750),
+            ]
+        );
+        assert_eq!(derive.config().target_freq, 500);
+    }
+
+    #[test]
+    fn test_invalid_profile() {
+        let cases = [
+            "command = \"opcode\"",
+            "[[step]]\ncommand = \"flash\"",
+            "[[step]]\ncommand = \"core_mask\"",
+            "[[step]]\ncommand = \"hw_params\"\nfreq = 70000",
+            "[[step]]\ncommand = opcode",
+        ];
+        for text in cases.iter() {
+            assert!(InitProfile::parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! CSV log of every job each device mined and how it ended, for offline analysis
+//! of luck and per-device performance. Rows are appended, the header is written
+//! when the file is new or empty.
+
+use anyhow::Result;
+use starcoin_types::system_events::SealEvent;
+use starcoin_types::U256;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+pub const HEADER: &str =
+    "timestamp,difficulty,target,job_id,device,outcome,elapsed_ms,nonce,hash,nonce_start,nonce_end";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobOutcome {
+    /// The device found the solution that was submitted.
+    Solved { nonce: u32, hash: String },
+    /// The job was stopped, or another device solved it.
+    Stopped,
+    /// The job deadline passed without a solution.
+    Timeout,
+    /// Every device was lost during the job.
+    Lost,
+}
+
+impl JobOutcome {
+    pub fn solved(seal: &SealEvent) -> Self {
+        JobOutcome::Solved {
+            nonce: seal.nonce,
+            hash: seal.hash_result.clone(),
+        }
+    }
+}
+
+impl fmt::Display for JobOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            JobOutcome::Solved { .. } => "solved",
+            JobOutcome::Stopped => "stopped",
+            JobOutcome::Timeout => "timeout",
+            JobOutcome::Lost => "lost",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Nonces a device searched, from `start` up to but excluding `end`. Past the 32
+/// bits of the device nonce, the host nonce prefix counts as the high bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NonceRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl NonceRange {
+    /// `count` nonces searched from `start`.
+    pub fn covered(start: u64, count: u64) -> Self {
+        Self {
+            start,
+            end: start.saturating_add(count),
+        }
+    }
+
+    /// Nonces searched from `start` up to the `nonce` a device found under `prefix`.
+    pub fn up_to(start: u64, prefix: u32, nonce: u32) -> Self {
+        let end = (u64::from(prefix) << 32 | u64::from(nonce)) + 1;
+        Self {
+            start,
+            end: end.max(start),
+        }
+    }
+}
+
+/// One device's run of one job.
+#[derive(Clone, Debug)]
+pub struct JobRecord {
+    pub started: SystemTime,
+    pub difficulty: U256,
+    pub target: u32,
+    pub job_id: u8,
+    pub device: String,
+    pub outcome: JobOutcome,
+    /// Time to the solution, or until the job ended without one.
+    pub elapsed: Duration,
+    /// The firmware has no nonce counter, so this is only known for the device that
+    /// found the solution: from the nonce it started at up to the one found, under the
+    /// prefix it was found with. `None` for the others.
+    pub nonces: Option<NonceRange>,
+}
+
+impl JobRecord {
+    fn to_csv(&self) -> String {
+        let started = self
+            .started
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let (nonce, hash) = match &self.outcome {
+            JobOutcome::Solved { nonce, hash } => (nonce.to_string(), hash.as_str()),
+            _ => (String::new(), ""),
+        };
+        let (nonce_start, nonce_end) = match &self.nonces {
+            Some(range) => (range.start.to_string(), range.end.to_string()),
+            None => (String::new(), String::new()),
+        };
+        format!(
+            "{}.{:03},{},{:#010x},{},{},{},{},{},{},{},{}",
+            started.as_secs(),
+            started.subsec_millis(),
+            self.difficulty,
+            self.target,
+            self.job_id,
+            csv_field(&self.device),
+            self.outcome,
+            self.elapsed.as_millis(),
+            nonce,
+            hash,
+            nonce_start,
+            nonce_end
+        )
+    }
+}
+
+/// Append `records` to the CSV file at `path`.
+pub fn append<P: AsRef<Path>>(path: P, records: &[JobRecord]) -> Result<()> {
+    let path = path.as_ref();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open job log {:?}: {}", path, e))?;
+    let mut rows = String::new();
+    if file.metadata()?.len() == 0 {
+        rows.push_str(HEADER);
+        rows.push('\n');
+    }
+    for record in records {
+        rows.push_str(&record.to_csv());
+        rows.push('\n');
+    }
+    file.write_all(rows.as_bytes())?;
+    Ok(())
+}
+
+// Serials come from the usb descriptor, quote one that would break the row.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_append_rows() {
+        let path = std::env::temp_dir().join(format!("job_log_{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let record = JobRecord {
+            started: SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_250),
+            difficulty: U256::from(1000u64),
+            target: 0x0041_8937,
+            job_id: 3,
+            device: "A1,B2".to_string(),
+            outcome: JobOutcome::Solved {
+                nonce: 42,
+                hash: "11".repeat(32),
+            },
+            elapsed: Duration::from_millis(1500),
+            nonces: Some(NonceRange::up_to(0, 1, 42)),
+        };
+        let timeout = JobRecord {
+            outcome: JobOutcome::Timeout,
+            device: "C3".to_string(),
+            nonces: None,
+            ..record
+        };
+        append(&path, &[record]).unwrap();
+        append(&path, &[timeout]).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let rows: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            rows,
+            vec![
+                HEADER.to_string(),
+                format!(
+                    "1600000000.250,1000,0x00418937,3,\"A1,B2\",solved,1500,42,{},0,4294967339",
+                    "11".repeat(32)
+                ),
+                "1600000000.250,1000,0x00418937,3,C3,timeout,1500,,,,".to_string(),
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_nonce_range() {
+        let range = NonceRange::covered(1000, 500);
+        assert_eq!(
+            range,
+            NonceRange {
+                start: 1000,
+                end: 1500
+            }
+        );
+        assert_eq!(NonceRange::covered(u64::MAX - 1, 10).end, u64::MAX);
+        // the nonce found is the last one searched
+        assert_eq!(
+            NonceRange::up_to(0, 0, 41),
+            NonceRange { start: 0, end: 42 }
+        );
+        assert_eq!(NonceRange::up_to(0, 2, 0).end, (2 << 32) + 1);
+        assert_eq!(
+            NonceRange::up_to(100, 0, 10),
+            NonceRange {
+                start: 100,
+                end: 100
+            }
+        );
+    }
+}
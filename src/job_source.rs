@@ -0,0 +1,42 @@
+use starcoin_types::system_events::{MintBlockEvent, MintEventExtra};
+use starcoin_types::U256;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Work to mine, independent of the front-end it came from.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub minting_blob: Vec<u8>,
+    pub difficulty: U256,
+    pub extra: Option<MintEventExtra>,
+    /// Height of the block being mined.
+    pub block_number: u64,
+}
+
+impl Job {
+    /// Hash of the blob, difficulty and extra, the same for jobs of the same work.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.minting_blob.hash(&mut hasher);
+        self.difficulty.hash(&mut hasher);
+        self.extra.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl From<MintBlockEvent> for Job {
+    fn from(event: MintBlockEvent) -> Self {
+        Self {
+            minting_blob: event.minting_blob,
+            difficulty: event.difficulty,
+            extra: event.extra,
+            block_number: event.block_number,
+        }
+    }
+}
+
+/// Yields jobs to `UsbSolver::run`, e.g. a node, a pool adapter or a test harness.
+pub trait JobSource {
+    /// The next job, blocking until there is one. `None` ends the run.
+    fn next_job(&mut self) -> Option<Job>;
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+/// Where the time between uploading a job and submitting its solution went.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    /// Job upload until the solution frame started to come in.
+    pub device: Duration,
+    /// Reading and parsing the solution frame.
+    pub read: Duration,
+    /// Submit hook, extra sinks and `nonce_tx`.
+    pub submit: Duration,
+}
+
+/// Stage timings of the submitted solutions.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyStats {
+    count: u32,
+    total: StageTimings,
+    last: Option<StageTimings>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, timings: StageTimings) {
+        self.count += 1;
+        self.total.device += timings.device;
+        self.total.read += timings.read;
+        self.total.submit += timings.submit;
+        self.last = Some(timings);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn last(&self) -> Option<StageTimings> {
+        self.last
+    }
+
+    pub fn mean(&self) -> Option<StageTimings> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(StageTimings {
+            device: self.total.device / self.count,
+            read: self.total.read / self.count,
+            submit: self.total.submit / self.count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mean() {
+        let mut stats = LatencyStats::default();
+        assert!(stats.mean().is_none());
+        let ms = Duration::from_millis;
+        stats.record(StageTimings {
+            device: ms(100),
+            read: ms(2),
+            submit: ms(10),
+        });
+        stats.record(StageTimings {
+            device: ms(300),
+            read: ms(4),
+            submit: ms(20),
+        });
+        assert_eq!(
+            stats.mean(),
+            Some(StageTimings {
+                device: ms(200),
+                read: ms(3),
+                submit: ms(15),
+            })
+        );
+        assert_eq!(stats.last().map(|t| t.device), Some(ms(300)));
+    }
+}
@@ -1,9 +1,38 @@
+pub mod aggregator;
+pub mod alerts;
+pub mod autotune;
+pub mod clock_skew;
+pub mod contention;
+pub mod diagnostics;
+pub mod env_config;
+pub mod extra;
+pub mod hashrate;
+pub mod idle_backoff;
+pub mod init_profile;
+pub mod job_log;
+pub mod job_source;
+pub mod latency;
+pub mod nak_breaker;
+pub mod nonce_positions;
+pub mod panic_hook;
+pub mod pressure;
+pub mod share_stats;
+pub mod solution_rate;
+pub mod telemetry;
+pub mod timeout_reason;
 pub mod usb_solver;
 
+use crate::env_config::EnvConfig;
 use crate::usb_solver::UsbSolver;
 use starcoin_miner_client_api::Solver;
 
+/// Takes the USB ids and device settings from `USBSOLVER_*` environment variables,
+/// see `EnvConfig`.
 #[no_mangle]
 pub extern "C" fn create_solver() -> Box<dyn Solver> {
-    Box::new(UsbSolver::new().expect("Failed to create usb solver"))
+    let _ = starcoin_logger::init();
+    let env = EnvConfig::from_env();
+    Box::new(
+        UsbSolver::with_config(env.vid, env.pid, env.config).expect("Failed to create usb solver"),
+    )
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+/// Circuit breaker for a device that keeps sending bad replies: after `limit`
+/// consecutive NAKs the device is left alone for `cooldown`, then tried again.
+#[derive(Clone, Copy, Debug)]
+pub struct NakBreaker {
+    limit: u32,
+    cooldown: Duration,
+    naks: u32,
+    open_until: Option<Instant>,
+}
+
+impl NakBreaker {
+    pub fn new(limit: u32, cooldown: Duration) -> Self {
+        Self {
+            limit,
+            cooldown,
+            naks: 0,
+            open_until: None,
+        }
+    }
+
+    /// Count a bad reply, true if it trips the breaker.
+    pub fn record_nak(&mut self, now: Instant) -> bool {
+        if self.is_open(now) {
+            return false;
+        }
+        self.naks += 1;
+        if self.naks < self.limit {
+            return false;
+        }
+        self.naks = 0;
+        self.open_until = Some(now + self.cooldown);
+        true
+    }
+
+    /// A good reply, the NAKs so far were not consecutive.
+    pub fn record_ok(&mut self) {
+        self.naks = 0;
+    }
+
+    /// Whether the device is cooling down and should not be sent anything.
+    pub fn is_open(&self, now: Instant) -> bool {
+        self.open_until.map_or(false, |until| now < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_trips_and_cools_down() {
+        let start = Instant::now();
+        let mut breaker = NakBreaker::new(3, Duration::from_secs(60));
+        assert!(!breaker.record_nak(start));
+        breaker.record_ok();
+        assert!(!breaker.record_nak(start));
+        assert!(!breaker.record_nak(start));
+        assert!(breaker.record_nak(start));
+        assert!(breaker.is_open(start + Duration::from_secs(59)));
+        // NAKs during the cooldown do not trip it again
+        assert!(!breaker.record_nak(start + Duration::from_secs(1)));
+
+        let later = start + Duration::from_secs(60);
+        assert!(!breaker.is_open(later));
+        assert!(!breaker.record_nak(later));
+    }
+}
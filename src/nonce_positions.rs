@@ -0,0 +1,119 @@
+//! Searched nonce position per device and job, persisted so a restarted process
+//! resumes the search of a long running job instead of re-scanning from zero.
+//!
+//! One `<device serial> <device header hex> <position>` line per device and job.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+#[derive(Clone)]
+pub struct NoncePositions {
+    path: PathBuf,
+    // by device serial and header hex
+    positions: HashMap<(String, String), u64>,
+}
+
+impl NoncePositions {
+    /// Load saved positions, a missing file is an empty store.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut positions = HashMap::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let mut fields = line.split_whitespace();
+            match (
+                fields.next(),
+                fields.next(),
+                fields.next().map(str::parse::<u64>),
+            ) {
+                (Some(serial), Some(job), Some(Ok(position))) => {
+                    positions.insert((serial.to_string(), job.to_string()), position);
+                }
+                _ => anyhow::bail!("Invalid nonce position line: {}", line),
+            }
+        }
+        Ok(Self { path, positions })
+    }
+
+    /// Where device `serial` resumes the search of the job with this device header.
+    pub fn get(&self, serial: &str, header: &[u8]) -> Option<u64> {
+        self.positions
+            .get(&(serial.to_string(), hex::encode(header)))
+            .copied()
+    }
+
+    /// Save the position reached, positions never move backwards.
+    pub fn record(&mut self, serial: &str, header: &[u8], position: u64) -> Result<()> {
+        if self
+            .get(serial, header)
+            .map_or(false, |saved| saved >= position)
+        {
+            return Ok(());
+        }
+        self.positions
+            .insert((serial.to_string(), hex::encode(header)), position);
+        self.save()
+    }
+
+    /// Forget every job but those with these device headers.
+    pub fn retain(&mut self, headers: &[Vec<u8>]) -> Result<()> {
+        let keep: Vec<String> = headers.iter().map(hex::encode).collect();
+        let before = self.positions.len();
+        self.positions.retain(|(_, job), _| keep.contains(job));
+        if self.positions.len() < before {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut content = String::new();
+        for ((serial, job), position) in &self.positions {
+            content.push_str(&format!("{} {} {}\n", serial, job, position));
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_restore_position() {
+        let path = std::env::temp_dir().join(format!("nonce_positions_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let header_a = [1u8; 76];
+        let header_b = [2u8; 76];
+
+        let mut positions = NoncePositions::load(&path).unwrap();
+        assert_eq!(positions.get("A1", &header_a), None);
+        positions.record("A1", &header_a, 1000).unwrap();
+        positions.record("A1", &header_a, 500).unwrap();
+        positions.record("B2", &header_a, 7).unwrap();
+        positions.record("A1", &header_b, 42).unwrap();
+
+        let mut restored = NoncePositions::load(&path).unwrap();
+        assert_eq!(restored.get("A1", &header_a), Some(1000));
+        assert_eq!(restored.get("B2", &header_a), Some(7));
+        assert_eq!(restored.get("A1", &header_b), Some(42));
+
+        // a new job drops the positions of the others
+        restored.retain(&[header_b.to_vec()]).unwrap();
+        let restored = NoncePositions::load(&path).unwrap();
+        assert_eq!(restored.get("A1", &header_a), None);
+        assert_eq!(restored.get("B2", &header_a), None);
+        assert_eq!(restored.get("A1", &header_b), Some(42));
+        fs::remove_file(&path).unwrap();
+    }
+}
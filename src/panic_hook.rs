@@ -0,0 +1,88 @@
+use starcoin_logger::prelude::*;
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+/// Device state of the solve loop on the current thread, logged when it panics.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PanicContext {
+    pub serial: Option<String>,
+    pub job_id: Option<u8>,
+    pub last_frame: Option<String>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<PanicContext> = RefCell::new(PanicContext::default());
+    static CAPTURED: RefCell<Option<PanicContext>> = RefCell::new(None);
+}
+
+static INSTALL: Once = Once::new();
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Chain a hook in front of the current one that logs the device context.
+/// Installed at most once per process.
+pub fn install() {
+    INSTALL.call_once(|| {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let ctx = CONTEXT.with(|c| c.try_borrow().map(|c| c.clone()).unwrap_or_default());
+            error!(
+                "Solver panicked, device: {:?}, job: {:?}, last frame: {:?}",
+                ctx.serial, ctx.job_id, ctx.last_frame
+            );
+            CAPTURED.with(|c| {
+                if let Ok(mut c) = c.try_borrow_mut() {
+                    *c = Some(ctx);
+                }
+            });
+            prev(info);
+        }));
+        ENABLED.store(true, Ordering::SeqCst);
+    });
+}
+
+pub fn set_job(serial: Option<&str>, job_id: u8) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    CONTEXT.with(|c| {
+        let mut c = c.borrow_mut();
+        c.serial = serial.map(|s| s.to_string());
+        c.job_id = Some(job_id);
+        c.last_frame = None;
+    });
+}
+
+pub fn record_frame<T: Debug>(frame: &T) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    CONTEXT.with(|c| c.borrow_mut().last_frame = Some(format!("{:?}", frame)));
+}
+
+/// Context captured by the last panic on the current thread.
+pub fn captured() -> Option<PanicContext> {
+    CAPTURED.with(|c| c.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_context_captured() {
+        install();
+        set_job(Some("A1"), 7);
+        record_frame(&vec![0xa5u8, 0x3c, 0x96]);
+
+        let result = panic::catch_unwind(|| panic!("simulated solver panic"));
+        assert!(result.is_err());
+
+        let ctx = captured().expect("context should be captured");
+        assert_eq!(ctx.serial.as_deref(), Some("A1"));
+        assert_eq!(ctx.job_id, Some(7));
+        assert_eq!(ctx.last_frame.as_deref(), Some("[165, 60, 150]"));
+    }
+}
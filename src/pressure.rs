@@ -0,0 +1,97 @@
+//! Host resource pressure, so the solver can be a good neighbor on a shared host.
+//! How usage is measured is up to the caller, the monitor only applies thresholds.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Host resources in use, as measured by a `ResourceCheck`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResourceUsage {
+    /// Busy fraction of all cpus, from 0 to 1.
+    pub cpu: f64,
+    /// Bytes of memory still available.
+    pub free_memory: u64,
+}
+
+pub type ResourceCheck = Arc<dyn Fn() -> ResourceUsage + Send + Sync>;
+
+/// Runs the check at most every `interval` and keeps the verdict in between.
+#[derive(Clone)]
+pub struct PressureMonitor {
+    check: ResourceCheck,
+    cpu_limit: f64,
+    min_free_memory: u64,
+    interval: Duration,
+    checked_at: Option<Instant>,
+    under_pressure: bool,
+}
+
+impl PressureMonitor {
+    pub fn new(
+        check: ResourceCheck,
+        cpu_limit: f64,
+        min_free_memory: u64,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            check,
+            cpu_limit,
+            min_free_memory,
+            interval,
+            checked_at: None,
+            under_pressure: false,
+        }
+    }
+
+    /// Whether cpu use is over the limit or free memory under the minimum.
+    pub fn under_pressure(&mut self, now: Instant) -> bool {
+        let due = self.checked_at.map_or(true, |at| {
+            now.saturating_duration_since(at) >= self.interval
+        });
+        if due {
+            let usage = (self.check)();
+            self.under_pressure =
+                usage.cpu > self.cpu_limit || usage.free_memory < self.min_free_memory;
+            self.checked_at = Some(now);
+        }
+        self.under_pressure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_pressure_thresholds() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let usages = [
+            ResourceUsage {
+                cpu: 0.5,
+                free_memory: 1 << 30,
+            },
+            ResourceUsage {
+                cpu: 0.95,
+                free_memory: 1 << 30,
+            },
+            ResourceUsage {
+                cpu: 0.5,
+                free_memory: 1 << 20,
+            },
+        ];
+        let counter = checks.clone();
+        let check: ResourceCheck =
+            Arc::new(move || usages[counter.fetch_add(1, Ordering::SeqCst).min(2)]);
+        let interval = Duration::from_secs(5);
+        let mut monitor = PressureMonitor::new(check, 0.9, 256 << 20, interval);
+        let start = Instant::now();
+        assert!(!monitor.under_pressure(start));
+        // the verdict holds until the next check is due
+        assert!(!monitor.under_pressure(start + Duration::from_secs(1)));
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+        assert!(monitor.under_pressure(start + interval));
+        assert!(monitor.under_pressure(start + interval * 2));
+        assert_eq!(checks.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Shares accepted and rejected while a device ran at one frequency.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrequencyShares {
+    pub freq: u16,
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+impl FrequencyShares {
+    pub fn reject_rate(&self) -> f64 {
+        let total = self.accepted + self.rejected;
+        if total == 0 {
+            return 0.0;
+        }
+        self.rejected as f64 / total as f64
+    }
+}
+
+/// Share acks per device and frequency over the session, to find the frequency
+/// where rejects start to climb.
+#[derive(Clone, Debug, Default)]
+pub struct ShareStats {
+    devices: HashMap<String, BTreeMap<u16, FrequencyShares>>,
+}
+
+impl ShareStats {
+    pub fn record(&mut self, serial: &str, freq: u16, accepted: bool) {
+        let shares = self
+            .devices
+            .entry(serial.to_string())
+            .or_insert_with(BTreeMap::new)
+            .entry(freq)
+            .or_insert(FrequencyShares {
+                freq,
+                ..Default::default()
+            });
+        if accepted {
+            shares.accepted += 1;
+        } else {
+            shares.rejected += 1;
+        }
+    }
+
+    /// Shares by ascending frequency for each device, devices sorted by serial.
+    pub fn report(&self) -> Vec<(String, Vec<FrequencyShares>)> {
+        let mut report: Vec<(String, Vec<FrequencyShares>)> = self
+            .devices
+            .iter()
+            .map(|(serial, shares)| (serial.clone(), shares.values().copied().collect()))
+            .collect();
+        report.sort_by(|a, b| a.0.cmp(&b.0));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_by_frequency() {
+        let mut stats = ShareStats::default();
+        for i in 0..10 {
+            stats.record("A1", 600, i != 0);
+            stats.record("A1", 700, i % 2 == 0);
+        }
+        stats.record("B2", 650, true);
+
+        let report = stats.report();
+        assert_eq!(report.len(), 2);
+        let (serial, shares) = &report[0];
+        assert_eq!(serial, "A1");
+        assert_eq!(
+            shares,
+            &vec![
+                FrequencyShares {
+                    freq: 600,
+                    accepted: 9,
+                    rejected: 1,
+                },
+                FrequencyShares {
+                    freq: 700,
+                    accepted: 5,
+                    rejected: 5,
+                },
+            ]
+        );
+        assert!((shares[0].reject_rate() - 0.1).abs() < f64::EPSILON);
+        assert!((shares[1].reject_rate() - 0.5).abs() < f64::EPSILON);
+        assert_eq!(report[1].0, "B2");
+        assert!(report[1].1[0].reject_rate() < f64::EPSILON);
+    }
+}
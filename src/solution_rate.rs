@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Fewer solutions than this are never implausible, a lucky streak is not a bug.
+const MIN_SOLUTIONS: usize = 5;
+
+/// Spots firmware reporting false positives: a solution at difficulty d takes d
+/// hashes on average, so the solutions of a board within `window` claim work it
+/// could not have done at its nominal hashrate. The measured hashrate is no
+/// yardstick here, it is estimated from the same solutions.
+#[derive(Clone, Debug)]
+pub struct SolutionRateCheck {
+    nominal_hashrate: f64,
+    factor: f64,
+    window: Duration,
+    // time and difficulty of each solution within the window
+    solutions: VecDeque<(Instant, f64)>,
+}
+
+impl SolutionRateCheck {
+    pub fn new(nominal_hashrate: f64, factor: f64, window: Duration) -> Self {
+        Self {
+            nominal_hashrate,
+            factor,
+            window,
+            solutions: VecDeque::new(),
+        }
+    }
+
+    /// Count a solution at `difficulty`, true if the rate is implausible.
+    pub fn record(&mut self, difficulty: f64, now: Instant) -> bool {
+        self.solutions.push_back((now, difficulty));
+        let window = self.window;
+        while self
+            .solutions
+            .front()
+            .map_or(false, |(at, _)| now.saturating_duration_since(*at) > window)
+        {
+            self.solutions.pop_front();
+        }
+        let claimed: f64 = self
+            .solutions
+            .iter()
+            .map(|(_, difficulty)| difficulty)
+            .sum();
+        let plausible = self.nominal_hashrate * window.as_secs_f64() * self.factor;
+        if self.solutions.len() < MIN_SOLUTIONS || claimed <= plausible {
+            return false;
+        }
+        self.solutions.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implausible_rate() {
+        let start = Instant::now();
+        // 1000 H/s for 60s may claim up to 600k hashes at factor 10
+        let mut check = SolutionRateCheck::new(1000.0, 10.0, Duration::from_secs(60));
+        for i in 0..20 {
+            let at = start + Duration::from_secs(i * 10);
+            assert!(!check.record(20_000.0, at), "{}", i);
+        }
+        // one lucky solution at a high difficulty is no evidence
+        assert!(!check.record(1e9, start + Duration::from_secs(400)));
+
+        let later = start + Duration::from_secs(1000);
+        for i in 0..4 {
+            assert!(!check.record(200_000.0, later + Duration::from_secs(i)));
+        }
+        assert!(check.record(200_000.0, later + Duration::from_secs(4)));
+        // counting starts over once reported
+        assert!(!check.record(200_000.0, later + Duration::from_secs(5)));
+    }
+}
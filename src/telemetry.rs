@@ -0,0 +1,302 @@
+//! Telemetry snapshot of a solver and, behind the `binary-telemetry` feature, a
+//! compact fixed layout encoding of it for monitoring over constrained links.
+
+use crate::timeout_reason::TimeoutReason;
+use serde::Serialize;
+use usbderive::State;
+
+/// Counters of a solver since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct SolverStats {
+    pub framing_errors: u64,
+    pub baud_reprobes: u64,
+    pub unknown_responses: u64,
+    /// `None` while warming up.
+    pub hashrate: Option<f64>,
+}
+
+/// What a monitoring dashboard shows for one device.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeviceStats {
+    pub serial: String,
+    pub cores: u8,
+    pub goodcores: u8,
+    pub target_freq: u16,
+    pub target_voltage: u16,
+    /// `None` if the sensor is disconnected.
+    pub temperature: Option<u8>,
+    /// Rated hashrate of the device, else its share of the measured one, `None`
+    /// without either.
+    pub hashrate: Option<f64>,
+}
+
+/// Solver counters with the last state of each device, keyed by serial.
+#[derive(Clone, Debug, Default)]
+pub struct Telemetry {
+    pub stats: SolverStats,
+    pub devices: Vec<(String, State)>,
+    /// Why the last job that ran out of time found no solution.
+    pub last_timeout: Option<TimeoutReason>,
+}
+
+#[cfg(feature = "binary-telemetry")]
+pub use self::binary::{decode, encode};
+
+#[cfg(feature = "binary-telemetry")]
+mod binary {
+    use super::{SolverStats, Telemetry, TimeoutReason};
+    use anyhow::Result;
+    use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+    use std::io::{Cursor, Read};
+    use std::time::Duration;
+    use usbderive::State;
+
+    // 2 added the core frequency range, 3 the last timeout reason
+    const VERSION: u8 = 3;
+
+    /// Little endian fixed layout: version, stats, device count, then per device
+    /// the serial with a u8 length and the state fields in declaration order, then
+    /// the last timeout reason as a kind byte, 0 for none, and its fields.
+    /// Optional values are a presence byte followed by the value.
+    pub fn encode(telemetry: &Telemetry) -> Result<Vec<u8>> {
+        let count = telemetry.devices.len();
+        if count > usize::from(u8::MAX) {
+            anyhow::bail!("Too many devices: {}", count);
+        }
+        let mut buf = vec![VERSION];
+        let stats = &telemetry.stats;
+        buf.write_u64::<LittleEndian>(stats.framing_errors)?;
+        buf.write_u64::<LittleEndian>(stats.baud_reprobes)?;
+        buf.write_u64::<LittleEndian>(stats.unknown_responses)?;
+        buf.push(stats.hashrate.is_some() as u8);
+        buf.write_f64::<LittleEndian>(stats.hashrate.unwrap_or_default())?;
+        buf.push(telemetry.devices.len() as u8);
+        for (serial, state) in &telemetry.devices {
+            write_serial(&mut buf, serial)?;
+            buf.extend_from_slice(&[state.chips, state.cores, state.goodcores, state.scanbits]);
+            buf.write_u16::<LittleEndian>(state.scantime)?;
+            buf.write_u16::<LittleEndian>(state.voltage)?;
+            buf.write_u16::<LittleEndian>(state.freq)?;
+            buf.write_u32::<LittleEndian>(state.varity)?;
+            buf.extend_from_slice(&[state.temp, state.hwreboot, state.tempwarn]);
+            buf.push(state.core_mask.is_some() as u8);
+            buf.write_u32::<LittleEndian>(state.core_mask.unwrap_or_default())?;
+            buf.push(state.max_freq.is_some() as u8);
+            buf.write_u16::<LittleEndian>(state.max_freq.unwrap_or_default())?;
+            let (min_freq, max_core_freq) = state.core_freq_range.unwrap_or_default();
+            buf.push(state.core_freq_range.is_some() as u8);
+            buf.write_u16::<LittleEndian>(min_freq)?;
+            buf.write_u16::<LittleEndian>(max_core_freq)?;
+            buf.write_u64::<LittleEndian>(state.latest_updated.as_millis() as u64)?;
+        }
+        write_timeout(&mut buf, telemetry.last_timeout.as_ref())?;
+        Ok(buf)
+    }
+
+    fn write_timeout(buf: &mut Vec<u8>, reason: Option<&TimeoutReason>) -> Result<()> {
+        match reason {
+            None => buf.push(0),
+            Some(TimeoutReason::NotHashing { serial }) => {
+                buf.push(1);
+                write_serial(buf, serial)?;
+            }
+            Some(TimeoutReason::LinkErrors { framing_errors }) => {
+                buf.push(2);
+                buf.write_u64::<LittleEndian>(*framing_errors)?;
+            }
+            Some(TimeoutReason::DegradedCores {
+                serial,
+                goodcores,
+                cores,
+            }) => {
+                buf.push(3);
+                write_serial(buf, serial)?;
+                buf.extend_from_slice(&[*goodcores, *cores]);
+            }
+            Some(TimeoutReason::DifficultyTooHigh { expected, ttl }) => {
+                buf.push(4);
+                buf.write_u64::<LittleEndian>(expected.as_millis() as u64)?;
+                buf.write_u64::<LittleEndian>(ttl.as_millis() as u64)?;
+            }
+            Some(TimeoutReason::BadLuck { expected, ttl }) => {
+                buf.push(5);
+                buf.write_u64::<LittleEndian>(expected.as_millis() as u64)?;
+                buf.write_u64::<LittleEndian>(ttl.as_millis() as u64)?;
+            }
+            Some(TimeoutReason::Unknown) => buf.push(6),
+        }
+        Ok(())
+    }
+
+    fn read_timeout(data: &mut Cursor<&[u8]>) -> Result<Option<TimeoutReason>> {
+        let reason = match data.read_u8()? {
+            0 => return Ok(None),
+            1 => TimeoutReason::NotHashing {
+                serial: read_serial(data)?,
+            },
+            2 => TimeoutReason::LinkErrors {
+                framing_errors: data.read_u64::<LittleEndian>()?,
+            },
+            3 => TimeoutReason::DegradedCores {
+                serial: read_serial(data)?,
+                goodcores: data.read_u8()?,
+                cores: data.read_u8()?,
+            },
+            kind @ 4 | kind @ 5 => {
+                let expected = Duration::from_millis(data.read_u64::<LittleEndian>()?);
+                let ttl = Duration::from_millis(data.read_u64::<LittleEndian>()?);
+                if kind == 4 {
+                    TimeoutReason::DifficultyTooHigh { expected, ttl }
+                } else {
+                    TimeoutReason::BadLuck { expected, ttl }
+                }
+            }
+            6 => TimeoutReason::Unknown,
+            kind => anyhow::bail!("Unknown timeout reason {}", kind),
+        };
+        Ok(Some(reason))
+    }
+
+    fn write_serial(buf: &mut Vec<u8>, serial: &str) -> Result<()> {
+        if serial.len() > usize::from(u8::MAX) {
+            anyhow::bail!("Serial too long: {}", serial);
+        }
+        buf.push(serial.len() as u8);
+        buf.extend_from_slice(serial.as_bytes());
+        Ok(())
+    }
+
+    fn read_serial(data: &mut Cursor<&[u8]>) -> Result<String> {
+        let mut serial = vec![0u8; usize::from(data.read_u8()?)];
+        data.read_exact(&mut serial)?;
+        Ok(String::from_utf8(serial)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Telemetry> {
+        let mut data = Cursor::new(data);
+        let version = data.read_u8()?;
+        if version != VERSION {
+            anyhow::bail!("Unsupported telemetry version {}", version);
+        }
+        let framing_errors = data.read_u64::<LittleEndian>()?;
+        let baud_reprobes = data.read_u64::<LittleEndian>()?;
+        let unknown_responses = data.read_u64::<LittleEndian>()?;
+        let has_hashrate = data.read_u8()? != 0;
+        let hashrate = data.read_f64::<LittleEndian>()?;
+        let stats = SolverStats {
+            framing_errors,
+            baud_reprobes,
+            unknown_responses,
+            hashrate: if has_hashrate { Some(hashrate) } else { None },
+        };
+        let count = data.read_u8()?;
+        let mut devices = Vec::with_capacity(usize::from(count));
+        for _ in 0..count {
+            let serial = read_serial(&mut data)?;
+            let chips = data.read_u8()?;
+            let cores = data.read_u8()?;
+            let goodcores = data.read_u8()?;
+            let scanbits = data.read_u8()?;
+            let scantime = data.read_u16::<LittleEndian>()?;
+            let voltage = data.read_u16::<LittleEndian>()?;
+            let freq = data.read_u16::<LittleEndian>()?;
+            let varity = data.read_u32::<LittleEndian>()?;
+            let temp = data.read_u8()?;
+            let hwreboot = data.read_u8()?;
+            let tempwarn = data.read_u8()?;
+            let has_core_mask = data.read_u8()? != 0;
+            let core_mask = data.read_u32::<LittleEndian>()?;
+            let has_max_freq = data.read_u8()? != 0;
+            let max_freq = data.read_u16::<LittleEndian>()?;
+            let has_core_freq_range = data.read_u8()? != 0;
+            let core_freq_range = (
+                data.read_u16::<LittleEndian>()?,
+                data.read_u16::<LittleEndian>()?,
+            );
+            let latest_updated = Duration::from_millis(data.read_u64::<LittleEndian>()?);
+            let state = State {
+                chips,
+                cores,
+                goodcores,
+                scanbits,
+                scantime,
+                voltage,
+                freq,
+                varity,
+                temp,
+                hwreboot,
+                tempwarn,
+                core_mask: if has_core_mask { Some(core_mask) } else { None },
+                max_freq: if has_max_freq { Some(max_freq) } else { None },
+                core_freq_range: if has_core_freq_range {
+                    Some(core_freq_range)
+                } else {
+                    None
+                },
+                latest_updated,
+            };
+            devices.push((serial, state));
+        }
+        let last_timeout = read_timeout(&mut data)?;
+        Ok(Telemetry {
+            stats,
+            devices,
+            last_timeout,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_round_trip() {
+            let mut raw_data = vec![0u8; 35];
+            raw_data[9..13].copy_from_slice(&[2, 8, 7, 32]);
+            raw_data[15..17].copy_from_slice(&760u16.to_le_bytes());
+            raw_data[17..19].copy_from_slice(&600u16.to_le_bytes());
+            raw_data[23] = 65;
+            raw_data[26..30].copy_from_slice(&0xffu32.to_le_bytes());
+            raw_data[32..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+            let mut state = State::new(&raw_data).unwrap();
+            state.latest_updated = Duration::from_millis(1_600_000_000_123);
+            state.core_freq_range = Some((590, 600));
+            let telemetry = Telemetry {
+                stats: SolverStats {
+                    framing_errors: 3,
+                    baud_reprobes: 1,
+                    unknown_responses: 7,
+                    hashrate: Some(1.5e6),
+                },
+                devices: vec![("A1".to_string(), state.clone())],
+                last_timeout: Some(TimeoutReason::DegradedCores {
+                    serial: "A1".to_string(),
+                    goodcores: 5,
+                    cores: 8,
+                }),
+            };
+
+            let encoded = encode(&telemetry).unwrap();
+            let decoded = decode(&encoded).unwrap();
+            assert_eq!(decoded.stats, telemetry.stats);
+            assert_eq!(decoded.devices.len(), 1);
+            assert_eq!(decoded.devices[0].0, "A1");
+            assert_eq!(decoded.last_timeout, telemetry.last_timeout);
+            assert_eq!(
+                format!("{:?}", decoded.devices[0].1),
+                format!("{:?}", state)
+            );
+
+            assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        }
+
+        #[test]
+        fn test_old_version_rejected() {
+            let mut encoded = encode(&Telemetry::default()).unwrap();
+            // the previous layout ends after the devices, without the last timeout
+            encoded[0] = VERSION - 1;
+            let err = decode(&encoded).unwrap_err();
+            assert!(err.to_string().contains("Unsupported telemetry version"));
+        }
+    }
+}
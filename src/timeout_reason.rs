@@ -0,0 +1,201 @@
+//! Why a job ran out of time without a solution, from the last state of each board
+//! and the link errors during the job.
+
+use crate::hashrate::difficulty_hashes;
+use starcoin_types::U256;
+use std::fmt;
+use std::time::Duration;
+use usbderive::State;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimeoutReason {
+    /// A board reports no good cores, it is not hashing at all.
+    NotHashing { serial: String },
+    /// Frames, maybe solutions among them, were lost to link errors.
+    LinkErrors { framing_errors: u64 },
+    /// A board hashes with part of its cores only.
+    DegradedCores {
+        serial: String,
+        goodcores: u8,
+        cores: u8,
+    },
+    /// At the measured hashrate a solution takes longer than the job had.
+    DifficultyTooHigh { expected: Duration, ttl: Duration },
+    /// The boards look fine and a solution was due within the time, bad luck.
+    BadLuck { expected: Duration, ttl: Duration },
+    /// Nothing looks wrong and there is no hashrate to judge the difficulty by yet.
+    Unknown,
+}
+
+/// What is known about a job when its time is up.
+#[derive(Clone, Debug)]
+pub struct TimeoutFacts {
+    /// Last state of each board, keyed by serial.
+    pub states: Vec<(String, Option<State>)>,
+    /// Framing errors since the job started.
+    pub framing_errors: u64,
+    pub difficulty: U256,
+    pub hashrate: Option<f64>,
+    pub ttl: Duration,
+}
+
+// Cap of the expected solve time, far beyond any job time to live.
+const MAX_EXPECTED_SECS: f64 = 1e9;
+
+impl TimeoutReason {
+    /// The most actionable reason first: a board not hashing, then lost frames,
+    /// then a board short of cores, then the odds.
+    pub fn explain(facts: &TimeoutFacts) -> Self {
+        let states = facts
+            .states
+            .iter()
+            .filter_map(|(serial, state)| Some((serial, state.as_ref()?)));
+        if let Some((serial, _)) = states.clone().find(|(_, state)| state.goodcores == 0) {
+            return TimeoutReason::NotHashing {
+                serial: serial.clone(),
+            };
+        }
+        if facts.framing_errors > 0 {
+            return TimeoutReason::LinkErrors {
+                framing_errors: facts.framing_errors,
+            };
+        }
+        if let Some((serial, state)) = states
+            .clone()
+            .find(|(_, state)| state.goodcores < state.cores)
+        {
+            return TimeoutReason::DegradedCores {
+                serial: serial.clone(),
+                goodcores: state.goodcores,
+                cores: state.cores,
+            };
+        }
+        let hashrate = match facts.hashrate {
+            Some(hashrate) if hashrate > 0.0 => hashrate,
+            _ => return TimeoutReason::Unknown,
+        };
+        let secs = (difficulty_hashes(facts.difficulty) / hashrate).min(MAX_EXPECTED_SECS);
+        let expected = Duration::from_secs_f64(secs);
+        if expected > facts.ttl {
+            TimeoutReason::DifficultyTooHigh {
+                expected,
+                ttl: facts.ttl,
+            }
+        } else {
+            TimeoutReason::BadLuck {
+                expected,
+                ttl: facts.ttl,
+            }
+        }
+    }
+}
+
+impl fmt::Display for TimeoutReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeoutReason::NotHashing { serial } => write!(f, "{} has no good cores", serial),
+            TimeoutReason::LinkErrors { framing_errors } => {
+                write!(f, "{} frames lost to link errors", framing_errors)
+            }
+            TimeoutReason::DegradedCores {
+                serial,
+                goodcores,
+                cores,
+            } => write!(f, "{} hashes on {}/{} cores", serial, goodcores, cores),
+            TimeoutReason::DifficultyTooHigh { expected, ttl } => write!(
+                f,
+                "a solution takes {:?} on average, the job had {:?}",
+                expected, ttl
+            ),
+            TimeoutReason::BadLuck { expected, ttl } => write!(
+                f,
+                "bad luck, a solution takes {:?} on average and the job had {:?}",
+                expected, ttl
+            ),
+            TimeoutReason::Unknown => f.write_str("no hashrate measured yet"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(cores: u8, goodcores: u8) -> State {
+        let mut raw_data = vec![0u8; 29];
+        raw_data[10] = cores;
+        raw_data[11] = goodcores;
+        State::new(&raw_data).unwrap()
+    }
+
+    #[test]
+    fn test_explain() {
+        let healthy = TimeoutFacts {
+            states: vec![
+                ("A1".to_string(), Some(state(8, 8))),
+                ("B2".to_string(), None),
+            ],
+            framing_errors: 0,
+            difficulty: U256::from(1000u64),
+            hashrate: Some(10.0),
+            ttl: Duration::from_secs(60),
+        };
+        assert_eq!(
+            TimeoutReason::explain(&healthy),
+            TimeoutReason::DifficultyTooHigh {
+                expected: Duration::from_secs(100),
+                ttl: Duration::from_secs(60)
+            }
+        );
+        let lucky = TimeoutFacts {
+            hashrate: Some(100.0),
+            ..healthy.clone()
+        };
+        assert_eq!(
+            TimeoutReason::explain(&lucky),
+            TimeoutReason::BadLuck {
+                expected: Duration::from_secs(10),
+                ttl: Duration::from_secs(60)
+            }
+        );
+        let warming_up = TimeoutFacts {
+            hashrate: None,
+            ..healthy.clone()
+        };
+        assert_eq!(TimeoutReason::explain(&warming_up), TimeoutReason::Unknown);
+
+        let degraded = TimeoutFacts {
+            states: vec![("A1".to_string(), Some(state(8, 5)))],
+            ..healthy
+        };
+        assert_eq!(
+            TimeoutReason::explain(&degraded),
+            TimeoutReason::DegradedCores {
+                serial: "A1".to_string(),
+                goodcores: 5,
+                cores: 8
+            }
+        );
+        let lossy = TimeoutFacts {
+            framing_errors: 3,
+            ..degraded
+        };
+        assert_eq!(
+            TimeoutReason::explain(&lossy),
+            TimeoutReason::LinkErrors { framing_errors: 3 }
+        );
+        let dead = TimeoutFacts {
+            states: vec![
+                ("A1".to_string(), Some(state(8, 5))),
+                ("B2".to_string(), Some(state(8, 0))),
+            ],
+            ..lossy
+        };
+        assert_eq!(
+            TimeoutReason::explain(&dead),
+            TimeoutReason::NotHashing {
+                serial: "B2".to_string()
+            }
+        );
+    }
+}
@@ -1,115 +1,4162 @@
 use anyhow::Result;
-use byteorder::{BigEndian, ReadBytesExt};
-use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use async_std::task;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use futures::executor::block_on;
-use futures::SinkExt;
+use futures::{select, FutureExt, StreamExt};
+use starcoin_consensus::{difficult_to_target, Consensus};
 use starcoin_logger::prelude::*;
-use starcoin_types::{U256, system_events::{SealEvent, MintBlockEvent}, block::BlockHeaderExtra};
-use std::io::{Cursor, Write};
-use usbderive::{Config, DeriveResponse, UsbDerive};
-use std::borrow::BorrowMut;
+use starcoin_types::block::BlockHeaderExtra;
+use starcoin_types::genesis_config::ConsensusStrategy;
+use starcoin_types::{U256, system_events::{SealEvent, MintBlockEvent}};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use usbderive::controller::crowded_controllers;
+use usbderive::hotplug::{DeviceEvent, EventDebouncer};
+use usbderive::{
+    Config, DeriveError, DeriveResponse, SerialPortInfo, State, TargetResolution, TargetRounding,
+    UnknownResponse, UsbDerive,
+};
+use crate::aggregator::SolutionAggregator;
+use crate::diagnostics::{DeviceReport, DiagnosticReport};
+use crate::alerts::{Alert, AlertMonitor};
+use crate::clock_skew::ClockSkew;
+use crate::contention::ContentionDetector;
+use crate::extra::{
+    check_blob_version, prefixed_event_extra, prefixed_header, solved_header,
+};
+use crate::hashrate::{difficulty_hashes, HashrateMeter};
+use crate::idle_backoff::IdleBackoff;
+use crate::init_profile::InitProfile;
+use crate::job_log::{self, JobOutcome, JobRecord, NonceRange};
+use crate::job_source::{Job, JobSource};
+use crate::latency::{LatencyStats, StageTimings};
+use crate::nak_breaker::NakBreaker;
+use crate::nonce_positions::NoncePositions;
+use crate::panic_hook;
+use crate::pressure::{PressureMonitor, ResourceUsage};
+use crate::share_stats::{FrequencyShares, ShareStats};
+use crate::solution_rate::SolutionRateCheck;
+use crate::timeout_reason::{TimeoutFacts, TimeoutReason};
+use crate::telemetry::{DeviceStats, SolverStats, Telemetry};
 use starcoin_miner_client_api::Solver;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
+/// A solution exactly as the device sent it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawSolution {
+    pub nonce: u32,
+    pub frame: Vec<u8>,
+}
+
+/// Post-processes every solution before it is sent to `nonce_tx`.
+pub type SubmitHook = Arc<dyn Fn(&mut SealEvent) + Send + Sync>;
+
+/// Custom tags of a device, e.g. the pool worker its board is assigned to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubmitMetadata {
+    /// Replaces the worker id of solutions that carry an extra.
+    pub worker: Option<String>,
+    /// Up to the metadata hook.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl SubmitMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.worker.is_none() && self.tags.is_empty()
+    }
+}
+
+/// Attaches the metadata of the device that found a solution to it.
+pub type MetadataHook = Arc<dyn Fn(&mut SealEvent, &SubmitMetadata) + Send + Sync>;
+
+/// Checks a solution the sanity check would reject, true lets it through.
+pub type SolutionVerifier = Arc<dyn Fn(&SealEvent) -> bool + Send + Sync>;
+
+/// Whether the pow hash of `seal` meets the target of `difficulty`, as the node checks it.
+pub fn verify_seal(seal: &SealEvent, difficulty: U256) -> bool {
+    let extra = seal.extra.as_ref().map_or(BlockHeaderExtra::new([0u8; 4]), |e| e.extra);
+    let strategy = ConsensusStrategy::CryptoNight;
+    match strategy.calculate_pow_hash(&seal.minting_blob, seal.nonce, &extra) {
+        Ok(hash) => U256::from_big_endian(hash.as_slice()) <= difficult_to_target(difficulty),
+        Err(e) => {
+            warn!("Failed to hash solution nonce {}: {}", seal.nonce, e);
+            false
+        }
+    }
+}
+
+/// Gets the serial of the device a share is booked on and whether it was accepted.
+pub type ShareCallback = Arc<dyn Fn(&str, bool) + Send + Sync>;
+
+/// Gets the serial of a device found in bootloader mode, e.g. to reflash it.
+pub type BootloaderHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Where the job id of a solution comes from, among the ids issued to its device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolutionOrigin {
+    /// The job the device is running.
+    Current,
+    /// One of the last few earlier jobs of this solver.
+    Stale,
+    /// A job this solver never issued, e.g. left on the device by a previous process,
+    /// or one issued too long ago.
+    Foreign,
+}
+
+/// A derive and what the solver keeps track of for it between jobs.
 #[derive(Clone)]
-pub struct UsbSolver {
+struct Device {
     derive: UsbDerive,
+    // the device hashes its current job, as far as the solver knows
+    job_running: bool,
+    // the running job is still wanted, a new one queues behind it
+    job_in_flight: bool,
+    asleep: bool,
+    last_state: Option<State>,
+    state_read_at: Option<Instant>,
+    state_asked_at: Option<Instant>,
+    recent_frames: VecDeque<String>,
+    job_uploaded_at: Option<Instant>,
+    // consecutive failed reads and writes
+    link_errors: u32,
+    breaker: NakBreaker,
+    contention: ContentionDetector,
+    // the last job ids issued to the device, oldest first
+    issued_job_ids: VecDeque<u8>,
+    current_job_id: Option<u8>,
+    // host nonce prefix each job id was last issued with
+    nonce_prefixes: [u32; 16],
+    solution_rate: Option<SolutionRateCheck>,
+    // every solution goes through the verifier after an implausible solution rate
+    verify_all: bool,
+    // left out of jobs until reopened, mining commands get it nowhere
+    in_bootloader: bool,
+    // the last state had the voltage off target
+    voltage_off: bool,
+    unknown_responses: u64,
+    // did not answer the last ping
+    unresponsive: bool,
+    // where the search of its current header resumes, from a saved nonce position
+    resume_nonce: Option<u64>,
+    clock: ClockSkew,
+}
+
+impl Device {
+    fn new(derive: UsbDerive) -> Self {
+        let config = derive.config();
+        let breaker = NakBreaker::new(config.nak_breaker_limit, config.nak_cooldown);
+        let contention = ContentionDetector::new(config.contention_window, config.contention_limit);
+        let solution_rate = config.nominal_hashrate.map(|hashrate| {
+            SolutionRateCheck::new(
+                hashrate,
+                config.solution_rate_factor,
+                config.solution_rate_window,
+            )
+        });
+        Self {
+            derive,
+            job_running: false,
+            job_in_flight: false,
+            asleep: false,
+            last_state: None,
+            state_read_at: None,
+            state_asked_at: None,
+            recent_frames: VecDeque::new(),
+            job_uploaded_at: None,
+            link_errors: 0,
+            breaker,
+            contention,
+            issued_job_ids: VecDeque::new(),
+            current_job_id: None,
+            nonce_prefixes: [0; 16],
+            solution_rate,
+            verify_all: false,
+            in_bootloader: false,
+            voltage_off: false,
+            unknown_responses: 0,
+            unresponsive: false,
+            resume_nonce: None,
+            clock: ClockSkew::default(),
+        }
+    }
+
+    // Link errors or bad replies, the board may have wedged while still on the bus.
+    fn suspect(&self, now: Instant) -> bool {
+        self.link_errors > 0 || self.breaker.is_open(now)
+    }
+
+    fn issue_job_id(&mut self, job_id: u8, nonce_prefix: u32) {
+        // a reused id is only as old as its last issue
+        self.issued_job_ids.retain(|&id| id != job_id);
+        if self.issued_job_ids.len() == RECENT_JOB_IDS {
+            self.issued_job_ids.pop_front();
+        }
+        self.issued_job_ids.push_back(job_id);
+        self.current_job_id = Some(job_id);
+        self.nonce_prefixes[usize::from(job_id)] = nonce_prefix;
+    }
+
+    fn set_state(&mut self, state: State) {
+        self.last_state = Some(state);
+        self.state_read_at = Some(Instant::now());
+    }
+
+    fn nonce_prefix(&self, job_id: u8) -> u32 {
+        self.nonce_prefixes.get(usize::from(job_id)).copied().unwrap_or(0)
+    }
+
+    fn solution_origin(&self, job_id: u8) -> SolutionOrigin {
+        if self.current_job_id == Some(job_id) {
+            SolutionOrigin::Current
+        } else if self.issued_job_ids.contains(&job_id) {
+            SolutionOrigin::Stale
+        } else {
+            SolutionOrigin::Foreign
+        }
+    }
+}
+
+/// A job being mined, what the solve loop keeps track of from turn to turn.
+struct JobRun<'a> {
+    job: &'a Job,
+    deadline: Option<Instant>,
+    // the target as the devices get it, and its leading 32 bits
+    device_target: Vec<u8>,
+    target: u32,
+    started: Instant,
+    started_at: SystemTime,
+    // framing errors of the solver when the job started
+    framing_errors: u64,
+    fingerprint: u64,
+    job_ids: Vec<u8>,
+    // header each device searches, under its nonce prefix while prefixing
+    headers: Vec<Vec<u8>>,
+    prefixing: bool,
+    device_count: u32,
+    // round of nonce prefixes and when its nonce space is searched
+    round: u32,
+    round_end: Instant,
+    job_sent_at: Instant,
+    positions_saved_at: Instant,
+    // devices mining the job, and those it was set up on
+    active: Vec<usize>,
+    mined: Vec<usize>,
+    aggregator: SolutionAggregator,
+    idle_backoff: Option<IdleBackoff>,
+    // which ready device is read first
+    turn: usize,
+    outcome: JobOutcome,
+    solved_by: Option<usize>,
+    stopped: bool,
+}
+
+impl<'a> JobRun<'a> {
+    fn nonce_prefix(&self, index: usize) -> u32 {
+        if self.prefixing {
+            self.round
+                .wrapping_mul(self.device_count)
+                .wrapping_add(index as u32)
+        } else {
+            0
+        }
+    }
+
+    fn header(&self, index: usize) -> Result<Vec<u8>> {
+        let extra = self.job.extra.as_ref().map(|e| &e.extra);
+        prefixed_header(&self.job.minting_blob, extra, self.nonce_prefix(index))
+    }
+}
+
+#[derive(Clone)]
+pub struct UsbSolver {
+    vid: u16,
+    pid: u16,
+    init_profile: InitProfile,
+    devices: Vec<Device>,
+    config: Config,
+    raw_tx: Option<UnboundedSender<RawSolution>>,
+    alerts: AlertMonitor,
+    share_stats: ShareStats,
+    submit_hook: Option<SubmitHook>,
+    // by device serial, devices without an entry submit untagged
+    device_metadata: HashMap<String, SubmitMetadata>,
+    metadata_hook: Option<MetadataHook>,
+    verifier: Option<SolutionVerifier>,
+    on_share: Option<ShareCallback>,
+    on_bootloader: Option<BootloaderHook>,
+    // ids of devices reported in bootloader mode
+    bootloader_reported: HashSet<String>,
+    // holds back closing a device missing from a refresh, it may be back on the next
+    hotplug: EventDebouncer,
+    pressure: Option<PressureMonitor>,
+    // host under resource pressure as of the last check, auxiliary work is throttled
+    under_pressure: bool,
+    firmware_bugs: u64,
+    foreign_solutions: u64,
+    tip: Arc<AtomicU64>,
+    timeouts_adapted_at: Option<Instant>,
+    last_timeout: Option<TimeoutReason>,
+    unknown_responses: u64,
+    sinks: Vec<UnboundedSender<SealEvent>>,
+    job_rng: Option<StdRng>,
+    hashrate: HashrateMeter,
+    // device of the last solution read, shares are booked on it
+    solved_by: Option<usize>,
+    // fingerprint and job ids of the last job set up, to spot a duplicate of it
+    last_job: Option<(u64, Vec<u8>)>,
+    // device and read stages of the last solution read, until it is submitted
+    pending_timings: Option<StageTimings>,
+    latency: LatencyStats,
+    nonce_positions: Option<NoncePositions>,
+}
+
+/// USB vendor id of the stock boards.
+pub const VID: u16 = 1155;
+/// USB product id of the stock boards.
+pub const PID: u16 = 22336;
+// frames of each device kept for the panic report
+const RECENT_FRAMES: usize = 16;
+// job ids run from 1 to 15
+const JOB_IDS: usize = 15;
+// job ids a device keeps solutions of, older ones are taken for foreign
+const RECENT_JOB_IDS: usize = 4;
+// wait between polls while several devices are idle
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+// wait for input on a port that cannot tell how much is pending
+const INPUT_POLL_TIMEOUT: Duration = Duration::from_millis(1);
+
+fn attach_metadata(seal: &mut SealEvent, metadata: &SubmitMetadata, hook: Option<&MetadataHook>) {
+    if let (Some(worker), Some(extra)) = (&metadata.worker, seal.extra.as_mut()) {
+        extra.worker_id = worker.clone();
+    }
+    if let Some(hook) = hook {
+        hook(seal, metadata);
+    }
 }
 
-const VID: u16 = 1155;
-const PID: u16 = 22336;
+// The leading 32 bits of a big endian target, what the stock firmware compares.
+fn target_word(target: &[u8]) -> u32 {
+    let mut word = [0u8; 4];
+    let len = target.len().min(4);
+    word[..len].copy_from_slice(&target[..len]);
+    u32::from_be_bytes(word)
+}
 
 impl UsbSolver {
     pub fn new() -> Result<Self> {
+        Self::with_config(VID, PID, Config::default())
+    }
+
+    /// Solve on the derives that enumerate under `vid` and `pid`, opened with `config`.
+    pub fn with_config(vid: u16, pid: u16, config: Config) -> Result<Self> {
+        Self::open(vid, pid, config, InitProfile::default())
+    }
+
+    /// Set up each device with the init sequence of the profile at `path` instead of
+    /// the built-in one, see `init_profile`.
+    pub fn new_from_profile<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open(VID, PID, Config::default(), InitProfile::load(path)?)
+    }
+
+    fn open(vid: u16, pid: u16, config: Config, init_profile: InitProfile) -> Result<Self> {
         let _ = starcoin_logger::init();
-        let ports = UsbDerive::detect(VID, PID)?;
-        let mut usb_derive: Option<UsbDerive> = None;
-        for port in ports {
-            match UsbDerive::open(&port.port_name, Config::default()) {
+        let mut solver = Self::from_derives(vec![], config);
+        solver.vid = vid;
+        solver.pid = pid;
+        solver.init_profile = init_profile;
+        solver.refresh_devices()?;
+        if solver.devices.is_empty() {
+            anyhow::bail!("No usb derive found");
+        }
+        info!("Usb solver inited with {} derives", solver.devices.len());
+        if let Some(limit) = solver.config.controller_device_limit {
+            for (controller, serials) in crowded_controllers(&solver.controllers(), limit) {
+                warn!(
+                    "{} derives share usb controller {}, move some to another: {}",
+                    serials.len(),
+                    controller,
+                    serials.join(", ")
+                );
+            }
+        }
+
+        Ok(solver)
+    }
+
+    #[cfg(test)]
+    fn from_derive(derive: UsbDerive, config: Config) -> Self {
+        Self::from_derives(vec![derive], config)
+    }
+
+    fn from_derives(derives: Vec<UsbDerive>, config: Config) -> Self {
+        let alerts = AlertMonitor::new(config.alert_interval);
+        let hashrate = HashrateMeter::new(config.warmup);
+        let hotplug = EventDebouncer::new(config.hotplug_debounce);
+        let nonce_positions = config.nonce_positions.as_ref().and_then(|path| {
+            NoncePositions::load(path)
+                .map_err(|e| warn!("Failed to load nonce positions, start over: {:?}", e))
+                .ok()
+        });
+        Self {
+            vid: VID,
+            pid: PID,
+            init_profile: InitProfile::default(),
+            devices: derives.into_iter().map(Device::new).collect(),
+            config,
+            raw_tx: None,
+            alerts,
+            share_stats: ShareStats::default(),
+            submit_hook: None,
+            device_metadata: HashMap::new(),
+            metadata_hook: None,
+            verifier: None,
+            on_share: None,
+            on_bootloader: None,
+            bootloader_reported: HashSet::new(),
+            hotplug,
+            pressure: None,
+            under_pressure: false,
+            firmware_bugs: 0,
+            foreign_solutions: 0,
+            tip: Arc::new(AtomicU64::new(0)),
+            timeouts_adapted_at: None,
+            last_timeout: None,
+            unknown_responses: 0,
+            sinks: vec![],
+            job_rng: None,
+            hashrate,
+            solved_by: None,
+            last_job: None,
+            pending_timings: None,
+            latency: LatencyStats::default(),
+            nonce_positions,
+        }
+    }
+
+    /// Enumerate the derives again and swap in the new set: devices still plugged in
+    /// keep running with their config as is, new ones are opened and get the init
+    /// profile, removed ones are closed, as are ones that stopped answering, to be
+    /// opened again. Devices over `Config::max_devices` are left out.
+    pub fn refresh_devices(&mut self) -> Result<()> {
+        let ports = UsbDerive::detect(self.vid, self.pid)?;
+        let config = self.config.clone();
+        let init_profile = self.init_profile.clone();
+        self.open_ports(ports, |port| {
+            let mut derive = UsbDerive::open_port(port, config.clone())?;
+            init_profile.apply(&mut derive)?;
+            let profile = init_profile.clone();
+            derive.set_init_hook(Arc::new(move |derive| profile.reapply(derive)));
+            Ok(derive)
+        });
+        Ok(())
+    }
+
+    /// Swap in the devices on the first `Config::max_devices` of `ports`, the new
+    /// ones are opened with `open`.
+    fn open_ports<F>(&mut self, ports: Vec<SerialPortInfo>, mut open: F)
+    where
+        F: FnMut(&SerialPortInfo) -> Result<UsbDerive>,
+    {
+        let ports = UsbDerive::limit_ports(ports, self.config.max_devices);
+        let ids: Vec<String> = ports.iter().map(UsbDerive::port_id).collect();
+        self.swap_devices(&ids, |index| open(&ports[index]));
+    }
+
+    /// Keep the devices whose id is in `ids` and open the others with `open`,
+    /// which gets the index of the id. A device missing from `ids` is closed once
+    /// it stayed away for `Config::hotplug_debounce`, one back before is kept as is.
+    /// A device with link errors or bad replies is pinged, one that does not answer
+    /// counts as missing, so it is opened again.
+    fn swap_devices<F>(&mut self, ids: &[String], mut open: F)
+    where
+        F: FnMut(usize) -> Result<UsbDerive>,
+    {
+        let solved_by = self
+            .solved_by
+            .and_then(|index| self.devices.get(index))
+            .map(|device| device.derive.id());
+        let now = Instant::now();
+        let mut events = vec![];
+        for device in &mut self.devices {
+            let id = device.derive.id();
+            let listed = ids.contains(&id);
+            device.unresponsive = listed && device.suspect(now) && !device.derive.is_alive();
+            if device.unresponsive {
+                warn!("Usb derive {} does not answer, open it again", id);
+                self.alerts.record_unresponsive(&id, now);
+            }
+            let event = if listed && !device.unresponsive {
+                DeviceEvent::Connected(id)
+            } else {
+                DeviceEvent::Disconnected(id)
+            };
+            events.extend(self.hotplug.push(event, now));
+        }
+        events.extend(self.hotplug.poll(now));
+        let gone: Vec<String> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                DeviceEvent::Disconnected(id) => Some(id),
+                DeviceEvent::Connected(_) => None,
+            })
+            .collect();
+        // one in bootloader mode is opened again, it may have been flashed meanwhile
+        self.devices.retain(|device| {
+            let id = device.derive.id();
+            let keep = !gone.contains(&id) && !device.in_bootloader;
+            if !keep {
+                info!("Close usb derive {}", id);
+            }
+            keep
+        });
+        for (index, id) in ids.iter().enumerate() {
+            if self.devices.iter().any(|device| &device.derive.id() == id) {
+                continue;
+            }
+            match open(index) {
                 Ok(derive) => {
-                    usb_derive = Some(derive);
-                    break;
+                    info!("Open usb derive {}", id);
+                    self.bootloader_reported.remove(id);
+                    self.devices.push(Device::new(derive));
+                }
+                Err(e) if UsbDerive::is_in_bootloader(&e) => self.report_bootloader(id),
+                Err(e) => warn!("Failed to open usb derive {}: {:?}", id, e),
+            }
+        }
+        self.solved_by = solved_by.and_then(|id| {
+            self.devices
+                .iter()
+                .position(|device| device.derive.id() == id)
+        });
+    }
+
+    /// Draw job ids from `rng` instead of `thread_rng`, to reproduce a job id sequence.
+    pub fn set_job_rng(&mut self, rng: StdRng) {
+        self.job_rng = Some(rng);
+    }
+
+    fn next_job_id(&mut self) -> u8 {
+        match &mut self.job_rng {
+            Some(rng) => rng.gen_range(1..16),
+            None => rand::thread_rng().gen_range(1..16),
+        }
+    }
+
+    /// A job id for each device, distinct as long as there are ids left.
+    fn next_job_ids(&mut self) -> Vec<u8> {
+        let count = self.devices.len();
+        let mut ids = Vec::with_capacity(count);
+        while ids.len() < count {
+            let id = self.next_job_id();
+            if !ids.contains(&id) || ids.len() >= JOB_IDS {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Job ids for the devices, each other than its id in `previous` so a late
+    /// solution of the previous ids is not taken for one of the new.
+    fn next_job_ids_after(&mut self, previous: &[u8]) -> Vec<u8> {
+        loop {
+            let ids = self.next_job_ids();
+            if ids.iter().zip(previous).all(|(id, previous)| id != previous) {
+                return ids;
+            }
+        }
+    }
+
+    /// Also send every solution to `sink`, e.g. a backup pool or a log.
+    /// Closed sinks are dropped, solving goes on while any sink is open.
+    pub fn add_sink(&mut self, sink: UnboundedSender<SealEvent>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn set_submit_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&mut SealEvent) + Send + Sync + 'static,
+    {
+        self.submit_hook = Some(Arc::new(hook));
+    }
+
+    /// Tag every solution of the device with `serial` with `metadata`, an empty one
+    /// clears it.
+    pub fn set_device_metadata(&mut self, serial: &str, metadata: SubmitMetadata) {
+        if metadata.is_empty() {
+            self.device_metadata.remove(serial);
+        } else {
+            self.device_metadata.insert(serial.to_string(), metadata);
+        }
+    }
+
+    /// Called with solutions of devices that have metadata, after the submit hook.
+    pub fn set_metadata_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&mut SealEvent, &SubmitMetadata) + Send + Sync + 'static,
+    {
+        self.metadata_hook = Some(Arc::new(hook));
+    }
+
+    /// Verify solutions with an all-zero hash or nonce, which buggy firmware reports
+    /// when it found nothing. Without a verifier they are checked with `verify_seal`.
+    pub fn set_verifier<F>(&mut self, verifier: F)
+    where
+        F: Fn(&SealEvent) -> bool + Send + Sync + 'static,
+    {
+        self.verifier = Some(Arc::new(verifier));
+    }
+
+    /// Why the last job that ran out of time found no solution.
+    pub fn last_timeout(&self) -> Option<&TimeoutReason> {
+        self.last_timeout.as_ref()
+    }
+
+    /// USB host controller of each device keyed by serial, `None` where unknown.
+    pub fn controllers(&self) -> Vec<(String, Option<String>)> {
+        self.devices
+            .iter()
+            .map(|device| (device.derive.id(), device.derive.metadata().controller))
+            .collect()
+    }
+
+    /// Set the command timeout of every device to `Config::latency_timeout_factor`
+    /// times its ping latency, a device that does not answer keeps its timeout.
+    pub fn adapt_ack_timeouts(&mut self) {
+        let factor = match self.config.latency_timeout_factor {
+            Some(factor) => factor,
+            None => return,
+        };
+        for device in &mut self.devices {
+            match device.derive.adapt_ack_timeout(factor) {
+                Ok(timeout) => debug!("Ack timeout of {} is {:?}", device.derive.id(), timeout),
+                Err(e) => warn!("Failed to ping {}: {:?}", device.derive.id(), e),
+            }
+        }
+        self.timeouts_adapted_at = Some(Instant::now());
+    }
+
+    /// Call `on_share` with every share fed to `record_share`, e.g. for accounting.
+    pub fn set_share_callback<F>(&mut self, on_share: F)
+    where
+        F: Fn(&str, bool) + Send + Sync + 'static,
+    {
+        self.on_share = Some(Arc::new(on_share));
+    }
+
+    /// Call `on_bootloader` with the serial of a device that answers from its
+    /// bootloader, the device mines again once reopened after recovery.
+    pub fn set_bootloader_hook<F>(&mut self, on_bootloader: F)
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.on_bootloader = Some(Arc::new(on_bootloader));
+    }
+
+    // Reported once per device until it opens in mining mode again.
+    fn report_bootloader(&mut self, serial: &str) {
+        if !self.bootloader_reported.insert(serial.to_string()) {
+            return;
+        }
+        error!("Usb derive {} is in bootloader mode, flash its firmware", serial);
+        if let Some(on_bootloader) = &self.on_bootloader {
+            on_bootloader(serial);
+        }
+    }
+
+    /// Measure host resource use with `check`, past the `pressure_*` limits of the
+    /// config the solve loop throttles its auxiliary work, mining goes on.
+    pub fn set_resource_check<F>(&mut self, check: F)
+    where
+        F: Fn() -> ResourceUsage + Send + Sync + 'static,
+    {
+        self.pressure = Some(PressureMonitor::new(
+            Arc::new(check),
+            self.config.pressure_cpu_limit,
+            self.config.pressure_min_free_memory,
+            self.config.pressure_check_interval,
+        ));
+    }
+
+    /// Whether the last resource check found the host under pressure.
+    pub fn under_pressure(&self) -> bool {
+        self.under_pressure
+    }
+
+    fn check_pressure(&mut self, now: Instant) {
+        let under_pressure = match &mut self.pressure {
+            Some(pressure) => pressure.under_pressure(now),
+            None => return,
+        };
+        if under_pressure != self.under_pressure {
+            info!("Host under resource pressure: {}", under_pressure);
+            self.under_pressure = under_pressure;
+        }
+    }
+
+    /// Bogus solutions dropped since the solver was created.
+    pub fn firmware_bugs(&self) -> u64 {
+        self.firmware_bugs
+    }
+
+    /// Serials of the devices left alone after repeated bad replies, until their
+    /// cooldown is over, and of those that did not answer a ping on the last
+    /// `refresh_devices`.
+    pub fn unhealthy_devices(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.devices
+            .iter()
+            .filter(|device| device.breaker.is_open(now) || device.unresponsive)
+            .map(|device| device.derive.id())
+            .collect()
+    }
+
+    /// Solutions dropped since the solver was created because their job id was never
+    /// issued to the device.
+    pub fn foreign_solutions(&self) -> u64 {
+        self.foreign_solutions
+    }
+
+    /// Height of the newest block on chain, shared by all clones of the solver.
+    /// Solutions for a block at or below it are orphans and get dropped.
+    pub fn set_current_tip(&self, height: u64) {
+        self.tip.fetch_max(height, Ordering::SeqCst);
+    }
+
+    /// Push notifications when a device crosses a health boundary,
+    /// a new call replaces the previous receiver.
+    pub fn alerts(&mut self) -> UnboundedReceiver<Alert> {
+        self.alerts.subscribe()
+    }
+
+    /// Feed back whether the node accepted a submitted solution,
+    /// it is booked on the device that found the last one. Skipped before any.
+    pub fn record_share(&mut self, accepted: bool) {
+        let index = match self.solved_by {
+            Some(index) => index,
+            None => {
+                warn!("Skip share of a solution no known device found");
+                return;
+            }
+        };
+        let derive = match self.devices.get(index) {
+            Some(device) => &device.derive,
+            None => return,
+        };
+        let serial = derive.id();
+        self.share_stats
+            .record(&serial, derive.config().target_freq, accepted);
+        let max_reject_rate = self.config.reject_rate_limit;
+        self.alerts
+            .record_share(&serial, accepted, max_reject_rate, Instant::now());
+        if let Some(on_share) = &self.on_share {
+            on_share(&serial, accepted);
+        }
+    }
+
+    /// Raw frames of every solution read from the device, including ones the submit window drops.
+    /// Requires `Config::raw_solutions`, a new call replaces the previous receiver.
+    pub fn raw_solutions(&mut self) -> Result<UnboundedReceiver<RawSolution>> {
+        if !self.config.raw_solutions {
+            anyhow::bail!("Raw solutions are disabled in config");
+        }
+        let (raw_tx, raw_rx) = mpsc::unbounded();
+        self.raw_tx = Some(raw_tx);
+        Ok(raw_rx)
+    }
+
+    /// Opt-in, logs the device serial, job id and last frame when the solve loop panics.
+    pub fn enable_panic_hook(&self) {
+        panic_hook::install();
+    }
+
+    /// Accepted and rejected shares by frequency for each device.
+    pub fn share_report(&self) -> Vec<(String, Vec<FrequencyShares>)> {
+        self.share_stats.report()
+    }
+
+    /// Responses of a type the solver does not know, since it was created.
+    pub fn unknown_responses(&self) -> u64 {
+        self.unknown_responses
+    }
+
+    /// Time each solution spent on the device, the serial link and in submission.
+    pub fn latency(&self) -> LatencyStats {
+        self.latency.clone()
+    }
+
+    /// Hashes per second, estimated from the difficulty of the solutions found.
+    /// `None` during the warmup after the first job or a frequency change.
+    pub fn hashrate(&self) -> Option<f64> {
+        self.hashrate.hashrate(Instant::now())
+    }
+
+    // Rated hashrate of a device, else its share of the measured one among `devices`.
+    fn device_hashrate(&self, index: usize, devices: usize) -> Option<f64> {
+        let nominal = self.devices[index].derive.config().nominal_hashrate;
+        nominal.or_else(|| self.hashrate().map(|rate| rate / devices as f64))
+    }
+
+    /// Solver stats, the link stats summed over all devices.
+    pub fn stats(&self) -> SolverStats {
+        let mut stats = SolverStats {
+            framing_errors: 0,
+            baud_reprobes: 0,
+            unknown_responses: self.unknown_responses,
+            hashrate: self.hashrate(),
+        };
+        for device in &self.devices {
+            let derive_stats = device.derive.stats();
+            stats.framing_errors += derive_stats.framing_errors;
+            stats.baud_reprobes += derive_stats.baud_reprobes;
+        }
+        stats
+    }
+
+    /// Solver stats with the last state read from each device.
+    pub fn telemetry(&self) -> Telemetry {
+        let devices = self
+            .devices
+            .iter()
+            .filter_map(|device| {
+                let state = device.last_state.clone()?;
+                Some((device.derive.id(), state))
+            })
+            .collect();
+        Telemetry {
+            stats: self.stats(),
+            devices,
+            last_timeout: self.last_timeout.clone(),
+        }
+    }
+
+    /// Last state read from the device with this serial, without going to the device.
+    /// The solve loop refreshes it every `Config::state_refresh_interval`.
+    pub fn snapshot_state(&self, serial: &str) -> Option<State> {
+        self.device(serial)?.last_state.clone()
+    }
+
+    /// Time since the snapshot of the device with this serial was read.
+    pub fn snapshot_age(&self, serial: &str) -> Option<Duration> {
+        self.device(serial)?.state_read_at.map(|at| at.elapsed())
+    }
+
+    fn device(&self, serial: &str) -> Option<&Device> {
+        self.devices.iter().find(|device| device.derive.id() == serial)
+    }
+
+    /// Query every device for its state, a failed query falls back to the last
+    /// state read. Fails if a device has not reported any state yet.
+    pub fn device_stats(&mut self) -> Result<Vec<DeviceStats>> {
+        let mut stats = vec![];
+        let count = self.devices.len();
+        for index in 0..count {
+            let hashrate = self.device_hashrate(index, count);
+            let device = &mut self.devices[index];
+            let serial = device.derive.id();
+            match device.derive.get_state() {
+                Ok(state) => device.set_state(state),
+                Err(e) if device.last_state.is_some() => {
+                    warn!("Get state of {} failed, use the last one: {:?}", serial, e)
                 }
+                Err(e) => return Err(e),
+            }
+            let state = device.last_state.as_ref().expect("state is set above");
+            let config = device.derive.config();
+            stats.push(DeviceStats {
+                serial,
+                cores: state.cores,
+                goodcores: state.goodcores,
+                target_freq: config.target_freq,
+                target_voltage: config.target_voltage,
+                temperature: state.temperature(),
+                hashrate,
+            });
+        }
+        Ok(stats)
+    }
+
+    /// Query every device for its state, and its error log where the firmware has the
+    /// command, and gather them with the config, link stats, clock skew and recent
+    /// frames. A failed query leaves its section empty.
+    pub fn diagnostic_report(&mut self) -> DiagnosticReport {
+        let mut devices = vec![];
+        for device in &mut self.devices {
+            let state = match device.derive.get_state() {
+                Ok(state) => Some(state),
                 Err(e) => {
-                    warn!("Failed to open port:{:?}", e);
-                    continue;
+                    warn!("Get state for diagnostics failed: {:?}", e);
+                    device.last_state.clone()
                 }
+            };
+            let error_log = if device.derive.config().error_log_command {
+                match device.derive.error_log() {
+                    Ok(entries) => {
+                        device.clock.sample_error_log(&entries, Instant::now());
+                        Some(entries)
+                    }
+                    Err(e) => {
+                        warn!("Get error log for diagnostics failed: {:?}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            devices.push(DeviceReport {
+                serial: device.derive.id(),
+                metadata: device.derive.metadata(),
+                config: device.derive.config().clone(),
+                state,
+                stats: device.derive.stats(),
+                error_log,
+                clock_skew_ppm: device.clock.skew_ppm(),
+                recent_frames: device.recent_frames.iter().cloned().collect(),
+            });
+        }
+        DiagnosticReport {
+            solver: self.stats(),
+            devices,
+        }
+    }
+
+    /// Config in effect on each device, keyed by serial number.
+    pub fn device_configs(&self) -> Vec<(String, Config)> {
+        self.devices
+            .iter()
+            .map(|device| (device.derive.id(), device.derive.config().clone()))
+            .collect()
+    }
+
+    /// Set the frequency and voltage of every device without reopening it. Nothing
+    /// is sent if the values are out of range for any device.
+    pub fn retune(&mut self, freq: u16, voltage: u16) -> Result<()> {
+        for device in &self.devices {
+            device.derive.config().check_hw_params(freq, voltage)?;
+        }
+        let mut result = Ok(());
+        for device in &mut self.devices {
+            if let Err(e) = device.derive.set_freq_voltage(freq, voltage) {
+                warn!("Failed to retune {}: {:?}", device.derive.id(), e);
+                result = Err(e);
             }
         }
-        let mut derive = match usb_derive {
-            None => anyhow::bail!("No usb derive found"),
-            Some(d) => d,
-        };
-        derive.set_hw_params()?;
-        derive.set_opcode()?;
-        info!("Usb solver inited");
+        self.hashrate.restart(Instant::now());
+        result
+    }
+
+    /// Reboot every device so the next run finds them idle, rather than still
+    /// mining the last job.
+    pub fn shutdown(mut self) {
+        for device in &mut self.devices {
+            if let Err(e) = device.derive.reboot() {
+                warn!("Failed to reboot {} on shutdown: {:?}", device.derive.id(), e);
+            }
+        }
+    }
+
+    /// Mine the job of `event` until it is solved or `stop_rx` says stop. While no
+    /// device has input the solver waits on the stop signal and the device poll at
+    /// once, a device is only read once it has something to read.
+    pub async fn solve_async(
+        &mut self,
+        event: MintBlockEvent,
+        mut nonce_tx: UnboundedSender<SealEvent>,
+        mut stop_rx: UnboundedReceiver<bool>,
+    ) -> Result<()> {
+        self.solve_job(&event.into(), &mut nonce_tx, &mut stop_rx, None).await
+    }
+
+    pub fn identify_device(&mut self, serial: &str, duration: Duration) -> Result<()> {
+        match self
+            .devices
+            .iter_mut()
+            .find(|device| device.derive.serial() == Some(serial))
+        {
+            Some(device) => device.derive.identify(duration),
+            None => anyhow::bail!("No usb derive with serial {}", serial),
+        }
+    }
 
-        Ok(Self { derive })
+    /// Device target of a job with the stock firmware, which has room for a u32 only,
+    /// no exponent, and compares it against the leading 32 bits of each hash. So this
+    /// is the top word of the 256-bit target, rounded as configured; difficulties
+    /// of 2^32 and up all map to 0.
+    #[cfg(test)]
+    fn difficulty_to_target_u32(difficulty: U256, rounding: TargetRounding) -> u32 {
+        let target =
+            UsbSolver::difficulty_to_target(difficulty, rounding, TargetResolution::Bits32);
+        target_word(&target)
     }
 
-    fn difficulty_to_target_u32(difficulty: U256) -> u32 {
+    /// The leading big endian bytes of the 256-bit target the firmware compares at
+    /// `resolution`, the last one rounded as configured unless nothing is cut off.
+    fn difficulty_to_target(
+        difficulty: U256,
+        rounding: TargetRounding,
+        resolution: TargetResolution,
+    ) -> Vec<u8> {
+        let len = resolution.bytes();
+        if difficulty.is_zero() {
+            return vec![0xff; len];
+        }
         let target = U256::max_value() / difficulty;
         let mut tb = [0u8; 32];
         target.to_big_endian(tb.as_mut());
-        let mut data = Cursor::new(tb);
-        data.read_u32::<BigEndian>().unwrap()
+        UsbSolver::round_target(&tb, rounding, len)
     }
-}
 
-impl Solver for UsbSolver {
-    fn solve(
+    // The leading `len` bytes of the big endian `target`, rounded down one unit as
+    // `rounding` says if the bytes cut off are not all zero.
+    fn round_target(target: &[u8; 32], rounding: TargetRounding, len: usize) -> Vec<u8> {
+        let (kept, dropped) = target.split_at(len);
+        let mut kept = kept.to_vec();
+        let harder = match (rounding, dropped.first()) {
+            (_, None) | (TargetRounding::Truncate, _) => false,
+            (TargetRounding::Harder, _) => dropped.iter().any(|b| *b != 0),
+            // the dropped bits are at least half a unit
+            (TargetRounding::Nearest, Some(dropped)) => dropped & 0x80 == 0,
+        };
+        if harder && kept.iter().any(|b| *b != 0) {
+            // one unit below, borrowing across bytes
+            for b in kept.iter_mut().rev() {
+                let borrow = *b == 0;
+                *b = b.wrapping_sub(1);
+                if !borrow {
+                    break;
+                }
+            }
+        }
+        kept
+    }
+
+    /// Mine `event` and resolve to the next solution, `None` if none was found within
+    /// `timeout`.
+    pub async fn next_solution(
         &mut self,
         event: MintBlockEvent,
-        nonce_tx: UnboundedSender<SealEvent>,
-        mut stop_rx: UnboundedReceiver<bool>,
+        timeout: Duration,
+    ) -> Result<Option<SealEvent>> {
+        let (mut nonce_tx, mut nonce_rx) = mpsc::unbounded();
+        let (_stop_tx, mut stop_rx) = mpsc::unbounded();
+        let deadline = Instant::now() + timeout;
+        let job = event.into();
+        self.solve_job(&job, &mut nonce_tx, &mut stop_rx, Some(deadline)).await?;
+        Ok(nonce_rx.try_next().ok().flatten())
+    }
+
+    /// Mine the jobs of `source` one after the other until it runs out or every
+    /// solution sink is closed. A job is given up after `job_timeout`, if set.
+    pub fn run(
+        &mut self,
+        source: &mut dyn JobSource,
+        mut nonce_tx: UnboundedSender<SealEvent>,
+        job_timeout: Option<Duration>,
     ) {
-        let target = UsbSolver::difficulty_to_target_u32(event.difficulty);
-        let job_id = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("System time is before the UNIX_EPOCH").as_millis() % 15 + 1;
-        let mut nonce_tx = nonce_tx.clone();
-        let mut blob = event.minting_blob.clone();
-        let extra = match &event.extra {
-            None => { BlockHeaderExtra::new([0u8; 4]) }
-            Some(e) => { e.extra }
-        };
-        let _ = blob[35..39].borrow_mut().write_all(extra.as_slice());
-        if let Err(e) = self.derive.write_state() {
-            error!("get state failed:{}", e);
+        let (_stop_tx, mut stop_rx) = mpsc::unbounded();
+        while let Some(job) = source.next_job() {
+            let deadline = job_timeout.map(|timeout| Instant::now() + timeout);
+            if let Err(e) = block_on(self.solve_job(&job, &mut nonce_tx, &mut stop_rx, deadline)) {
+                error!("Failed to solve job: {:?}", e);
+            }
+            if nonce_tx.is_closed() && self.sinks.iter().all(|sink| sink.is_closed()) {
+                break;
+            }
+        }
+    }
+
+    fn submit_seal(
+        &mut self,
+        nonce_tx: &mut UnboundedSender<SealEvent>,
+        mut seal: SealEvent,
+        block_number: u64,
+    ) {
+        if let Some(device) = self.solved_by.and_then(|index| self.devices.get_mut(index)) {
+            device.job_running = false;
+            device.job_in_flight = false;
         }
-        if let Err(e) = self.derive.set_job(job_id as u8, target, &blob) {
-            error!("Set mint job to derive failed: {:?}", e);
+        let submit_started = Instant::now();
+        let tip = self.tip.load(Ordering::SeqCst);
+        if block_number <= tip {
+            info!(
+                "Drop solution for block {}, chain tip is already at {}",
+                block_number, tip
+            );
             return;
         }
+        if let Some(hook) = &self.submit_hook {
+            hook(&mut seal);
+        }
+        let metadata = self
+            .solved_by
+            .and_then(|index| self.devices.get(index))
+            .and_then(|device| self.device_metadata.get(&device.derive.id()));
+        if let Some(metadata) = metadata {
+            attach_metadata(&mut seal, metadata, self.metadata_hook.as_ref());
+        }
+        if self.config.log_solved_headers {
+            match solved_header(&seal) {
+                Ok(header) => info!("Submit nonce {} header {}", seal.nonce, hex::encode(header)),
+                Err(e) => warn!("Failed to rebuild the header of nonce {}: {:?}", seal.nonce, e),
+            }
+        }
+        self.sinks
+            .retain(|sink| sink.unbounded_send(seal.clone()).is_ok());
+        let _ = nonce_tx.unbounded_send(seal);
+        if let Some(mut timings) = self.pending_timings.take() {
+            timings.submit = submit_started.elapsed();
+            self.latency.record(timings);
+        }
+    }
 
-        loop {
-            if stop_rx.try_next().is_ok() {
-                debug!("Stop solver");
-                break;
+    /// Back off the frequency a step each time the supply stops holding the target
+    /// voltage, a run of off-voltage states is one step.
+    fn check_voltage(&mut self, index: usize, state: &State) {
+        let device = &mut self.devices[index];
+        let config = device.derive.config();
+        let off = config.voltage_off(state);
+        let went_off = off && !device.voltage_off;
+        device.voltage_off = off;
+        let step = match config.voltage_freq_step {
+            Some(step) if went_off => step,
+            _ => return,
+        };
+        let freq = config.target_freq.saturating_sub(step).max(config.freq_limits.0);
+        if freq >= config.target_freq {
+            return;
+        }
+        let voltage = config.target_voltage;
+        warn!("Voltage {}mV off target, lower frequency to {}", state.voltage, freq);
+        match device.derive.set_freq_voltage(freq, voltage) {
+            Ok(()) => self.hashrate.restart(Instant::now()),
+            Err(e) => warn!("Failed to lower frequency: {:?}", e),
+        }
+    }
+
+    // The device whose solution the aggregator handed out last, it may have arrived
+    // ahead of the one just read.
+    fn note_solved_by(&mut self, aggregator: &SolutionAggregator) {
+        if let Some(id) = aggregator.submitted_by() {
+            self.solved_by = self.devices.iter().position(|device| device.derive.id() == id);
+        }
+    }
+
+    /// Read one response from device `index`, returns a solution once one is ready to submit.
+    /// `target` is the target the device got for `job`.
+    fn read_solution(
+        &mut self,
+        index: usize,
+        job: &Job,
+        target: &[u8],
+        aggregator: &mut SolutionAggregator,
+    ) -> Option<SealEvent> {
+        let device = &mut self.devices[index];
+        let framing_errors = device.derive.stats().framing_errors;
+        // With nothing received yet the read waits on the device, not the link.
+        let read_started = if device.derive.pending_bytes() > 0 {
+            Some(Instant::now())
+        } else {
+            None
+        };
+        let resp = device.derive.read();
+        let serial = device.derive.id();
+        match &resp {
+            Err(e) if UsbDerive::is_link_error(e) => device.link_errors += 1,
+            _ => device.link_errors = 0,
+        }
+        if let Ok(resp) = &resp {
+            if !self.under_pressure {
+                panic_hook::record_frame(resp);
+                if device.recent_frames.len() == RECENT_FRAMES {
+                    device.recent_frames.pop_front();
+                }
+                device.recent_frames.push_back(format!("{:?}", resp));
             }
-            // Blocking read since the poll has non-zero timeout
-            match self.derive.read() {
-                Ok(resp) => match resp {
-                    DeriveResponse::SolvedJob(seal) => {
-                        block_on(async {
-                            let _ = nonce_tx.send(SealEvent {
-                                minting_blob: event.minting_blob.clone(),
-                                nonce: seal.nonce,
-                                extra: event.extra,
-                                hash_result: hex::encode(seal.hash),
-                            }).await;
-                        });
-                        break;
-                    }
-                    resp => {
-                        debug!("get resp {:?}", resp);
-                        continue;
-                    }
-                },
+            device.breaker.record_ok();
+            self.alerts.reset_naks(&serial);
+        } else if device.derive.stats().framing_errors > framing_errors {
+            let now = Instant::now();
+            let contended = device.contention.record_bad_frame(now);
+            self.alerts
+                .record_nak(&serial, self.config.nak_alert_limit, now);
+            self.record_nak(index, now);
+            if contended {
+                self.report_contention(&serial, now);
+            }
+        }
+        match resp {
+            Ok(DeriveResponse::SolvedJob(seal)) => {
+                if let Some(raw_tx) = &self.raw_tx {
+                    let _ = raw_tx.unbounded_send(RawSolution {
+                        nonce: seal.nonce,
+                        frame: seal.raw,
+                    });
+                }
+                match self.devices[index].solution_origin(seal.job_id) {
+                    SolutionOrigin::Current => {}
+                    SolutionOrigin::Stale => {
+                        info!("Solution nonce {} for earlier job {}", seal.nonce, seal.job_id)
+                    }
+                    SolutionOrigin::Foreign => {
+                        self.foreign_solutions += 1;
+                        let now = Instant::now();
+                        if self.devices[index].contention.record_foreign_solution(now) {
+                            self.report_contention(&serial, now);
+                        }
+                        warn!(
+                            "Drop solution nonce {} for job {} never issued to {}",
+                            seal.nonce, seal.job_id, serial
+                        );
+                        return None;
+                    }
+                }
+                let bogus = seal.hash == [0u8; 32] || seal.nonce == 0;
+                let prefix = self.devices[index].nonce_prefix(seal.job_id);
+                let seal = SealEvent {
+                    minting_blob: job.minting_blob.clone(),
+                    nonce: seal.nonce,
+                    extra: prefixed_event_extra(job.extra.as_ref(), prefix),
+                    hash_result: hex::encode(seal.hash),
+                };
+                // a zero hash meets a zero target
+                let zero_target = target.iter().all(|b| *b == 0);
+                let suspect = (bogus && !zero_target) || self.devices[index].verify_all;
+                let verified = match &self.verifier {
+                    Some(verifier) => verifier(&seal),
+                    None => verify_seal(&seal, job.difficulty),
+                };
+                if suspect && !verified {
+                    self.firmware_bugs += 1;
+                    warn!("Drop bogus solution nonce {} hash {}", seal.nonce, seal.hash_result);
+                    return None;
+                }
+                let hashes = difficulty_hashes(job.difficulty);
+                self.check_solution_rate(index, hashes);
+                self.hashrate.record(hashes, Instant::now());
+                if let Some(uploaded_at) = self.devices[index].job_uploaded_at {
+                    let now = Instant::now();
+                    let frame_started = read_started.unwrap_or(now);
+                    self.pending_timings = Some(StageTimings {
+                        device: frame_started.saturating_duration_since(uploaded_at),
+                        read: now.saturating_duration_since(frame_started),
+                        submit: Duration::from_secs(0),
+                    });
+                }
+                let submitted = aggregator.push(seal, &serial, Instant::now());
+                if submitted.is_some() {
+                    self.note_solved_by(aggregator);
+                }
+                submitted
+            }
+            Ok(DeriveResponse::State(state)) => {
+                self.alerts.check_state(
+                    &serial,
+                    &state,
+                    self.devices[index].derive.config(),
+                    Instant::now(),
+                );
+                self.check_voltage(index, &state);
+                self.devices[index].set_state(state);
+                None
+            }
+            Ok(resp) if resp.is_unknown() => {
+                self.unknown_responses += 1;
+                let device = &mut self.devices[index];
+                device.unknown_responses += 1;
+                info!("Unknown resp {:?}", resp);
+                if let UnknownResponse::Alert(after) = self.config.unknown_response {
+                    if device.unknown_responses >= after {
+                        self.alerts.record_unknown_responses(
+                            &serial,
+                            device.unknown_responses,
+                            Instant::now(),
+                        );
+                    }
+                }
+                None
+            }
+            Ok(resp) => {
+                debug!("get resp {:?}", resp);
+                None
+            }
+            Err(e) if UsbDerive::is_in_bootloader(&e) => {
+                self.devices[index].in_bootloader = true;
+                self.report_bootloader(&serial);
+                None
+            }
+            Err(e) => {
+                debug!("Failed to solve: {:?}", e);
+                None
+            }
+        }
+    }
+
+    fn upload_job(
+        &mut self,
+        index: usize,
+        job_id: u8,
+        target: &[u8],
+        header: &[u8],
+        nonce_prefix: u32,
+    ) -> Result<()> {
+        let start_nonce = self.nonce_start(index);
+        let device = &mut self.devices[index];
+        device.issue_job_id(job_id, nonce_prefix);
+        // Without a solution the previous job may still be running on the device,
+        // queue the new one behind it instead of interrupting.
+        let queued = device.job_in_flight && self.config.job_queueing;
+        if queued {
+            device.derive.queue_job_target_from(job_id, target, start_nonce, header)?;
+        } else {
+            device.derive.set_job_target_from(job_id, target, start_nonce, header)?;
+        }
+        device.job_running = true;
+        device.job_in_flight = true;
+        if self.config.verify_target && !queued {
+            // the readback has the leading 32 bits only
+            let target = target_word(target);
+            let loaded = device.derive.current_target()?;
+            if loaded.job_id != job_id || loaded.target != target {
+                anyhow::bail!(
+                    "Device loaded job {} target {:#x}, expect job {} target {:#x}",
+                    loaded.job_id,
+                    loaded.target,
+                    job_id,
+                    target
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Devices mining one job search apart, each from its own slice of the nonce space,
+    // or from where a saved position has its search got to.
+    fn nonce_start(&self, index: usize) -> u64 {
+        let slice_start = (1u64 << 32) / self.devices.len() as u64 * index as u64;
+        let resume = self.devices[index].resume_nonce;
+        resume.map_or(slice_start, |nonce| nonce.max(slice_start))
+    }
+
+    // Resume each device where its saved position for its header is, and forget
+    // the positions of other jobs.
+    fn resume_positions(&mut self, headers: &[Vec<u8>]) {
+        let positions = match &mut self.nonce_positions {
+            Some(positions) => positions,
+            None => return,
+        };
+        if let Err(e) = positions.retain(headers) {
+            warn!("Failed to save nonce positions: {:?}", e);
+        }
+        for (device, header) in self.devices.iter_mut().zip(headers) {
+            device.resume_nonce = positions.get(&device.derive.id(), header);
+            if let Some(nonce) = device.resume_nonce {
+                info!("Resume {} at nonce {}", device.derive.id(), nonce);
+            }
+        }
+    }
+
+    // Save how far each device searched its header, reckoned from its hashrate since
+    // the job was sent, at most every `Config::nonce_position_interval`.
+    fn save_positions(&mut self, run: &mut JobRun) {
+        if self.nonce_positions.is_none()
+            || run.positions_saved_at.elapsed() < self.config.nonce_position_interval
+        {
+            return;
+        }
+        run.positions_saved_at = Instant::now();
+        let searched = run.job_sent_at.elapsed().as_secs_f64();
+        let reached: Vec<(usize, u64)> = run
+            .active
+            .iter()
+            .filter_map(|&index| {
+                let rate = self.device_hashrate(index, run.active.len())?;
+                let position = self.nonce_start(index) + (searched * rate) as u64;
+                Some((index, position.min(1u64 << 32)))
+            })
+            .collect();
+        if let Some(positions) = &mut self.nonce_positions {
+            for (index, position) in reached {
+                let serial = self.devices[index].derive.id();
+                if let Err(e) = positions.record(&serial, &run.headers[index], position) {
+                    warn!("Failed to save nonce positions: {:?}", e);
+                }
+            }
+        }
+    }
+
+    // Time until the first of `active` has searched its nonce range. The device does
+    // not report where its search is: it is reckoned from its hashrate, rated or
+    // measured, else from `Config::nonce_space_time` for the whole range.
+    fn nonce_space_span(&self, active: &[usize]) -> Duration {
+        let full_range = 1u64 << 32;
+        let full_range_time = self.config.nonce_space_time.unwrap_or_default();
+        active
+            .iter()
+            .map(|&index| {
+                let range = full_range - self.nonce_start(index);
+                match self.device_hashrate(index, active.len()) {
+                    Some(rate) if rate > 0.0 => Duration::from_secs_f64(range as f64 / rate),
+                    _ => full_range_time.mul_f64(range as f64 / full_range as f64),
+                }
+            })
+            .min()
+            .unwrap_or(full_range_time)
+    }
+
+    // Whether device `index` has input to read, after a short timed read where the
+    // port cannot tell. A failed read counts as a link error.
+    fn input_ready(&mut self, index: usize) -> bool {
+        let device = &mut self.devices[index];
+        match device.derive.poll_input(INPUT_POLL_TIMEOUT) {
+            Ok(pending) => pending > 0,
+            Err(e) => {
+                debug!("Failed to poll {}: {:?}", device.derive.id(), e);
+                if UsbDerive::is_link_error(&e) {
+                    device.link_errors += 1;
+                }
+                false
+            }
+        }
+    }
+
+    /// Reopen device `index` after a run of link errors, false once every attempt failed.
+    fn reconnect_device(&mut self, index: usize) -> bool {
+        let attempts = self.config.reconnect_attempts;
+        let (vid, pid) = (self.vid, self.pid);
+        let device = &mut self.devices[index];
+        let id = device.derive.id();
+        device.link_errors = 0;
+        for attempt in 1..=attempts {
+            warn!("Link to {} lost, reconnect {}/{}", id, attempt, attempts);
+            match device.derive.recover(vid, pid) {
+                Ok(()) => {
+                    device.job_running = false;
+                    device.job_in_flight = false;
+                    device.asleep = false;
+                    return true;
+                }
+                Err(e) => warn!("Failed to reconnect {}: {:?}", id, e),
+            }
+            if attempt < attempts {
+                thread::sleep(self.config.reconnect_delay);
+            }
+        }
+        error!("Failed to reconnect {} after {} attempts", id, attempts);
+        false
+    }
+
+    /// Count a bad reply of device `index`, true if it trips the breaker.
+    fn record_nak(&mut self, index: usize, now: Instant) -> bool {
+        let device = &mut self.devices[index];
+        if !device.breaker.record_nak(now) {
+            return false;
+        }
+        let serial = device.derive.id();
+        let (limit, cooldown) = (self.config.nak_breaker_limit, self.config.nak_cooldown);
+        warn!("Leave {} alone for {:?} after {} bad replies", serial, cooldown, limit);
+        self.alerts.record_breaker_trip(&serial, limit, cooldown, now);
+        true
+    }
+
+    fn check_solution_rate(&mut self, index: usize, difficulty: f64) {
+        let device = &mut self.devices[index];
+        let implausible = match &mut device.solution_rate {
+            Some(check) => check.record(difficulty, Instant::now()),
+            None => return,
+        };
+        if !implausible {
+            return;
+        }
+        warn!(
+            "{} reports more solutions than it can find, its firmware may report false positives",
+            device.derive.id()
+        );
+        if device.derive.config().verify_implausible && !device.verify_all {
+            info!("Verify every solution of {} from now on", device.derive.id());
+            device.verify_all = true;
+        }
+    }
+
+    fn report_contention(&mut self, serial: &str, now: Instant) {
+        warn!(
+            "Another process may be using {}, it sends bad frames and foreign solutions",
+            serial
+        );
+        self.alerts.record_contention(serial, now);
+    }
+
+    /// Wake device `index` if needed and upload the job to it, retrying transient failures.
+    fn setup_job(
+        &mut self,
+        index: usize,
+        job_id: u8,
+        target: &[u8],
+        header: &[u8],
+        nonce_prefix: u32,
+    ) -> Result<()> {
+        if self.devices[index].breaker.is_open(Instant::now()) {
+            anyhow::bail!("Cooling down after repeated bad replies");
+        }
+        if self.devices[index].in_bootloader {
+            return Err(DeriveError::InBootloader.into());
+        }
+        if self.devices[index].asleep {
+            self.devices[index].derive.wake()?;
+            self.devices[index].asleep = false;
+        }
+        let mut retries = 0;
+        loop {
+            let framing_errors = self.devices[index].derive.stats().framing_errors;
+            let e = match self.upload_job(index, job_id, target, header, nonce_prefix) {
+                Ok(()) => break,
+                Err(e) => e,
+            };
+            let nak = self.devices[index].derive.stats().framing_errors > framing_errors;
+            if nak && self.record_nak(index, Instant::now()) {
+                return Err(e);
+            }
+            if retries >= self.config.job_setup_retries {
+                return Err(e);
+            }
+            retries += 1;
+            warn!(
+                "Job setup failed, retry {}/{}: {:?}",
+                retries, self.config.job_setup_retries, e
+            );
+            // drop any half-read reply before setting the job again
+            let _ = self.devices[index].derive.clear_input();
+        }
+        let device = &mut self.devices[index];
+        device.job_uploaded_at = Some(Instant::now());
+        // after the readback, so the state reply cannot be taken for its answer
+        device.state_asked_at = Some(Instant::now());
+        if let Err(e) = device.derive.write_state() {
+            error!("get state failed:{}", e);
+        }
+        panic_hook::set_job(device.derive.serial(), job_id);
+        Ok(())
+    }
+
+    /// Mine `job` on every device, each under its own job id, and submit the first
+    /// solution any of them finds. A device that fails is left out, the others go on.
+    async fn solve_job(
+        &mut self,
+        job: &Job,
+        nonce_tx: &mut UnboundedSender<SealEvent>,
+        stop_rx: &mut UnboundedReceiver<bool>,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        let mut run = self.start_job(job, deadline)?;
+        loop {
+            // A solution the device found before the stop still gets submitted: the
+            // frames already received are drained and a pending submit window is cut short.
+            if nonce_tx.is_closed() && self.sinks.iter().all(|sink| sink.is_closed()) {
+                debug!("All solution sinks are closed");
+                break;
+            }
+            if let Some(seal) = run.aggregator.poll(Instant::now()) {
+                self.note_solved_by(&run.aggregator);
+                self.submit_solution(&mut run, nonce_tx, seal);
+                break;
+            }
+            if self.check_deadline(&mut run) {
+                break;
+            }
+            self.dispatch(&mut run)?;
+            self.save_positions(&mut run);
+            if !self.retain_active(&mut run) {
+                break;
+            }
+            // a message or a closed channel both stop the job
+            let polled = select! {
+                _ = stop_rx.next() => None,
+                ready = self.poll_ready(&mut run).fuse() => Some(ready),
+            };
+            let ready = match polled {
+                Some(Some(ready)) => ready,
+                Some(None) => continue,
+                None => {
+                    debug!("Stop solver");
+                    if let Some(seal) = self.drain_after_stop(&mut run).await {
+                        self.submit_solution(&mut run, nonce_tx, seal);
+                    }
+                    run.stopped = true;
+                    break;
+                }
+            };
+            if let Some(seal) = self.read_ready(&mut run, ready) {
+                self.submit_solution(&mut run, nonce_tx, seal);
+                break;
+            }
+        }
+        self.finish_job(run);
+        Ok(())
+    }
+
+    // Work out the targets, job ids and headers of `job` and upload it to every device.
+    fn start_job<'a>(&mut self, job: &'a Job, deadline: Option<Instant>) -> Result<JobRun<'a>> {
+        if let Some(versions) = &self.config.blob_versions {
+            check_blob_version(&job.minting_blob, versions)?;
+        }
+        // between jobs, so no solution frame is taken for the answer to a ping
+        let interval = self.config.latency_check_interval;
+        if self.timeouts_adapted_at.map_or(true, |at| at.elapsed() >= interval) {
+            self.adapt_ack_timeouts();
+        }
+        let device_target = UsbSolver::difficulty_to_target(
+            job.difficulty,
+            self.config.target_rounding,
+            self.config.target_resolution,
+        );
+        if !job.difficulty.is_zero() && device_target.iter().all(|b| *b == 0) {
+            warn!(
+                "Difficulty {} is past what a {:?} target holds, the device takes any hash \
+                 with the leading bits zero; configure a wider target resolution",
+                job.difficulty, self.config.target_resolution
+            );
+        }
+        let target = target_word(&device_target);
+        let (started, started_at) = (Instant::now(), SystemTime::now());
+        let framing_errors = self.stats().framing_errors;
+        let fingerprint = job.fingerprint();
+        let duplicate = match self.last_job.take() {
+            Some((last, job_ids))
+                if self.config.keep_duplicate_jobs
+                    && last == fingerprint
+                    && job_ids.len() == self.devices.len() =>
+            {
+                Some(job_ids)
+            }
+            _ => None,
+        };
+        let job_ids = match &duplicate {
+            Some(job_ids) => job_ids.clone(),
+            None => self.next_job_ids(),
+        };
+        // With nonce prefixing each device searches a header of its own, under a prefix
+        // no other device gets. A job without an extra has no room for a prefix.
+        let prefixing = self.config.nonce_space_time.is_some() && job.extra.is_some();
+        if self.config.nonce_space_time.is_some() && !prefixing {
+            debug!("Job has no extra to put a nonce prefix in");
+        }
+        let mut run = JobRun {
+            job,
+            deadline,
+            device_target,
+            target,
+            started,
+            started_at,
+            framing_errors,
+            fingerprint,
+            job_ids,
+            headers: vec![],
+            prefixing,
+            device_count: self.devices.len() as u32,
+            round: 0,
+            round_end: started,
+            job_sent_at: started,
+            positions_saved_at: started,
+            active: vec![],
+            mined: vec![],
+            aggregator: SolutionAggregator::new(
+                self.config.submit_window,
+                self.config.submit_policy.clone(),
+            ),
+            idle_backoff: self.config.max_idle_sleep.map(IdleBackoff::new),
+            turn: 0,
+            outcome: JobOutcome::Stopped,
+            solved_by: None,
+            stopped: false,
+        };
+        run.headers = (0..self.devices.len())
+            .map(|index| run.header(index))
+            .collect::<Result<Vec<_>>>()?;
+        self.resume_positions(&run.headers);
+        let mut setup_error = None;
+        for (index, &job_id) in run.job_ids.iter().enumerate() {
+            let device = &self.devices[index];
+            if duplicate.is_some()
+                && device.job_running
+                && !device.asleep
+                && device.current_job_id == Some(job_id)
+            {
+                debug!("{} is still mining the same job", device.derive.id());
+                run.active.push(index);
+                continue;
+            }
+            let prefix = run.nonce_prefix(index);
+            match self.setup_job(index, job_id, &run.device_target, &run.headers[index], prefix) {
+                Ok(()) => run.active.push(index),
                 Err(e) => {
-                    debug!("Failed to solve: {:?}", e);
+                    warn!("Leave {} out of the job: {:?}", self.devices[index].derive.id(), e);
+                    setup_error = Some(e);
+                }
+            }
+        }
+        if run.active.is_empty() {
+            return Err(setup_error
+                .unwrap_or_else(|| anyhow::anyhow!("No usb derive to solve on")));
+        }
+        if !self.hashrate.is_started() {
+            self.hashrate.restart(Instant::now());
+        }
+        run.mined = run.active.clone();
+        run.job_sent_at = Instant::now();
+        run.round_end = run.job_sent_at + self.nonce_space_span(&run.active);
+        Ok(run)
+    }
+
+    fn submit_solution(
+        &mut self,
+        run: &mut JobRun,
+        nonce_tx: &mut UnboundedSender<SealEvent>,
+        seal: SealEvent,
+    ) {
+        run.outcome = JobOutcome::solved(&seal);
+        run.solved_by = self.solved_by;
+        self.submit_seal(nonce_tx, seal, run.job.block_number);
+    }
+
+    // Read what the devices sent until a solution turns up or the stop grace is over,
+    // else cut a pending submit window short.
+    async fn drain_after_stop(&mut self, run: &mut JobRun<'_>) -> Option<SealEvent> {
+        let grace_end = Instant::now() + self.config.stop_grace;
+        let mut seal = None;
+        loop {
+            for &index in &run.active {
+                while seal.is_none() && self.input_ready(index) {
+                    seal = self.read_solution(
+                        index,
+                        run.job,
+                        &run.device_target,
+                        &mut run.aggregator,
+                    );
+                }
+            }
+            if seal.is_some() || Instant::now() >= grace_end {
+                break;
+            }
+            task::sleep(POLL_INTERVAL).await;
+        }
+        let seal = seal.or_else(|| run.aggregator.flush());
+        if seal.is_some() {
+            self.note_solved_by(&run.aggregator);
+        }
+        seal
+    }
+
+    // True once the deadline of the job passed, with the reason it found nothing kept.
+    fn check_deadline(&mut self, run: &mut JobRun) -> bool {
+        let deadline = match run.deadline {
+            Some(deadline) if Instant::now() >= deadline => deadline,
+            _ => return false,
+        };
+        let facts = TimeoutFacts {
+            states: run
+                .active
+                .iter()
+                .map(|&index| {
+                    let device = &self.devices[index];
+                    (device.derive.id(), device.last_state.clone())
+                })
+                .collect(),
+            framing_errors: self.stats().framing_errors - run.framing_errors,
+            difficulty: run.job.difficulty,
+            hashrate: self.hashrate(),
+            ttl: deadline.saturating_duration_since(run.started),
+        };
+        let reason = TimeoutReason::explain(&facts);
+        info!("Job timed out without a solution: {}", reason);
+        self.last_timeout = Some(reason);
+        run.outcome = JobOutcome::Timeout;
+        true
+    }
+
+    // Send the devices what is due while the job runs: the job again after the resend
+    // interval, state queries, and the job under new nonce prefixes once they have
+    // searched their nonce space.
+    fn dispatch(&mut self, run: &mut JobRun) -> Result<()> {
+        if let Some(interval) = self.config.resend_interval {
+            if run.job_sent_at.elapsed() >= interval {
+                for index in run.active.clone() {
+                    if let Err(e) = self.send_job(run, index) {
+                        debug!("Resend mint job to derive failed: {:?}", e);
+                    }
+                }
+                run.job_sent_at = Instant::now();
+            }
+        }
+        if let Some(interval) = self.config.state_refresh_interval {
+            for &index in &run.active {
+                let device = &mut self.devices[index];
+                if device.state_asked_at.map_or(true, |at| at.elapsed() >= interval) {
+                    device.state_asked_at = Some(Instant::now());
+                    if let Err(e) = device.derive.write_state() {
+                        debug!("Ask {} for its state failed: {:?}", device.derive.id(), e);
+                    }
+                }
+            }
+        }
+        // The device nonce is 32 bits, once it is used up new prefixes give the
+        // devices fresh headers to search, under new job ids.
+        if run.prefixing && Instant::now() >= run.round_end {
+            run.round += 1;
+            run.job_ids = self.next_job_ids_after(&run.job_ids);
+            debug!("Nonce space searched, move on to round {} of prefixes", run.round);
+            for index in run.active.clone() {
+                let prefix = run.nonce_prefix(index);
+                run.headers[index] = run.header(index)?;
+                self.devices[index].resume_nonce = None;
+                self.devices[index].issue_job_id(run.job_ids[index], prefix);
+                if let Err(e) = self.send_job(run, index) {
+                    warn!("Send mint job with prefix {} failed: {:?}", prefix, e);
+                }
+            }
+            run.job_sent_at = Instant::now();
+            run.round_end = run.job_sent_at + self.nonce_space_span(&run.active);
+        }
+        Ok(())
+    }
+
+    // Upload the job of `run` to device `index` as it stands, a failed write counts
+    // as a link error.
+    fn send_job(&mut self, run: &JobRun, index: usize) -> Result<()> {
+        let start_nonce = self.nonce_start(index);
+        let device = &mut self.devices[index];
+        let sent = device.derive.set_job_target_from(
+            run.job_ids[index],
+            &run.device_target,
+            start_nonce,
+            &run.headers[index],
+        );
+        if let Err(e) = &sent {
+            if UsbDerive::is_link_error(e) {
+                device.link_errors += 1;
+            }
+        }
+        sent
+    }
+
+    // Reopen the devices that dropped off the bus and leave out those that cannot go
+    // on with the job. False once none is left.
+    fn retain_active(&mut self, run: &mut JobRun) -> bool {
+        // A board that dropped off the bus fails every read, reopen it instead of spinning.
+        let lost: Vec<usize> = run
+            .active
+            .iter()
+            .copied()
+            .filter(|&index| self.devices[index].link_errors >= self.config.link_error_limit)
+            .collect();
+        // reopening resends the job along with hw params and opcode
+        for index in lost {
+            if !self.reconnect_device(index) {
+                run.active.retain(|&active| active != index);
+            }
+        }
+        let now = Instant::now();
+        self.check_pressure(now);
+        let devices = &self.devices;
+        run.active.retain(|&index| {
+            !devices[index].breaker.is_open(now) && !devices[index].in_bootloader
+        });
+        if run.active.is_empty() {
+            error!("Lost every usb derive, give up the job");
+            run.outcome = JobOutcome::Lost;
+            return false;
+        }
+        true
+    }
+
+    // The devices to read this turn, in turns on which goes first. `None` after
+    // backing off because there was nothing to read.
+    async fn poll_ready(&mut self, run: &mut JobRun<'_>) -> Option<Vec<usize>> {
+        let mut ready = vec![];
+        for &index in &run.active {
+            if self.input_ready(index) {
+                ready.push(index);
+            }
+        }
+        if let Some(backoff) = &mut run.idle_backoff {
+            if ready.is_empty() {
+                task::sleep(backoff.next_sleep()).await;
+                return None;
+            }
+            backoff.reset();
+        }
+        if ready.is_empty() {
+            // a blocking read would hold up the other devices and the stop signal
+            task::sleep(if self.under_pressure {
+                self.config.pressure_poll_interval
+            } else {
+                POLL_INTERVAL
+            })
+            .await;
+            return None;
+        }
+        // take turns on which device is read first
+        let first = run.turn % ready.len();
+        ready.rotate_left(first);
+        run.turn += 1;
+        Some(ready)
+    }
+
+    // Read one response of each of `ready`, up to the first solution to submit.
+    fn read_ready(&mut self, run: &mut JobRun, ready: Vec<usize>) -> Option<SealEvent> {
+        for index in ready {
+            let solved =
+                self.read_solution(index, run.job, &run.device_target, &mut run.aggregator);
+            if solved.is_some() {
+                return solved;
+            }
+        }
+        None
+    }
+
+    // Book the job once it is over: log it, remember it to spot a duplicate, and put
+    // the devices to sleep if configured or stopped.
+    fn finish_job(&mut self, run: JobRun) {
+        // a job given up keeps running until replaced, the next one does not queue behind it
+        if matches!(run.outcome, JobOutcome::Stopped | JobOutcome::Timeout | JobOutcome::Lost) {
+            for &index in &run.mined {
+                self.devices[index].job_in_flight = false;
+            }
+        }
+        if let Some(path) = &self.config.job_log {
+            let elapsed = run.started.elapsed();
+            let records: Vec<JobRecord> = run
+                .mined
+                .iter()
+                .map(|&index| JobRecord {
+                    started: run.started_at,
+                    difficulty: run.job.difficulty,
+                    target: run.target,
+                    job_id: run.job_ids[index],
+                    device: self.devices[index].derive.id(),
+                    outcome: match &run.outcome {
+                        JobOutcome::Solved { .. } if run.solved_by != Some(index) => {
+                            JobOutcome::Stopped
+                        }
+                        outcome => outcome.clone(),
+                    },
+                    elapsed,
+                    nonces: match &run.outcome {
+                        // a nonce below the start means the device wrapped around and
+                        // the range it searched is not known
+                        JobOutcome::Solved { nonce, .. } if run.solved_by == Some(index) => {
+                            let prefix = self.devices[index].nonce_prefix(run.job_ids[index]);
+                            let start = u64::from(prefix) << 32 | self.nonce_start(index);
+                            Some(NonceRange::up_to(start, prefix, *nonce))
+                                .filter(|range| range.end > range.start)
+                        }
+                        _ => None,
+                    },
+                })
+                .collect();
+            if let Err(e) = job_log::append(path, &records) {
+                warn!("Failed to log job: {:?}", e);
+            }
+        }
+        // a solved job is not searched again
+        let solved = matches!(run.outcome, JobOutcome::Solved { .. });
+        if let (true, Some(positions)) = (solved, &mut self.nonce_positions) {
+            if let Err(e) = positions.retain(&[]) {
+                warn!("Failed to save nonce positions: {:?}", e);
+            }
+        }
+        // a job moved to new nonce prefixes runs under other ids and headers
+        if run.round == 0 {
+            self.last_job = Some((run.fingerprint, run.job_ids));
+        }
+        // The codec has no command to cancel a job, sleep is what takes a stopped one
+        // off the devices that have the power mode command; the next job wakes them.
+        if self.config.sleep_between_jobs || run.stopped {
+            for device in &mut self.devices {
+                if !device.derive.config().power_mode {
+                    continue;
+                }
+                match device.derive.sleep() {
+                    Ok(()) => {
+                        device.asleep = true;
+                        device.job_running = false;
+                        device.job_in_flight = false;
+                    }
+                    Err(e) => warn!("Failed to put device to sleep: {:?}", e),
                 }
             }
         }
     }
 }
+
+impl Solver for UsbSolver {
+    fn solve(
+        &mut self,
+        event: MintBlockEvent,
+        nonce_tx: UnboundedSender<SealEvent>,
+        stop_rx: UnboundedReceiver<bool>,
+    ) {
+        if let Err(e) = block_on(self.solve_async(event, nonce_tx, stop_rx)) {
+            error!("Failed to solve mint job: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use starcoin_types::system_events::MintEventExtra;
+    use starcoin_types::HashValue;
+    use std::convert::TryInto;
+    use std::thread;
+    use usbderive::mock::MockPort;
+    use usbderive::{Message, SerialPortType};
+
+    const TYPE_SEND_WORK: u8 = 0xA1;
+
+    fn nonce_frame(job_id: u8, nonce: u32, hash: [u8; 32]) -> Vec<u8> {
+        let mut frame = vec![0u8; 56];
+        frame[..3].copy_from_slice(&[0xa5, 0x3c, 0x96]);
+        frame[3] = 0x51;
+        frame[4] = 0x10;
+        frame[5] = 50;
+        frame[9] = job_id;
+        frame[12..16].copy_from_slice(&nonce.to_le_bytes());
+        frame[21..53].copy_from_slice(&hash);
+        frame[53..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        frame
+    }
+
+    /// Seed the job ids of a single device solver, returns the ids it will issue.
+    fn seed_job_ids(solver: &mut UsbSolver) -> Vec<u8> {
+        use rand::SeedableRng;
+
+        solver.set_job_rng(StdRng::seed_from_u64(0));
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..4).map(|_| rng.gen_range(1..16)).collect()
+    }
+
+    // for callers that hand reports and stats on as json and the like
+    fn assert_serialize<T: serde::Serialize>(_: &T) {}
+
+    fn extra_event() -> MintBlockEvent {
+        MintBlockEvent {
+            extra: Some(MintEventExtra {
+                worker_id: "rig".to_string(),
+                job_id: "1".to_string(),
+                extra: BlockHeaderExtra::new([0u8; 4]),
+            }),
+            ..mint_event()
+        }
+    }
+
+    fn mint_event() -> MintBlockEvent {
+        MintBlockEvent {
+            parent_hash: HashValue::zero(),
+            strategy: ConsensusStrategy::CryptoNight,
+            minting_blob: vec![0u8; 76],
+            difficulty: U256::from(1000u64),
+            block_number: 1,
+            extra: None,
+        }
+    }
+
+    #[test]
+    fn test_identify_device() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+
+        assert!(solver.identify_device("B2", Duration::from_secs(5)).is_err());
+        assert!(port.written().is_empty());
+
+        solver.identify_device("A1", Duration::from_secs(5)).unwrap();
+        let written = port.written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][3], 0xA6);
+    }
+
+    #[test]
+    fn test_resend_interval() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.resend_interval = Some(Duration::from_millis(50));
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+
+        let started = Instant::now();
+        let handle = thread::spawn(move || solver.solve(mint_event(), nonce_tx, stop_rx));
+        let sent = || {
+            port
+                .written()
+                .iter()
+                .filter(|msg| msg[3] == TYPE_SEND_WORK)
+                .count()
+        };
+        while sent() < 3 {
+            thread::yield_now();
+        }
+        stop_tx.unbounded_send(true).unwrap();
+        handle.join().unwrap();
+
+        // every resend waits out the interval since the one before, polling meanwhile
+        let jobs = sent();
+        assert!(started.elapsed() >= Duration::from_millis(50) * (jobs as u32 - 1));
+        assert!(port.polls() > jobs * 10);
+    }
+
+    #[test]
+    fn test_idle_sleep_adapts() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.max_idle_sleep = Some(Duration::from_millis(20));
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+
+        let handle = thread::spawn(move || solver.solve(mint_event(), nonce_tx, stop_rx));
+        let polls = loop {
+            let polls = port.polls();
+            if port.written().iter().any(|msg| msg[3] == TYPE_SEND_WORK) {
+                break polls;
+            }
+            thread::yield_now();
+        };
+        while port.polls() == polls {
+            thread::yield_now();
+        }
+        let idle_reads = port.reads();
+        let idle_since = Instant::now();
+        while port.polls() < polls + 7 {
+            thread::yield_now();
+        }
+        // an idle device is polled, not read, with sleeps of at least 1, 2, 4, 8
+        // and 16ms between the polls
+        assert!(idle_since.elapsed() >= Duration::from_millis(31));
+        assert_eq!(port.reads(), idle_reads);
+
+        // a busy device is read back to back
+        let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+        for _ in 0..50 {
+            port.push_response(&ack);
+        }
+        while port.boxed().bytes_to_read().unwrap() > 0 {
+            thread::yield_now();
+        }
+        stop_tx.unbounded_send(true).unwrap();
+        handle.join().unwrap();
+        assert!(port.reads() >= idle_reads + 50);
+    }
+
+    #[test]
+    fn test_idle_sleep_without_pending_count() {
+        let port = MockPort::new();
+        port.hide_pending();
+        let config = Config {
+            max_idle_sleep: Some(Duration::from_millis(20)),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        // the idle check reads ahead where the port cannot count pending bytes
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("solution should be returned");
+        assert_eq!(seal.nonce, 0x1234);
+    }
+
+    #[test]
+    fn test_resource_pressure() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        solver.set_resource_check(|| ResourceUsage {
+            cpu: 0.99,
+            free_memory: 1 << 30,
+        });
+
+        let device = port;
+        let handle = thread::spawn(move || loop {
+            let written = device.written();
+            if let Some(job) = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK) {
+                let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+                device.push_response(&ack);
+                device.push_response(&nonce_frame(job[30], 0x1234, [0x11; 32]));
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        });
+        // mining goes on, the frames are not kept for diagnostics
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("the device should find the solution");
+        handle.join().unwrap();
+        assert_eq!(seal.nonce, 0x1234);
+        assert!(solver.under_pressure());
+        assert!(solver.devices[0].recent_frames.is_empty());
+    }
+
+    #[test]
+    fn test_diagnostic_report() {
+        let port = MockPort::new();
+        let config = Config {
+            error_log_command: true,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let mut aggregator = SolutionAggregator::new(None, usbderive::SubmitPolicy::First);
+        let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+        port.push_response(&ack);
+        assert!(solver
+            .read_solution(0, &mint_event().into(), &[0xff; 4], &mut aggregator)
+            .is_none());
+
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[10] = 8;
+        state[23] = 60;
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&state);
+        let mut errlog = vec![0xa5, 0x3c, 0x96, 0x5c, 0x10, 0x0d, 0x00, 0x00, 0x00, 0x01];
+        errlog.extend_from_slice(&7u16.to_le_bytes());
+        errlog.extend_from_slice(&120u32.to_le_bytes());
+        errlog.extend_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&errlog);
+
+        let report = solver.diagnostic_report();
+        assert_eq!(report.solver.unknown_responses, 0);
+        assert_eq!(report.devices.len(), 1);
+        let device = &report.devices[0];
+        assert_eq!(device.serial, "A1");
+        assert_eq!(device.metadata.port_name.as_deref(), Some("mock"));
+        assert_eq!(device.config.target_freq, 600);
+        assert_eq!(device.state.as_ref().map(|s| s.cores), Some(8));
+        assert_eq!(device.error_log.as_ref().map(|log| log.len()), Some(1));
+        assert!(device.clock_skew_ppm.is_none());
+        assert_eq!(device.recent_frames.len(), 1);
+        assert!(device.recent_frames[0].contains("Others"));
+        assert_serialize(&report);
+
+        // entries logged between reports time the device clock
+        for timestamp in &[130u32, 140] {
+            thread::sleep(Duration::from_millis(2));
+            port.push_response(&state);
+            let mut errlog = vec![0xa5, 0x3c, 0x96, 0x5c, 0x10, 0x13, 0x00, 0x00, 0x00, 0x02];
+            for entry in &[120u32, *timestamp] {
+                errlog.extend_from_slice(&7u16.to_le_bytes());
+                errlog.extend_from_slice(&entry.to_le_bytes());
+            }
+            errlog.extend_from_slice(&[0x69, 0xc3, 0x5a]);
+            port.push_response(&errlog);
+            solver.diagnostic_report();
+        }
+        let report = solver.diagnostic_report();
+        assert!(report.devices[0].clock_skew_ppm.is_some());
+    }
+
+    #[test]
+    fn test_diagnostic_report_stock_firmware() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+
+        // the error log query is not sent to firmware that does not define it
+        let report = solver.diagnostic_report();
+        assert!(report.devices[0].error_log.is_none());
+        assert_eq!(port.written(), vec![Message::get_state_msg()]);
+    }
+
+    struct MockJobSource {
+        jobs: Vec<Job>,
+    }
+
+    impl JobSource for MockJobSource {
+        fn next_job(&mut self) -> Option<Job> {
+            if self.jobs.is_empty() {
+                None
+            } else {
+                Some(self.jobs.remove(0))
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_job_source() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        let jobs: Vec<Job> = (1..=3u8)
+            .map(|height| Job {
+                minting_blob: vec![height; 76],
+                difficulty: U256::from(1000u64),
+                extra: None,
+                block_number: u64::from(height),
+            })
+            .collect();
+        let mut source = MockJobSource { jobs: jobs.clone() };
+        port.push_response(&nonce_frame(job_ids[0], 0x11, [0x11; 32]));
+        port.push_response(&nonce_frame(job_ids[1], 0x22, [0x22; 32]));
+        let (nonce_tx, mut nonce_rx) = mpsc::unbounded();
+
+        // the third job finds nothing and is given up at the timeout
+        solver.run(&mut source, nonce_tx, Some(Duration::from_millis(20)));
+
+        let sent: Vec<Vec<u8>> = port
+            .written()
+            .into_iter()
+            .filter(|msg| msg[3] == TYPE_SEND_WORK)
+            .collect();
+        assert_eq!(sent.len(), 3);
+        let first = nonce_rx.try_next().unwrap().unwrap();
+        assert_eq!(first.nonce, 0x11);
+        assert_eq!(first.minting_blob, jobs[0].minting_blob);
+        let second = nonce_rx.try_next().unwrap().unwrap();
+        assert_eq!(second.nonce, 0x22);
+        assert_eq!(second.minting_blob, jobs[1].minting_blob);
+        assert!(nonce_rx.try_next().unwrap().is_none());
+    }
+
+    // The header of a CryptoNight block, its nonce left zero.
+    const INPUT_DATA: [u8; 76] = [
+        0x05, 0x05, 0xc0, 0xa7, 0xdb, 0xc7, 0x05, 0xb0, 0xad, 0xf8, 0x2c, 0x58, 0x1a, 0xae, 0xe4,
+        0x8b, 0x2e, 0x0a, 0xee, 0x2e, 0xa8, 0x97, 0x2d, 0xd7, 0x9d, 0xba, 0xf3, 0xca, 0x28, 0xac,
+        0xca, 0x5f, 0x73, 0xca, 0x2a, 0x90, 0x9c, 0x8c, 0x24, 0xf7, 0x09, 0x00, 0x80, 0xf9, 0x87,
+        0x13, 0xc6, 0x91, 0x9a, 0x42, 0x38, 0x9d, 0x53, 0xcb, 0xde, 0xd0, 0x4d, 0x02, 0x6c, 0x1d,
+        0xe4, 0x25, 0xf8, 0x77, 0xe8, 0x70, 0xb3, 0x8f, 0x91, 0x4c, 0xef, 0x40, 0xc6, 0x7f, 0xa4,
+        0x00,
+    ];
+
+    fn input_seal(nonce: u32) -> SealEvent {
+        SealEvent {
+            minting_blob: INPUT_DATA.to_vec(),
+            nonce,
+            extra: None,
+            hash_result: hex::encode([0u8; 32]),
+        }
+    }
+
+    #[test]
+    fn test_verify_seal() {
+        // every hash meets the target of difficulty 1
+        assert!(verify_seal(&input_seal(0x1234_5678), U256::from(1u64)));
+        // the target of the highest difficulty is 1, no hash meets it
+        assert!(!verify_seal(&input_seal(0x8765_4321), U256::max_value()));
+    }
+
+    #[test]
+    fn test_zero_solution_rejected() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        port.push_response(&nonce_frame(job_ids[0], 0, [0; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(50)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert_eq!(solver.firmware_bugs(), 1);
+
+        // a verifier can vouch for a genuine zero nonce
+        solver.set_verifier(|seal| seal.hash_result != hex::encode([0u8; 32]));
+        port.push_response(&nonce_frame(job_ids[1], 0, [0; 32]));
+        port.push_response(&nonce_frame(job_ids[1], 0, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(50)))
+            .unwrap()
+            .expect("verified solution should be submitted");
+        assert_eq!(seal.nonce, 0);
+        assert_eq!(solver.firmware_bugs(), 2);
+
+        // 2^32 is a zero target at 32 bits but not at 64, the zero hash is bogus there
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            target_resolution: TargetResolution::Bits64,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        let event = MintBlockEvent {
+            difficulty: U256::from(0x1_0000_0000u64),
+            ..mint_event()
+        };
+        port.push_response(&nonce_frame(job_ids[0], 0, [0; 32]));
+        let seal = block_on(solver.next_solution(event, Duration::from_millis(50)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert_eq!(solver.firmware_bugs(), 1);
+    }
+
+    #[test]
+    fn test_implausible_solution_rate() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            // may claim 0.1 * 600 * 10 = 600 hashes in the window
+            nominal_hashrate: Some(0.1),
+            verify_implausible: true,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        solver.set_verifier(|seal| seal.nonce != 0x1234);
+        // five solutions at difficulty 1000 in a row
+        for nonce in 1..=5 {
+            let job_ids = seed_job_ids(&mut solver);
+            port.push_response(&nonce_frame(job_ids[0], nonce, [0x11; 32]));
+            let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(50)))
+                .unwrap();
+            assert_eq!(seal.map(|seal| seal.nonce), Some(nonce));
+        }
+        assert!(solver.devices[0].verify_all);
+
+        // from now on a solution the verifier refuses is dropped
+        let job_ids = seed_job_ids(&mut solver);
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(50)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert_eq!(solver.firmware_bugs(), 1);
+    }
+
+    #[test]
+    fn test_adapted_ack_timeouts() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let config = Config {
+            latency_timeout_factor: Some(4.0),
+            ..Config::default()
+        };
+        let derives = ports
+            .iter()
+            .map(|port| UsbDerive::from_port(port.boxed(), None, config.clone()))
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+        ports[0].set_read_delay(Duration::from_millis(2));
+        ports[1].set_read_delay(Duration::from_millis(50));
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        for port in &ports {
+            port.push_response(&state);
+        }
+        solver.adapt_ack_timeouts();
+
+        let configs = solver.device_configs();
+        let timeouts: Vec<Duration> = configs
+            .iter()
+            .map(|(_, config)| config.ack_timeout.unwrap())
+            .collect();
+        assert!(timeouts[0] < Duration::from_millis(100), "{:?}", timeouts);
+        assert!(timeouts[1] >= Duration::from_millis(200), "{:?}", timeouts);
+        // the solve loop keeps waiting on solutions as long as before
+        for (port, (_, config)) in ports.iter().zip(&configs) {
+            assert_eq!(config.read_timeout, Config::default().read_timeout);
+            assert_eq!(port.boxed().timeout(), config.read_timeout);
+        }
+
+        // command replies are waited for as long as adapted
+        ports[1].push_response(&state);
+        solver.devices[1].derive.get_state().unwrap();
+        assert_eq!(ports[1].read_timeouts().last(), Some(&timeouts[1]));
+    }
+
+    #[test]
+    fn test_job_setup_retry() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        port.fail_writes(1);
+        let (device, job_id) = (port.clone(), job_ids[0]);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            device.push_response(&nonce_frame(job_id, 0x1234, [0x11; 32]));
+        });
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(200)))
+            .unwrap()
+            .expect("solve should go on after the retry");
+        handle.join().unwrap();
+        assert_eq!(seal.nonce, 0x1234);
+
+        // the first try and both retries fail
+        port.fail_writes(3);
+        assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_sleep_between_jobs() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        config.power_mode = true;
+        config.sleep_between_jobs = true;
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .expect("solution should be returned");
+        assert_eq!(port.written().last().unwrap(), &Message::sleep_msg());
+
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(10)))
+            .unwrap();
+        let written = port.written();
+        let wake = written.iter().position(|msg| msg == &Message::wake_msg());
+        let second_job = written.iter().rposition(|msg| msg[3] == TYPE_SEND_WORK);
+        assert!(wake.is_some());
+        assert!(wake < second_job);
+        assert_eq!(written.last().unwrap(), &Message::sleep_msg());
+    }
+
+    #[test]
+    fn test_stop_quiesces_devices() {
+        // stock firmware has no power mode command, nothing is sent on a stop
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+        stop_tx.unbounded_send(true).unwrap();
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+        assert!(!port.written().contains(&Message::sleep_msg()));
+
+        let port = MockPort::new();
+        let config = Config {
+            power_mode: true,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+        stop_tx.unbounded_send(true).unwrap();
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+        assert_eq!(port.written().last().unwrap(), &Message::sleep_msg());
+
+        let clone = solver.clone();
+        solver.shutdown();
+        assert_eq!(port.written().last().unwrap(), &Message::reboot_msg());
+        // the device is idle, dropping the other handles sends nothing more
+        let written = port.written().len();
+        drop(clone);
+        assert_eq!(port.written().len(), written);
+    }
+
+    #[test]
+    fn test_stop_grace() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            power_mode: true,
+            stop_grace: Duration::from_millis(300),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let (nonce_tx, mut nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+        stop_tx.unbounded_send(true).unwrap();
+
+        // the solution arrives after the stop was taken: past the job upload the
+        // stop is the first thing checked, input polled after it is the grace window
+        let device = port.clone();
+        let handle = thread::spawn(move || {
+            let (job_id, polls) = loop {
+                let polls = device.polls();
+                let written = device.written();
+                if let Some(job) = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK) {
+                    break (job[30], polls);
+                }
+                thread::yield_now();
+            };
+            while device.polls() == polls {
+                thread::yield_now();
+            }
+            device.push_response(&nonce_frame(job_id, 0x1234, [0x11; 32]));
+        });
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+        handle.join().unwrap();
+        let seal = nonce_rx.try_next().unwrap().expect("solution should be submitted");
+        assert_eq!(seal.nonce, 0x1234);
+        assert_eq!(port.written().last().unwrap(), &Message::sleep_msg());
+    }
+
+    #[test]
+    fn test_solve_async() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        let (nonce_tx, mut nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+
+        block_on(async {
+            let solving = solver.solve_async(mint_event(), nonce_tx, stop_rx);
+            let stopping = async {
+                let seal = nonce_rx.next().await.expect("solution should be sent");
+                // the solver may be done with the job already
+                let _ = stop_tx.unbounded_send(true);
+                seal
+            };
+            let (solved, seal) = futures::join!(solving, stopping);
+            assert_eq!(seal.nonce, 0x1234);
+            solved.unwrap();
+        });
+        assert_eq!(solver.stats().framing_errors, 0);
+    }
+
+    #[test]
+    fn test_solve_async_stops_while_waiting() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_secs(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+
+        // the device never answers, the stop is not held up behind a read
+        let started = Instant::now();
+        block_on(async {
+            let solving = solver.solve_async(mint_event(), nonce_tx, stop_rx);
+            let stopping = async {
+                task::sleep(Duration::from_millis(20)).await;
+                stop_tx.unbounded_send(true).unwrap();
+            };
+            let (solved, _) = futures::join!(solving, stopping);
+            solved.unwrap();
+        });
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(port.reads(), 0);
+    }
+
+    #[test]
+    fn test_latency_stages() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        // only read once a frame is pending, so the link time is told apart
+        config.max_idle_sleep = Some(Duration::from_millis(1));
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        solver.set_submit_hook(|_| thread::sleep(Duration::from_millis(30)));
+        port.set_read_delay(Duration::from_millis(20));
+
+        let job_id = job_ids[0];
+        let handle = thread::spawn(move || {
+            while !port.written().iter().any(|msg| msg[3] == TYPE_SEND_WORK) {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(60));
+            port.push_response(&nonce_frame(job_id, 0x1234, [0x11; 32]));
+        });
+        let started = Instant::now();
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("solution should be returned");
+        let elapsed = started.elapsed();
+        handle.join().unwrap();
+
+        let latency = solver.latency();
+        assert_eq!(latency.count(), 1);
+        let timings = latency.last().unwrap();
+        assert!(timings.device >= Duration::from_millis(40), "{:?}", timings);
+        assert!(timings.read >= Duration::from_millis(20), "{:?}", timings);
+        assert!(timings.submit >= Duration::from_millis(30), "{:?}", timings);
+        // the stages do not overlap
+        let total = timings.device + timings.read + timings.submit;
+        assert!(total <= elapsed, "{:?} in {:?}", timings, elapsed);
+    }
+
+    #[test]
+    fn test_unsupported_blob_version() {
+        let port = MockPort::new();
+        let config = Config {
+            blob_versions: Some(vec![0x05]),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let err = block_on(solver.next_solution(mint_event(), Duration::from_millis(10)))
+            .unwrap_err();
+        assert!(err.to_string().contains("Unsupported minting blob version"), "{}", err);
+        assert!(port.written().is_empty());
+    }
+
+    #[test]
+    fn test_long_blob_sends_header_only() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+        let mut event = mint_event();
+        event.minting_blob = (0..100).collect();
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+        stop_tx.unbounded_send(true).unwrap();
+        solver.solve(event.clone(), nonce_tx, stop_rx);
+
+        let written = port.written();
+        let job = written
+            .iter()
+            .find(|msg| msg[3] == TYPE_SEND_WORK)
+            .expect("job should be sent");
+        // frame header and job fields ahead of the data, PKT_ENDER after it
+        let data = &job[31..job.len() - 3];
+        let mut expect = event.minting_blob[..76].to_vec();
+        expect[35..39].copy_from_slice(&[0u8; 4]);
+        assert_eq!(data, expect.as_slice());
+    }
+
+    #[test]
+    fn test_next_solution() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(10);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(200)))
+            .unwrap()
+            .expect("solution should be returned");
+        assert_eq!(seal.nonce, 0x1234);
+        assert_eq!(seal.hash_result, hex::encode([0x11u8; 32]));
+
+        let start = Instant::now();
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_next_solution_awaits_input() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(10),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        // the frame comes from a future on the same executor as the solver
+        let seal = block_on(async {
+            let solving = solver.next_solution(mint_event(), Duration::from_secs(5));
+            let answering = async {
+                task::sleep(Duration::from_millis(20)).await;
+                port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+            };
+            futures::join!(solving, answering).0
+        });
+        assert_eq!(seal.unwrap().expect("solution should be returned").nonce, 0x1234);
+    }
+
+    #[test]
+    fn test_raw_solutions() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+        assert!(solver.raw_solutions().is_err());
+
+        let mut config = Config::default();
+        config.raw_solutions = true;
+        config.read_timeout = Duration::from_millis(10);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        let mut raw_rx = solver.raw_solutions().unwrap();
+
+        let frame = nonce_frame(job_ids[0], 0x1234, [0x11; 32]);
+        port.push_response(&frame);
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(200)))
+            .unwrap()
+            .expect("solution should be returned");
+        let raw = raw_rx.try_next().unwrap().unwrap();
+        assert_eq!(raw.nonce, seal.nonce);
+        assert_eq!(raw.frame, frame);
+    }
+
+    #[test]
+    fn test_target_rounding() {
+        let cases: [(u64, [u32; 3]); 3] = [
+            (1, [0xffff_ffff, 0xffff_fffe, 0xffff_ffff]),
+            (3, [0x5555_5555, 0x5555_5554, 0x5555_5554]),
+            (7, [0x2492_4924, 0x2492_4923, 0x2492_4924]),
+        ];
+        for (difficulty, expect) in cases.iter() {
+            let modes = [
+                TargetRounding::Truncate,
+                TargetRounding::Harder,
+                TargetRounding::Nearest,
+            ];
+            for (mode, target) in modes.iter().zip(expect.iter()) {
+                assert_eq!(
+                    UsbSolver::difficulty_to_target_u32(U256::from(*difficulty), *mode),
+                    *target,
+                    "difficulty {} rounding {:?}",
+                    difficulty,
+                    mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_target_resolution() {
+        let difficulty = U256::from(1000u64);
+        let truncated = |resolution| {
+            UsbSolver::difficulty_to_target(difficulty, TargetRounding::Truncate, resolution)
+        };
+        assert_eq!(truncated(TargetResolution::Bits32), vec![0x00, 0x41, 0x89, 0x37]);
+        assert_eq!(
+            truncated(TargetResolution::Bits64),
+            vec![0x00, 0x41, 0x89, 0x37, 0x4b, 0xc6, 0xa7, 0xef]
+        );
+        let mut full = [0u8; 32];
+        (U256::max_value() / difficulty).to_big_endian(&mut full);
+        assert_eq!(truncated(TargetResolution::Bits256), full.to_vec());
+
+        // rounding applies to the last byte kept, the borrow carries
+        let harder = UsbSolver::difficulty_to_target(
+            U256::from(0x100u64),
+            TargetRounding::Harder,
+            TargetResolution::Bits64,
+        );
+        assert_eq!(harder, vec![0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+        // nothing is cut off a full target
+        let exact = UsbSolver::difficulty_to_target(
+            difficulty,
+            TargetRounding::Harder,
+            TargetResolution::Bits256,
+        );
+        assert_eq!(exact, full.to_vec());
+        // nor rounded down if all that is cut off is zero
+        let mut round = [0u8; 32];
+        round[..4].copy_from_slice(&[0x00, 0x41, 0x89, 0x37]);
+        let kept = UsbSolver::round_target(&round, TargetRounding::Harder, 4);
+        assert_eq!(kept, vec![0x00, 0x41, 0x89, 0x37]);
+        round[31] = 1;
+        let kept = UsbSolver::round_target(&round, TargetRounding::Harder, 4);
+        assert_eq!(kept, vec![0x00, 0x41, 0x89, 0x36]);
+
+        let port = MockPort::new();
+        let config = Config {
+            target_resolution: TargetResolution::Bits64,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let (nonce_tx, _nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+        stop_tx.unbounded_send(true).unwrap();
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+        let written = port.written();
+        let job = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK).unwrap();
+        assert_eq!(&job[9..17], &[0xef, 0xa7, 0xc6, 0x4b, 0x37, 0x89, 0x41, 0x00]);
+    }
+
+    #[test]
+    fn test_target_known_pairs() {
+        let cases: [(u64, u32); 10] = [
+            (0, 0xffff_ffff),
+            (1, 0xffff_ffff),
+            (2, 0x7fff_ffff),
+            (0x100, 0x00ff_ffff),
+            (1000, 0x0041_8937),
+            (0x1_0000, 0x0000_ffff),
+            (0x100_0000, 0x0000_00ff),
+            (0xffff_ffff, 0x0000_0001),
+            (0x1_0000_0000, 0),
+            (0x100_0000_0000, 0),
+        ];
+        for (difficulty, target) in cases.iter() {
+            let difficulty_u256 = U256::from(*difficulty);
+            assert_eq!(
+                UsbSolver::difficulty_to_target_u32(difficulty_u256, TargetRounding::Truncate),
+                *target,
+                "difficulty {:#x}",
+                difficulty
+            );
+        }
+    }
+
+    #[test]
+    fn test_retune() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let derives = ports
+            .iter()
+            .map(|port| UsbDerive::from_port(port.boxed(), None, Config::default()))
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, Config::default());
+
+        solver.retune(500, 700).unwrap();
+        for port in &ports {
+            assert_eq!(port.written(), vec![Message::set_hw_params_msg(500, 700)]);
+        }
+        let tuned = |solver: &UsbSolver| {
+            solver
+                .device_configs()
+                .iter()
+                .all(|(_, config)| (config.target_freq, config.target_voltage) == (500, 700))
+        };
+        assert!(tuned(&solver));
+
+        assert!(solver.retune(500, 2000).is_err());
+        assert!(tuned(&solver));
+        assert_eq!(ports[0].written().len(), 1);
+    }
+
+    #[test]
+    fn test_device_configs() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+
+        solver.devices[0].derive.set_freq_voltage(650, 800).unwrap();
+        let configs = solver.device_configs();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].0, "A1");
+        assert_eq!(configs[0].1.target_freq, 650);
+        assert_eq!(configs[0].1.target_voltage, 800);
+    }
+
+    #[test]
+    fn test_overheat_alert_from_solve() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(10);
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let mut alerts_rx = solver.alerts();
+
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[10] = 8;
+        state[11] = 8;
+        state[23] = 95;
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&state);
+        assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .is_none());
+
+        let alert = alerts_rx.try_next().unwrap().unwrap();
+        assert_eq!(alert.serial, "A1");
+        assert!(alert.message.contains("Overheat"));
+    }
+
+    #[test]
+    fn test_share_report_follows_frequency() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), Config::default());
+        let mut solver = UsbSolver::from_derive(derive, Config::default());
+        // no solution yet, nothing to book it on
+        solver.record_share(true);
+        assert!(solver.share_report().is_empty());
+
+        solver.solved_by = Some(0);
+        solver.record_share(true);
+        solver.devices[0].derive.set_freq_voltage(700, 800).unwrap();
+        solver.record_share(false);
+
+        let report = solver.share_report();
+        assert_eq!(report.len(), 1);
+        let freqs: Vec<(u16, u64, u64)> = report[0]
+            .1
+            .iter()
+            .map(|s| (s.freq, s.accepted, s.rejected))
+            .collect();
+        assert_eq!(freqs, vec![(600, 1, 0), (700, 0, 1)]);
+    }
+
+    #[test]
+    fn test_share_callback() {
+        let derives = ["A1", "B2"]
+            .iter()
+            .map(|serial| {
+                let port = MockPort::new().boxed();
+                UsbDerive::from_port(port, Some(serial.to_string()), Config::default())
+            })
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, Config::default());
+        let shares = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = shares.clone();
+        solver.set_share_callback(move |serial, accepted| {
+            recorded.lock().unwrap().push((serial.to_string(), accepted))
+        });
+
+        solver.solved_by = Some(0);
+        solver.record_share(true);
+        solver.record_share(false);
+        solver.solved_by = Some(1);
+        solver.record_share(true);
+        assert_eq!(
+            *shares.lock().unwrap(),
+            vec![
+                ("A1".to_string(), true),
+                ("A1".to_string(), false),
+                ("B2".to_string(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_submit_hook() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(10);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        solver.set_submit_hook(|seal| seal.hash_result = format!("worker1:{}", seal.hash_result));
+
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(200)))
+            .unwrap()
+            .expect("solution should be returned");
+        assert_eq!(seal.nonce, 0x1234);
+        assert_eq!(
+            seal.hash_result,
+            format!("worker1:{}", hex::encode([0x11u8; 32]))
+        );
+    }
+
+    #[test]
+    fn test_solution_ready_with_stop_is_submitted() {
+        for window in [None, Some(Duration::from_secs(10))].iter() {
+            let port = MockPort::new();
+            let mut config = Config::default();
+            config.read_timeout = Duration::from_millis(10);
+            config.submit_window = *window;
+            let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+            let mut solver = UsbSolver::from_derive(derive, config);
+            let job_ids = seed_job_ids(&mut solver);
+            let (nonce_tx, mut nonce_rx) = mpsc::unbounded();
+            let (stop_tx, stop_rx) = mpsc::unbounded();
+
+            port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+            stop_tx.unbounded_send(true).unwrap();
+            solver.solve(mint_event(), nonce_tx, stop_rx);
+
+            let seal = nonce_rx.try_next().unwrap().expect("solution should be submitted");
+            assert_eq!(seal.nonce, 0x1234);
+        }
+    }
+
+    #[test]
+    fn test_verify_target() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.verify_target = true;
+        config.job_setup_retries = 0;
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let mut frame = vec![0xa5, 0x3c, 0x96, 0x5e, 0x10, 0x0b, 0x00, 0x00, 0x00, 0x00];
+        frame.extend_from_slice(&0x1234u32.to_le_bytes());
+        frame.extend_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&frame);
+
+        let err = block_on(solver.next_solution(mint_event(), Duration::from_millis(10)))
+            .unwrap_err();
+        assert!(err.to_string().contains("target 0x1234"));
+    }
+
+    #[test]
+    fn test_job_queueing() {
+        for queueing in [false, true].iter() {
+            let ports = vec![MockPort::new(), MockPort::new()];
+            ports[0].set_name("board-a");
+            ports[1].set_name("board-b");
+            let mut config = Config::default();
+            config.read_timeout = Duration::from_millis(10);
+            config.job_queueing = *queueing;
+            let derives = ports
+                .iter()
+                .map(|port| UsbDerive::from_port(port.boxed(), None, config.clone()))
+                .collect();
+            let mut solver = UsbSolver::from_derives(derives, config);
+            let job_ids = seed_job_ids(&mut solver);
+
+            // the second device solves, the first is still running the job when the
+            // next arrives
+            ports[1].push_response(&nonce_frame(job_ids[1], 0x1234, [0x11; 32]));
+            assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+                .unwrap()
+                .is_some());
+            // a job that timed out is given up, the next one replaces it
+            for _ in 0..2 {
+                assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(20)))
+                    .unwrap()
+                    .is_none());
+            }
+            let job_nums = |port: &MockPort| -> Vec<u8> {
+                port.written()
+                    .iter()
+                    .filter(|msg| msg[3] == TYPE_SEND_WORK)
+                    .map(|msg| msg[29])
+                    .collect()
+            };
+            let expect = if *queueing { vec![1, 2, 1] } else { vec![1, 1, 1] };
+            assert_eq!(job_nums(&ports[0]), expect);
+            assert_eq!(job_nums(&ports[1]), vec![1, 1, 1]);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_job_kept() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(10),
+            keep_duplicate_jobs: true,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let uploads = |port: &MockPort| {
+            let written = port.written();
+            written.iter().filter(|msg| msg[3] == TYPE_SEND_WORK).count()
+        };
+
+        for _ in 0..2 {
+            assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(20)))
+                .unwrap()
+                .is_none());
+        }
+        assert_eq!(uploads(&port), 1);
+
+        let mut event = mint_event();
+        event.difficulty = 2000.into();
+        assert!(block_on(solver.next_solution(event, Duration::from_millis(20)))
+            .unwrap()
+            .is_none());
+        assert_eq!(uploads(&port), 2);
+    }
+
+    #[test]
+    fn test_stale_height_dropped() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(10);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        // a clone handed to the node client moves the tip for the mining solver too
+        let client = solver.clone();
+        client.set_current_tip(5);
+
+        let mut event = mint_event();
+        event.block_number = 3;
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        assert!(block_on(solver.next_solution(event.clone(), Duration::from_millis(100)))
+            .unwrap()
+            .is_none());
+
+        event.block_number = 6;
+        // the tip never moves back
+        client.set_current_tip(4);
+        port.push_response(&nonce_frame(job_ids[1], 0x1234, [0x11; 32]));
+        assert!(block_on(solver.next_solution(event, Duration::from_millis(100)))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_unknown_response_policy() {
+        let unknown = [0xa5, 0x3c, 0x96, 0x5f, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+        for policy in [UnknownResponse::Count, UnknownResponse::Alert(2)].iter() {
+            let port = MockPort::new();
+            let mut config = Config::default();
+            config.read_timeout = Duration::from_millis(10);
+            config.unknown_response = *policy;
+            let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+            let mut solver = UsbSolver::from_derive(derive, config);
+            let mut alerts_rx = solver.alerts();
+
+            for _ in 0..3 {
+                port.push_response(&unknown);
+            }
+            assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+                .unwrap()
+                .is_none());
+            assert_eq!(solver.unknown_responses(), 3);
+
+            let alert = alerts_rx.try_next().ok().flatten();
+            match policy {
+                UnknownResponse::Count => assert!(alert.is_none()),
+                UnknownResponse::Alert(_) => {
+                    assert!(alert.unwrap().message.contains("2 responses of unknown type"));
+                    // repeated alerts are debounced
+                    assert!(alerts_rx.try_next().is_err());
+                }
+            }
+        }
+
+        // op acks are known, and the threshold is per device
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let config = Config {
+            read_timeout: Duration::from_millis(10),
+            unknown_response: UnknownResponse::Alert(2),
+            ..Config::default()
+        };
+        let derives = ports
+            .iter()
+            .map(|port| UsbDerive::from_port(port.boxed(), None, config.clone()))
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+        let mut alerts_rx = solver.alerts();
+        let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+        for port in &ports {
+            port.push_response(&ack);
+            port.push_response(&unknown);
+        }
+        assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .is_none());
+        assert_eq!(solver.unknown_responses(), 2);
+        assert!(alerts_rx.try_next().ok().flatten().is_none());
+    }
+
+    #[test]
+    fn test_solution_sinks() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(10);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        let (backup_tx, mut backup_rx) = mpsc::unbounded();
+        solver.add_sink(backup_tx);
+
+        port.push_response(&nonce_frame(job_ids[0], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .expect("primary should receive the solution");
+        assert_eq!(backup_rx.try_next().unwrap().unwrap().nonce, seal.nonce);
+
+        // the primary is gone, the backup still gets solutions
+        let (nonce_tx, nonce_rx) = mpsc::unbounded();
+        drop(nonce_rx);
+        let (_stop_tx, stop_rx) = mpsc::unbounded();
+        port.push_response(&nonce_frame(job_ids[1], 0x5678, [0x22; 32]));
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+        assert_eq!(backup_rx.try_next().unwrap().unwrap().nonce, 0x5678);
+
+        // with every sink closed solve returns without a stop
+        drop(backup_rx);
+        let (nonce_tx, nonce_rx) = mpsc::unbounded();
+        drop(nonce_rx);
+        let (_stop_tx, stop_rx) = mpsc::unbounded();
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+    }
+
+    #[test]
+    fn test_voltage_deviation_lowers_frequency() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(10);
+        config.command_timeouts.set_hw_params = Duration::from_millis(10);
+        config.voltage_freq_step = Some(25);
+        config.freq_limits = (560, 1000);
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let mut alerts_rx = solver.alerts();
+
+        let state = |voltage: u16| {
+            let mut state = vec![0u8; 29];
+            state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+            state[15..17].copy_from_slice(&voltage.to_le_bytes());
+            state[23] = 60;
+            state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+            state
+        };
+        let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+        // one step for a run of off-voltage states
+        port.push_response(&state(650));
+        port.push_response(&ack);
+        port.push_response(&state(650));
+        assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .is_none());
+
+        assert!(alerts_rx.try_next().unwrap().unwrap().message.starts_with("Under-voltage"));
+        assert_eq!(solver.devices[0].derive.config().target_freq, 575);
+        assert_eq!(port.written().last().unwrap(), &Message::set_hw_params_msg(575, 750));
+        let hw_params = |port: &MockPort| {
+            let steps = [575, 560, 550].iter().map(|&freq| Message::set_hw_params_msg(freq, 750));
+            let steps: Vec<Vec<u8>> = steps.collect();
+            port.written().iter().filter(|msg| steps.contains(msg)).count()
+        };
+        assert_eq!(hw_params(&port), 1);
+
+        // the next run steps again, no lower than the frequency limit
+        port.push_response(&state(750));
+        port.push_response(&state(650));
+        port.push_response(&ack);
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(solver.devices[0].derive.config().target_freq, 560);
+        assert_eq!(hw_params(&port), 2);
+
+        // no reading is not off target
+        port.push_response(&state(750));
+        port.push_response(&state(0));
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(hw_params(&port), 2);
+    }
+
+    #[test]
+    fn test_seeded_job_ids() {
+        use rand::SeedableRng;
+
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        solver.set_job_rng(StdRng::seed_from_u64(7));
+        for _ in 0..5 {
+            block_on(solver.next_solution(mint_event(), Duration::from_millis(5)))
+                .unwrap();
+        }
+
+        let job_ids: Vec<u8> = port
+            .written()
+            .iter()
+            .filter(|msg| msg[3] == TYPE_SEND_WORK)
+            .map(|msg| msg[30])
+            .collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        let expect: Vec<u8> = (0..5).map(|_| rng.gen_range(1..16)).collect();
+        assert_eq!(job_ids, expect);
+        assert!(job_ids.iter().all(|id| (1..16).contains(id)));
+    }
+
+    #[test]
+    fn test_multiple_devices() {
+        let ports = vec![MockPort::new(), MockPort::new(), MockPort::new()];
+        let mut config = Config::default();
+        config.read_timeout = Duration::from_millis(5);
+        let derives = ports
+            .iter()
+            .map(|port| UsbDerive::from_port(port.boxed(), None, config.clone()))
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        // the third device fails the setup and all retries, the others mine on
+        ports[2].fail_writes(3);
+        ports[1].push_response(&nonce_frame(job_ids[1], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("the second device should find the solution");
+        assert_eq!(seal.nonce, 0x1234);
+
+        let job_id = |port: &MockPort| {
+            port
+                .written()
+                .iter()
+                .find(|msg| msg[3] == TYPE_SEND_WORK)
+                .map(|msg| msg[30])
+        };
+        let (first, second) = (job_id(&ports[0]).unwrap(), job_id(&ports[1]).unwrap());
+        assert_ne!(first, second);
+        assert_eq!(job_id(&ports[2]), None);
+        // each device searches its own slice of the nonce space
+        let start_nonce = |port: &MockPort| {
+            let written = port.written();
+            let job = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK).unwrap();
+            u64::from_le_bytes(job[13..21].try_into().unwrap())
+        };
+        assert_eq!(start_nonce(&ports[0]), 0);
+        assert_eq!(start_nonce(&ports[1]), (1u64 << 32) / 3);
+
+        // a stop ends the job on all devices at once
+        let (nonce_tx, mut nonce_rx) = mpsc::unbounded();
+        let (stop_tx, stop_rx) = mpsc::unbounded();
+        stop_tx.unbounded_send(true).unwrap();
+        let started = Instant::now();
+        solver.solve(mint_event(), nonce_tx, stop_rx);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert!(nonce_rx.try_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ports_without_pending_count() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            link_error_limit: 3,
+            reconnect_attempts: 1,
+            ..Config::default()
+        };
+        let derives = ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| {
+                port.set_name(&format!("board-{}", index));
+                port.hide_pending();
+                UsbDerive::from_port(port.boxed(), None, config.clone())
+            })
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+        let job_ids = seed_job_ids(&mut solver);
+
+        ports[1].push_response(&nonce_frame(job_ids[1], 0x1234, [0x11; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("a timed read should find the solution");
+        assert_eq!(seal.nonce, 0x1234);
+        assert!(ports[0].read_timeouts().contains(&INPUT_POLL_TIMEOUT));
+
+        // failed polls are link errors, the devices are lost instead of polled forever
+        let reads: Vec<usize> = ports.iter().map(MockPort::reads).collect();
+        for port in &ports {
+            port.fail_reads(usize::MAX);
+        }
+        let started = Instant::now();
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_secs(5)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        for (port, reads) in ports.iter().zip(reads) {
+            assert_eq!(port.reads() - reads, 3);
+        }
+    }
+
+    #[test]
+    fn test_device_metadata() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derives = ports
+            .iter()
+            .zip(["A1", "B2"].iter())
+            .map(|(port, serial)| {
+                UsbDerive::from_port(port.boxed(), Some(serial.to_string()), config.clone())
+            })
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+        let mut metadata = SubmitMetadata {
+            worker: Some("rig.left".to_string()),
+            ..SubmitMetadata::default()
+        };
+        metadata.tags.insert("tag".to_string(), "t1".to_string());
+        solver.set_device_metadata("A1", metadata.clone());
+        let received = Arc::new(std::sync::Mutex::new(vec![]));
+        let hooked = received.clone();
+        solver.set_metadata_hook(move |seal, metadata| {
+            hooked.lock().unwrap().push((seal.nonce, metadata.clone()));
+        });
+
+        let mut solve_on = |index: usize, nonce: u32| {
+            // the same ids again for every job
+            let job_ids = seed_job_ids(&mut solver);
+            ports[index].push_response(&nonce_frame(job_ids[index], nonce, [0x11; 32]));
+            block_on(solver.next_solution(extra_event(), Duration::from_millis(500)))
+                .unwrap()
+                .expect("solution should be returned")
+        };
+        let tagged = solve_on(0, 0x1234);
+        assert_eq!(tagged.extra.unwrap().worker_id, "rig.left");
+
+        let untagged = solve_on(1, 0x5678);
+        assert_eq!(untagged.extra.unwrap().worker_id, "rig");
+        assert_eq!(*received.lock().unwrap(), vec![(0x1234, metadata)]);
+    }
+
+    #[test]
+    fn test_refresh_devices() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let derives = vec![
+            UsbDerive::from_port(ports[0].boxed(), Some("A1".to_string()), Config::default()),
+            UsbDerive::from_port(ports[1].boxed(), Some("B2".to_string()), Config::default()),
+        ];
+        let config = Config {
+            hotplug_debounce: Duration::from_secs(0),
+            ..Config::default()
+        };
+        let mut solver = UsbSolver::from_derives(derives, config);
+        solver.devices[1].derive.set_freq_voltage(650, 800).unwrap();
+        let written = ports[1].written().len();
+
+        // A1 was unplugged and C3 plugged in
+        let added = MockPort::new();
+        let mut opened = vec![];
+        let ids = vec!["B2".to_string(), "C3".to_string()];
+        solver.swap_devices(&ids, |index| {
+            opened.push(index);
+            Ok(UsbDerive::from_port(added.boxed(), Some(ids[index].clone()), Config::default()))
+        });
+
+        assert_eq!(opened, vec![1]);
+        let serials: Vec<String> = solver
+            .device_configs()
+            .into_iter()
+            .map(|(serial, _)| serial)
+            .collect();
+        assert_eq!(serials, vec!["B2", "C3"]);
+        assert_eq!(solver.devices[0].derive.config().target_freq, 650);
+        assert_eq!(ports[1].written().len(), written);
+    }
+
+    #[test]
+    fn test_unresponsive_device_reopened() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        let derive_config = Config {
+            command_timeouts: usbderive::CommandTimeouts {
+                ping: Duration::from_millis(5),
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let derives = vec![
+            UsbDerive::from_port(ports[0].boxed(), Some("A1".to_string()), derive_config.clone()),
+            UsbDerive::from_port(ports[1].boxed(), Some("B2".to_string()), derive_config),
+        ];
+        let config = Config {
+            hotplug_debounce: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let mut solver = UsbSolver::from_derives(derives, config);
+        let mut alerts = solver.alerts();
+        let ids = vec!["A1".to_string(), "B2".to_string()];
+
+        // a device without link errors is not pinged
+        solver.swap_devices(&ids, |_| unreachable!());
+        assert!(ports[0].written().is_empty());
+
+        // both had link errors, only B2 answers
+        for device in &mut solver.devices {
+            device.link_errors = 1;
+        }
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        ports[1].push_response(&state);
+        solver.swap_devices(&ids, |_| unreachable!());
+        assert_eq!(solver.unhealthy_devices(), vec!["A1"]);
+        let alert = alerts.try_next().unwrap().unwrap();
+        assert_eq!(alert.serial, "A1");
+        assert_eq!(alert.severity, crate::alerts::Severity::Critical);
+
+        // still not answering once the debounce is over, it is opened again
+        thread::sleep(Duration::from_millis(60));
+        ports[1].push_response(&state);
+        let mut opened = vec![];
+        solver.swap_devices(&ids, |index| {
+            opened.push(index);
+            let serial = Some(ids[index].clone());
+            Ok(UsbDerive::from_port(MockPort::new().boxed(), serial, Config::default()))
+        });
+        assert_eq!(opened, vec![0]);
+        assert!(solver.unhealthy_devices().is_empty());
+        assert_eq!(solver.devices.len(), 2);
+    }
+
+    #[test]
+    fn test_max_devices_opened() {
+        let config = Config {
+            max_devices: Some(2),
+            ..Config::default()
+        };
+        let mut solver = UsbSolver::from_derives(vec![], config);
+        let ports: Vec<SerialPortInfo> = (0..4)
+            .map(|i| SerialPortInfo {
+                port_name: format!("/dev/ttyACM{}", i),
+                port_type: SerialPortType::Unknown,
+            })
+            .collect();
+
+        let mut opened = vec![];
+        solver.open_ports(ports, |port| {
+            opened.push(port.port_name.clone());
+            let serial = Some(port.port_name.clone());
+            Ok(UsbDerive::from_port(MockPort::new().boxed(), serial, Config::default()))
+        });
+
+        assert_eq!(opened, vec!["/dev/ttyACM0", "/dev/ttyACM1"]);
+        let ids: Vec<String> = solver.devices.iter().map(|device| device.derive.id()).collect();
+        assert_eq!(ids, opened);
+    }
+
+    #[test]
+    fn test_replug_within_debounce() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), Config::default());
+        let config = Config {
+            hotplug_debounce: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let mut solver = UsbSolver::from_derives(vec![derive], config);
+        let ids = vec!["A1".to_string()];
+
+        // a bus reset drops A1 from one refresh, it is back on the next
+        solver.swap_devices(&[], |_| unreachable!());
+        assert_eq!(solver.devices.len(), 1);
+        solver.swap_devices(&ids, |_| unreachable!());
+        assert_eq!(solver.devices.len(), 1);
+        assert!(port.written().is_empty());
+        thread::sleep(Duration::from_millis(60));
+        solver.swap_devices(&ids, |_| unreachable!());
+        assert_eq!(solver.devices.len(), 1);
+
+        // gone for longer than the window it is closed, and opened when back
+        solver.swap_devices(&[], |_| unreachable!());
+        thread::sleep(Duration::from_millis(60));
+        solver.swap_devices(&[], |_| unreachable!());
+        assert!(solver.devices.is_empty());
+        let mut opened = 0;
+        solver.swap_devices(&ids, |_| {
+            opened += 1;
+            Ok(UsbDerive::from_port(port.boxed(), Some("A1".to_string()), Config::default()))
+        });
+        assert_eq!(opened, 1);
+        assert_eq!(solver.devices.len(), 1);
+    }
+
+    #[test]
+    fn test_timeout_reason() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        assert!(solver.last_timeout().is_none());
+
+        // the board reports 8 cores, none of them good
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[10] = 8;
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&state);
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(30)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert_eq!(
+            solver.last_timeout(),
+            Some(&TimeoutReason::NotHashing {
+                serial: "A1".to_string()
+            })
+        );
+        assert_eq!(solver.telemetry().last_timeout, solver.last_timeout().cloned());
+    }
+
+    #[test]
+    fn test_lost_device_ends_job() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            link_error_limit: 3,
+            reconnect_attempts: 2,
+            reconnect_delay: Duration::from_millis(10),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        // the polls read, as on a platform that cannot tell the pending bytes
+        port.hide_pending();
+        port.fail_reads(usize::MAX);
+
+        // a mock port has no path to reopen, so every reconnect fails
+        let started = Instant::now();
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_secs(5)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(port.reads(), 3);
+    }
+
+    #[test]
+    fn test_bootloader_ends_job() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let reported = Arc::new(std::sync::Mutex::new(vec![]));
+        let hook_reported = reported.clone();
+        solver.set_bootloader_hook(move |serial| {
+            hook_reported.lock().unwrap().push(serial.to_string());
+        });
+        let boot_frame = [0xa5, 0x3c, 0x96, 0x53, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a];
+        for _ in 0..3 {
+            port.push_response(&boot_frame);
+        }
+
+        let started = Instant::now();
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_secs(5)))
+            .unwrap();
+        assert!(seal.is_none());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(*reported.lock().unwrap(), vec!["A1".to_string()]);
+        // the next job is not sent to it either
+        let writes = port.written().len();
+        let err = block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap_err();
+        assert!(UsbDerive::is_in_bootloader(&err), "{:?}", err);
+        assert_eq!(port.written().len(), writes);
+
+        // a refresh opens it again, still in bootloader mode it is not reported twice
+        let ids = vec!["A1".to_string()];
+        solver.swap_devices(&ids, |_| Err(DeriveError::InBootloader.into()));
+        assert!(solver.devices.is_empty());
+        assert_eq!(reported.lock().unwrap().len(), 1);
+
+        // once flashed it mines again, and is reported if it falls back
+        let flashed = MockPort::new();
+        solver.swap_devices(&ids, |_| {
+            Ok(UsbDerive::from_port(flashed.boxed(), Some("A1".to_string()), Config::default()))
+        });
+        assert_eq!(solver.devices.len(), 1);
+        assert!(!solver.devices[0].in_bootloader);
+        solver.swap_devices(&ids, |_| unreachable!());
+        solver.devices[0].in_bootloader = true;
+        solver.swap_devices(&ids, |_| Err(DeriveError::InBootloader.into()));
+        assert_eq!(reported.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_foreign_solution_dropped() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        let foreign = (1..16).find(|id| !job_ids.contains(id)).unwrap();
+
+        port.push_response(&nonce_frame(foreign, 0x1234, [0x11; 32]));
+        port.push_response(&nonce_frame(job_ids[0], 0x5678, [0x22; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .expect("solution for the issued job should be submitted");
+        assert_eq!(seal.nonce, 0x5678);
+        assert_eq!(solver.foreign_solutions(), 1);
+
+        let device = &solver.devices[0];
+        assert_eq!(device.solution_origin(job_ids[0]), SolutionOrigin::Current);
+        assert_eq!(device.solution_origin(foreign), SolutionOrigin::Foreign);
+        assert_ne!(job_ids[0], job_ids[1]);
+        solver.devices[0].issue_job_id(job_ids[1], 0);
+        let origin = solver.devices[0].solution_origin(job_ids[0]);
+        assert_eq!(origin, SolutionOrigin::Stale);
+
+        // ids issued long ago are forgotten
+        let later: Vec<u8> = (1..16).filter(|&id| id != job_ids[0]).take(RECENT_JOB_IDS).collect();
+        for &id in &later {
+            solver.devices[0].issue_job_id(id, 0);
+        }
+        let origin = solver.devices[0].solution_origin(job_ids[0]);
+        assert_eq!(origin, SolutionOrigin::Foreign);
+        let origin = solver.devices[0].solution_origin(later[0]);
+        assert_eq!(origin, SolutionOrigin::Stale);
+    }
+
+    #[test]
+    fn test_contention_diagnosed() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            contention_limit: 2,
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let mut alerts_rx = solver.alerts();
+        let job_ids = seed_job_ids(&mut solver);
+        let foreign = (1..16).find(|id| !job_ids.contains(id)).unwrap();
+
+        // a second process's jobs and the frames torn by both reading the port
+        let mut torn = nonce_frame(foreign, 0x1111, [0x11; 32]);
+        torn.drain(20..30);
+        for nonce in 1..=2 {
+            port.push_response(&torn);
+            port.push_response(&nonce_frame(foreign, nonce, [0x11; 32]));
+        }
+        port.push_response(&nonce_frame(job_ids[0], 0x5678, [0x22; 32]));
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap()
+            .expect("solution for the issued job should be submitted");
+        assert_eq!(seal.nonce, 0x5678);
+
+        let mut alerts = vec![];
+        while let Ok(Some(alert)) = alerts_rx.try_next() {
+            alerts.push(alert);
+        }
+        let contention: Vec<_> = alerts
+            .iter()
+            .filter(|alert| alert.message.contains("another process"))
+            .collect();
+        assert_eq!(contention.len(), 1);
+        assert_eq!(contention[0].severity, crate::alerts::Severity::Critical);
+    }
+
+    #[test]
+    fn test_device_stats() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            nominal_hashrate: Some(1.5e6),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        assert!(solver.device_stats().is_err());
+
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[10] = 8;
+        state[11] = 7;
+        state[23] = 60;
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&state);
+        let expect = vec![DeviceStats {
+            serial: "A1".to_string(),
+            cores: 8,
+            goodcores: 7,
+            target_freq: 600,
+            target_voltage: 750,
+            temperature: Some(60),
+            hashrate: Some(1.5e6),
+        }];
+        assert_eq!(solver.device_stats().unwrap(), expect);
+        assert_serialize(&expect);
+        assert_eq!(port.written().last().unwrap(), &Message::get_state_msg());
+
+        // the device stopped answering, the last state stands in
+        assert_eq!(solver.device_stats().unwrap(), expect);
+    }
+
+    #[test]
+    fn test_state_snapshot() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            state_refresh_interval: Some(Duration::from_millis(10)),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        assert!(solver.snapshot_state("A1").is_none());
+        assert!(solver.snapshot_age("A1").is_none());
+
+        let mut state = vec![0u8; 29];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        state[23] = 60;
+        state[26..].copy_from_slice(&[0x69, 0xc3, 0x5a]);
+        port.push_response(&state);
+        let state_queries = || {
+            let query = Message::get_state_msg();
+            port.written().iter().filter(|msg| **msg == query).count()
+        };
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap();
+        // asked with the job, then again while mining
+        let queries = state_queries();
+        assert!(queries >= 2, "{}", queries);
+
+        // readers share the snapshot, nothing goes to the device
+        for _ in 0..100 {
+            assert_eq!(solver.snapshot_state("A1").unwrap().temp, 60);
+            assert_eq!(solver.telemetry().devices.len(), 1);
+        }
+        assert!(solver.snapshot_age("A1").is_some());
+        assert_eq!(state_queries(), queries);
+    }
+
+    #[test]
+    fn test_nak_breaker() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            nak_breaker_limit: 3,
+            nak_cooldown: Duration::from_millis(200),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let mut alerts_rx = solver.alerts();
+        let garbage = [0x11, 0x22, 0x33, 0x69, 0xc3, 0x5a];
+        for _ in 0..5 {
+            port.push_response(&garbage);
+        }
+
+        // the third bad reply trips the breaker and ends the job early
+        let started = Instant::now();
+        assert!(block_on(solver.next_solution(mint_event(), Duration::from_secs(5)))
+            .unwrap()
+            .is_none());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(solver.unhealthy_devices(), vec!["A1"]);
+        let alerts: Vec<Alert> = std::iter::from_fn(|| alerts_rx.try_next().ok().flatten())
+            .filter(|alert| alert.message.starts_with("Unhealthy"))
+            .collect();
+        assert_eq!(alerts.len(), 1);
+
+        // nothing is sent during the cooldown
+        let written = port.written().len();
+        assert!(block_on(solver.next_solution(mint_event(), Duration::from_millis(10)))
+            .is_err());
+        assert_eq!(port.written().len(), written);
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(solver.unhealthy_devices().is_empty());
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(10)))
+            .unwrap();
+        assert!(port.written().len() > written);
+    }
+
+    #[test]
+    fn test_job_sent_once_while_idle() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(100)))
+            .unwrap();
+
+        // many polls find nothing, the job and the state query still go out once
+        assert!(port.polls() >= 10);
+        let written = port.written();
+        assert_eq!(written.iter().filter(|msg| msg[3] == TYPE_SEND_WORK).count(), 1);
+        let state_queries = written
+            .iter()
+            .filter(|msg| *msg == &Message::get_state_msg())
+            .count();
+        assert_eq!(state_queries, 1);
+    }
+
+    #[test]
+    fn test_nonce_prefix_rotation() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            nonce_space_time: Some(Duration::from_millis(30)),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+
+        let device = port.clone();
+        let handle = thread::spawn(move || loop {
+            let written = device.written();
+            let jobs: Vec<_> = written.iter().filter(|msg| msg[3] == TYPE_SEND_WORK).collect();
+            if jobs.len() >= 2 {
+                device.push_response(&nonce_frame(jobs[1][30], 0x1234, [0x11; 32]));
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        });
+        let seal = block_on(solver.next_solution(extra_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("the solution under the second prefix should be submitted");
+        handle.join().unwrap();
+
+        let written = port.written();
+        let jobs: Vec<_> = written.iter().filter(|msg| msg[3] == TYPE_SEND_WORK).collect();
+        // extra bytes of the header, after the 31 bytes of frame header and job fields
+        assert_eq!(&jobs[0][31 + 35..31 + 39], &[0, 0, 0, 0]);
+        assert_eq!(&jobs[1][31 + 35..31 + 39], &[1, 0, 0, 0]);
+        assert_ne!(jobs[0][30], jobs[1][30]);
+        assert_eq!(seal.nonce, 0x1234);
+        assert_eq!(seal.extra.unwrap().extra.as_slice(), &[1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_nonce_prefix_per_device() {
+        let ports = vec![MockPort::new(), MockPort::new()];
+        ports[0].set_name("board-a");
+        ports[1].set_name("board-b");
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            nonce_space_time: Some(Duration::from_secs(60)),
+            ..Config::default()
+        };
+        let derives = ports
+            .iter()
+            .map(|port| UsbDerive::from_port(port.boxed(), None, config.clone()))
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+        let job_ids = seed_job_ids(&mut solver);
+        ports[1].push_response(&nonce_frame(job_ids[1], 0x1234, [0x11; 32]));
+
+        let seal = block_on(solver.next_solution(extra_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("the solution of the second device should be submitted");
+
+        let job_extra = |port: &MockPort| {
+            let written = port.written();
+            let job = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK).unwrap();
+            job[31 + 35..31 + 39].to_vec()
+        };
+        assert_eq!(job_extra(&ports[0]), vec![0, 0, 0, 0]);
+        assert_eq!(job_extra(&ports[1]), vec![1, 0, 0, 0]);
+        assert_eq!(seal.extra.unwrap().extra.as_slice(), &[1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_no_nonce_prefix_without_extra() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            nonce_space_time: Some(Duration::from_millis(1)),
+            ..Config::default()
+        };
+        let derive = UsbDerive::from_port(port.boxed(), None, config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+
+        let seal = block_on(solver.next_solution(mint_event(), Duration::from_millis(50))).unwrap();
+        assert!(seal.is_none());
+        let written = port.written();
+        let jobs = written.iter().filter(|msg| msg[3] == TYPE_SEND_WORK).count();
+        assert_eq!(jobs, 1);
+    }
+
+    #[test]
+    fn test_resume_nonce_position() {
+        let path = std::env::temp_dir().join(format!("solver_nonces_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            // a thousand hashes a millisecond
+            nominal_hashrate: Some(1e6),
+            nonce_positions: Some(path.clone()),
+            nonce_position_interval: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let header = prefixed_header(&mint_event().minting_blob, None, 0).unwrap();
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config.clone());
+        let timed_out = block_on(solver.next_solution(mint_event(), Duration::from_millis(50)))
+            .unwrap();
+        assert!(timed_out.is_none());
+        drop(solver);
+        let saved = NoncePositions::load(&path)
+            .unwrap()
+            .get("A1", &header)
+            .expect("the searched position should be saved");
+        assert!((5_000..=100_000).contains(&saved), "{}", saved);
+
+        // restarted, the device resumes the same job where it got to
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config.clone());
+        let mut solver = UsbSolver::from_derive(derive, config);
+        let job_ids = seed_job_ids(&mut solver);
+        port.push_response(&nonce_frame(job_ids[0], saved as u32 + 1, [0x11; 32]));
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(50)))
+            .unwrap()
+            .expect("the resumed job should be solved");
+        let written = port.written();
+        let job = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK).unwrap();
+        assert_eq!(&job[13..21], &saved.to_le_bytes());
+        // a solved job is not resumed again
+        assert_eq!(NoncePositions::load(&path).unwrap().get("A1", &header), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_job_log() {
+        let path = std::env::temp_dir().join(format!("solver_job_log_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ports = vec![MockPort::new(), MockPort::new()];
+        ports[0].set_name("board-a");
+        ports[1].set_name("board-b");
+        let config = Config {
+            read_timeout: Duration::from_millis(5),
+            job_log: Some(path.clone()),
+            ..Config::default()
+        };
+        let derives = ports
+            .iter()
+            .map(|port| UsbDerive::from_port(port.boxed(), None, config.clone()))
+            .collect();
+        let mut solver = UsbSolver::from_derives(derives, config);
+
+        let device = ports[1].clone();
+        let handle = thread::spawn(move || loop {
+            let written = device.written();
+            if let Some(job) = written.iter().find(|msg| msg[3] == TYPE_SEND_WORK) {
+                device.push_response(&nonce_frame(job[30], 0x8000_1234, [0x11; 32]));
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        });
+        block_on(solver.next_solution(mint_event(), Duration::from_millis(500)))
+            .unwrap()
+            .expect("the second device should find the solution");
+        handle.join().unwrap();
+        // a little past 20ms, the clock of the log starts after the deadline is set
+        let timed_out = block_on(solver.next_solution(mint_event(), Duration::from_millis(25)))
+            .unwrap();
+        assert!(timed_out.is_none());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<Vec<&str>> = content.lines().map(|row| row.split(',').collect()).collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0].join(","), crate::job_log::HEADER);
+        let target = format!("{:#010x}", 0xffff_ffffu32 / 1000);
+        for row in &rows[1..] {
+            assert_eq!(row.len(), 11);
+            assert_eq!((row[1], row[2]), ("1000", target.as_str()));
+        }
+        let hash = hex::encode([0x11u8; 32]);
+        let solved = &rows[1..3];
+        assert_eq!(&solved[0][4..6], &["board-a", "stopped"]);
+        // the device that did not find the solution does not say how far it got
+        assert_eq!(&solved[0][9..], &["", ""]);
+        assert_eq!(&solved[1][4..6], &["board-b", "solved"]);
+        // the second device starts half way through the nonce range
+        let found = &["2147488308", hash.as_str(), "2147483648", "2147488309"];
+        assert_eq!(&solved[1][7..], found);
+        assert_eq!(&rows[3][5..], &["timeout", rows[3][6], "", "", "", ""]);
+        assert!(rows[3][6].parse::<u64>().unwrap() >= 20);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
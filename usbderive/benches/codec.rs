@@ -0,0 +1,87 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::io::Cursor;
+use usbderive::{read_until, DeriveResponse, Message};
+
+const PKT_ENDER: [u8; 3] = [0x69, 0xC3, 0x5A];
+
+// Ack for the opcode command, no payload.
+const ACK_FRAME: [u8; 12] = [
+    0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+];
+
+// chips:1, cores:64, goodcores:64, voltage:750, freq:600, varity:4, temp:60
+const STATE_FRAME: [u8; 29] = [
+    0xa5, 0x3c, 0x96, 0x52, 0x10, 0x17, 0x00, 0x00, 0x00, 0x01, 0x40, 0x40, 0x20, 0x10, 0x00, 0xee,
+    0x02, 0x58, 0x02, 0x04, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+];
+
+fn nonce_frame() -> Vec<u8> {
+    let mut frame = vec![
+        0xa5, 0x3c, 0x96, 0x51, 0x10, 0x32, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00,
+    ];
+    frame.extend_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+    frame.extend_from_slice(&[0u8; 5]);
+    frame.extend_from_slice(&[0xab; 32]);
+    frame.extend_from_slice(&PKT_ENDER);
+    frame
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let nonce = nonce_frame();
+    let mut group = c.benchmark_group("DeriveResponse::new");
+    group.bench_function("ack", |b| {
+        b.iter(|| DeriveResponse::new(black_box(ACK_FRAME.to_vec())))
+    });
+    group.bench_function("state", |b| {
+        b.iter(|| DeriveResponse::new(black_box(STATE_FRAME.to_vec())))
+    });
+    group.bench_function("nonce", |b| {
+        b.iter(|| DeriveResponse::new(black_box(nonce.clone())))
+    });
+    group.finish();
+}
+
+fn bench_write_job(c: &mut Criterion) {
+    let blob = [0x5a_u8; 76];
+    c.bench_function("Message::write_job_msg", |b| {
+        b.iter(|| Message::write_job_msg(black_box(1), black_box(0x0000_ffff), black_box(&blob)))
+    });
+}
+
+fn read_parse(stream: &[u8]) -> usize {
+    let mut reader = Cursor::new(stream);
+    let mut parsed = 0;
+    loop {
+        let mut raw_resp = vec![];
+        let n = read_until(&mut reader, &PKT_ENDER, raw_resp.as_mut()).unwrap();
+        if n == 0 {
+            break;
+        }
+        if DeriveResponse::new(raw_resp).is_ok() {
+            parsed += 1;
+        }
+    }
+    parsed
+}
+
+fn bench_read_parse(c: &mut Criterion) {
+    let acks: Vec<u8> = ACK_FRAME
+        .iter()
+        .cycle()
+        .take(ACK_FRAME.len() * 16)
+        .copied()
+        .collect();
+    let states: Vec<u8> = STATE_FRAME
+        .iter()
+        .cycle()
+        .take(STATE_FRAME.len() * 16)
+        .copied()
+        .collect();
+    let mut group = c.benchmark_group("read_parse");
+    group.bench_function("ack", |b| b.iter(|| read_parse(black_box(&acks))));
+    group.bench_function("state", |b| b.iter(|| read_parse(black_box(&states))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_write_job, bench_read_parse);
+criterion_main!(benches);
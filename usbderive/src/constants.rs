@@ -18,6 +18,67 @@ pub(crate) const TYPE_RECV_INFO: u8 = 0x54;
 pub(crate) const TYPE_RECV_OP: u8 = 0x57;
 pub(crate) const TYPE_RECV_FWSTATE: u8 = 0x5A;
 pub(crate) const TYPE_RECV_TEST_RESULT: u8 = 0x5B;
+pub(crate) const TYPE_RECV_ERRLOG: u8 = 0x5C;
+pub(crate) const TYPE_RECV_TARGET: u8 = 0x5E;
+
+// Error log frame: entry count at offset 9, then (code: u16, timestamp: u32) entries.
+pub(crate) const ERRLOG_COUNT_OFFSET: usize = 9;
+pub(crate) const ERRLOG_ENTRY_LEN: usize = 6;
+
+pub(crate) const LED_MODE_BLINK: u8 = 0x01;
+
+// Every frame starts with the header, type, PV and the u32 length of all but the
+// header and PKT_ENDER. There is no checksum.
+pub(crate) const FRAME_PV_OFFSET: usize = 4;
+pub(crate) const FRAME_LEN_OFFSET: usize = 5;
+pub(crate) const FRAME_HEAD_LEN: usize = 9;
+
+// Length of an ack without PKT_ENDER: just the frame head.
+pub(crate) const UNTERMINATED_ACK_LEN: usize = FRAME_HEAD_LEN;
+
+// Job num of a work frame: replace the running job, or, on firmware with a job
+// queue, start it once the running one is done.
+pub(crate) const JOB_NUM_REPLACE: u8 = 1;
+pub(crate) const JOB_NUM_QUEUE: u8 = 2;
+
+// Nonce frame: job id at offset 9, the u32 nonce at 12 and the hash at 21, then PKT_ENDER.
+pub(crate) const NONCE_JOB_ID_OFFSET: usize = 9;
+pub(crate) const NONCE_OFFSET: usize = 12;
+pub(crate) const NONCE_HASH_OFFSET: usize = 21;
+pub(crate) const HASH_LEN: usize = 32;
+pub(crate) const NONCE_FRAME_LEN: usize = NONCE_HASH_OFFSET + HASH_LEN + PKT_ENDER.len();
+
+// Target readback frame: job id at offset 9, then the u32 target.
+pub(crate) const TARGET_JOB_ID_OFFSET: usize = 9;
+
+// Hw params sub-command enabling cores by bit, followed by the u32 mask. Custom
+// firmware only, stock firmware does not define it.
+pub(crate) const CMD_CORE_MASK: u8 = 0x5D;
+// Hw params sub-command switching the power mode, followed by one of the modes.
+pub(crate) const CMD_POWER_MODE: u8 = 0x5F;
+pub(crate) const POWER_MODE_WAKE: u8 = 0x00;
+pub(crate) const POWER_MODE_SLEEP: u8 = 0x01;
+// Offset of the active core mask in state frames of firmware that reports it.
+pub(crate) const STATE_CORE_MASK_OFFSET: usize = 26;
+// Offset of the u16 safe frequency ceiling, after the core mask, in state frames of
+// firmware that reports it.
+pub(crate) const STATE_MAX_FREQ_OFFSET: usize = 30;
+// Offset of the u16 lowest and highest core frequency, after the ceiling, in state
+// frames of firmware that reports them.
+pub(crate) const STATE_CORE_FREQ_OFFSET: usize = 32;
+
+// Lengths of the state frame of each firmware generation, from the basic one up to
+// the one reporting core frequencies.
+pub(crate) const STATE_FRAME_LENS: [usize; 4] = [
+    STATE_CORE_MASK_OFFSET + PKT_ENDER.len(),
+    STATE_MAX_FREQ_OFFSET + PKT_ENDER.len(),
+    STATE_CORE_FREQ_OFFSET + PKT_ENDER.len(),
+    STATE_CORE_FREQ_OFFSET + 4 + PKT_ENDER.len(),
+];
+
+// Temperature readings reported when the sensor is absent or shorted.
+pub(crate) const TEMP_SENSOR_OPEN: u8 = 0x00;
+pub(crate) const TEMP_SENSOR_FAULT: u8 = 0xFF;
 
 pub(crate) const ALGO_VARITY: u32 = 4;
 pub(crate) const TYPE_OFFSET: usize = 0;
@@ -0,0 +1,107 @@
+//! Which USB host controller a port hangs off, to spread boards across controllers.
+//!
+//! Boards on one controller share its bandwidth. Linux shows the controller in the
+//! sysfs path of the tty, the device right above the first root hub `usbN`, e.g.
+//! `/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/tty/ttyACM0` is on the
+//! controller `0000:00:14.0`. A controller with USB 2 and 3 root hubs is one.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Controller of the serial port `port_name`, `None` where the OS does not tell.
+pub fn port_controller(port_name: &str) -> Option<String> {
+    let tty = Path::new(port_name).file_name()?;
+    let sysfs_path = Path::new("/sys/class/tty").join(tty).canonicalize().ok()?;
+    controller_of(&sysfs_path)
+}
+
+/// Controller in a sysfs device path.
+pub fn controller_of(sysfs_path: &Path) -> Option<String> {
+    let components: Vec<&str> = sysfs_path
+        .iter()
+        .filter_map(|component| component.to_str())
+        .collect();
+    let hub = components
+        .iter()
+        .position(|component| is_root_hub(component))?;
+    match hub.checked_sub(1).map(|parent| components[parent]) {
+        Some(parent) if parent != "devices" && parent != "/" => Some(parent.to_string()),
+        _ => Some(components[hub].to_string()),
+    }
+}
+
+fn is_root_hub(component: &str) -> bool {
+    component.strip_prefix("usb").map_or(false, |bus| {
+        !bus.is_empty() && bus.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+/// Controllers with more than `limit` of `devices`, given as serial and controller,
+/// with their serials. Devices of unknown controller are left out.
+pub fn crowded_controllers(
+    devices: &[(String, Option<String>)],
+    limit: usize,
+) -> Vec<(String, Vec<String>)> {
+    let mut controllers: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (serial, controller) in devices {
+        if let Some(controller) = controller {
+            controllers
+                .entry(controller)
+                .or_insert_with(Vec::new)
+                .push(serial.clone());
+        }
+    }
+    controllers
+        .into_iter()
+        .filter(|(_, serials)| serials.len() > limit)
+        .map(|(controller, serials)| (controller.to_string(), serials))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controller_of() {
+        let controller = |path: &str| controller_of(Path::new(path));
+        assert_eq!(
+            controller("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/tty/ttyACM0"),
+            Some("0000:00:14.0".to_string())
+        );
+        assert_eq!(
+            controller("/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1.3/2-1.3:1.0/tty/ttyACM1"),
+            Some("0000:00:14.0".to_string())
+        );
+        assert_eq!(
+            controller(
+                "/sys/devices/platform/soc/3f980000.usb/usb1/1-1/1-1.2/1-1.2:1.0/tty/ttyACM0"
+            ),
+            Some("3f980000.usb".to_string())
+        );
+        assert_eq!(
+            controller("/sys/devices/usb3/3-1/3-1:1.0/tty/ttyACM0"),
+            Some("usb3".to_string())
+        );
+        assert_eq!(controller("/sys/devices/pnp0/00:04/tty/ttyS0"), None);
+    }
+
+    #[test]
+    fn test_crowded_controllers() {
+        let devices = vec![
+            ("A1".to_string(), Some("0000:00:14.0".to_string())),
+            ("A2".to_string(), Some("0000:00:14.0".to_string())),
+            ("A3".to_string(), Some("0000:00:14.0".to_string())),
+            ("B1".to_string(), Some("0000:03:00.0".to_string())),
+            ("C1".to_string(), None),
+        ];
+        assert_eq!(
+            crowded_controllers(&devices, 2),
+            vec![(
+                "0000:00:14.0".to_string(),
+                vec!["A1".to_string(), "A2".to_string(), "A3".to_string()]
+            )]
+        );
+        assert!(crowded_controllers(&devices, 3).is_empty());
+    }
+}
@@ -1,19 +1,356 @@
 use crate::constants::*;
-use crate::proto::{DeriveResponse, Message, State};
-use crate::read_until;
+use crate::controller;
+use crate::proto::{DeriveResponse, ErrorLogEntry, JobTarget, Message, State};
+use crate::{read_until_limited, read_until_strict, DeriveError, FrameTooLarge};
 use anyhow::Result;
-use serialport::{SerialPort, SerialPortInfo, SerialPortSettings, SerialPortType};
+use serde::Serialize;
+use serialport::{ClearBuffer, SerialPort, SerialPortInfo, SerialPortSettings, SerialPortType};
 use starcoin_logger::prelude::*;
+use std::convert::TryInto;
+use std::io;
 use std::io::BufReader;
-use std::io::Write;
-use std::time::Duration;
+use std::io::{Read, Write};
+use std::ops::BitOr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Clone)]
+// How long dropping the last handle may block sending the reboot.
+const QUIESCE_TIMEOUT: Duration = Duration::from_millis(100);
+// Floor of a command timeout adapted to the ping latency.
+const MIN_ADAPTED_ACK_TIMEOUT: Duration = Duration::from_millis(10);
+// one USB packet, what a poll for input reads at most
+const POLL_READ_LEN: usize = 64;
+
+/// What to do when the temperature sensor reports no reading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum UnknownTemp {
+    /// Treat the board as overheating.
+    Throttle,
+    /// Keep running as if the temperature was fine.
+    Ignore,
+}
+
+/// What the solver does with responses of a type it does not know.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum UnknownResponse {
+    /// Log and count them.
+    Count,
+    /// Also raise a warning alert once a device sent this many, a sign of firmware drift.
+    Alert(u64),
+}
+
+/// Which of several near-simultaneous solutions gets submitted. For solo mining
+/// any of them does, some pools credit the best share and want the lowest hash.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum SubmitPolicy {
+    /// The first one received.
+    First,
+    /// The one with the lowest hash.
+    LowestHash,
+    /// The one of the device listed first, by id. Devices not listed come last,
+    /// among them the first solution received wins.
+    DevicePriority(Vec<String>),
+}
+
+/// How long to wait for the ack of each command that expects one,
+/// `reboot`, `set_job` and `identify` are not acked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct CommandTimeouts {
+    /// Any answer within this counts as alive.
+    pub ping: Duration,
+    pub get_state: Duration,
+    pub get_target: Duration,
+    pub error_log: Duration,
+    /// The PLL relocks before the device acks new hw params.
+    pub set_hw_params: Duration,
+    pub set_opcode: Duration,
+    pub core_mask: Duration,
+}
+
+impl Default for CommandTimeouts {
+    fn default() -> Self {
+        Self {
+            ping: Duration::from_millis(200),
+            get_state: Duration::from_millis(500),
+            get_target: Duration::from_millis(500),
+            error_log: Duration::from_secs(1),
+            set_hw_params: Duration::from_secs(3),
+            set_opcode: Duration::from_secs(1),
+            core_mask: Duration::from_secs(1),
+        }
+    }
+}
+
+/// How the device delimits a reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Framing {
+    /// Terminated by `PKT_ENDER`.
+    Terminated,
+    /// Exactly this many bytes, some firmware acks without `PKT_ENDER`.
+    Fixed(usize),
+}
+
+/// Reply framing of each acked command, to match the firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ProtocolProfile {
+    pub get_state: Framing,
+    pub get_target: Framing,
+    pub error_log: Framing,
+    pub set_hw_params: Framing,
+    pub set_opcode: Framing,
+    pub core_mask: Framing,
+}
+
+impl Default for ProtocolProfile {
+    fn default() -> Self {
+        Self {
+            get_state: Framing::Terminated,
+            get_target: Framing::Terminated,
+            error_log: Framing::Terminated,
+            set_hw_params: Framing::Terminated,
+            set_opcode: Framing::Terminated,
+            core_mask: Framing::Terminated,
+        }
+    }
+}
+
+/// Known misbehaviours of some firmware batches and the workaround each one turns on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Quirks(u32);
+
+impl Quirks {
+    pub const NONE: Quirks = Quirks(0);
+    /// The opcode is not acked, it is sent without waiting for a reply.
+    pub const NO_OPCODE_ACK: Quirks = Quirks(1);
+    /// Acks of `set_hw_params` and `set_opcode` come without `PKT_ENDER` and are
+    /// read by length, overriding the `ProtocolProfile` of those commands.
+    pub const UNTERMINATED_ACKS: Quirks = Quirks(1 << 1);
+    /// Hw params are ignored, they are not sent and frequency changes fail.
+    pub const IGNORES_HW_PARAMS: Quirks = Quirks(1 << 2);
+
+    pub fn contains(self, other: Quirks) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Quirks) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitOr for Quirks {
+    type Output = Quirks;
+
+    fn bitor(self, rhs: Quirks) -> Quirks {
+        Quirks(self.0 | rhs.0)
+    }
+}
+
+/// How the 256-bit target is cut down to the 32 bits the device compares against.
+///
+/// The device accepts a hash when its top 32 bits do not exceed the target, so any
+/// rounding trades shares the network rejects for valid shares the device drops.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum TargetRounding {
+    /// Keep the top 32 bits. Easier than the real target: the device also reports
+    /// hashes just above it, which the network rejects.
+    Truncate,
+    /// One below the truncated target, unless only zero bits were cut off. Every
+    /// reported hash is valid, but valid hashes just below the real target are dropped.
+    Harder,
+    /// Whichever of the two is closer to the real target, errs either way by at
+    /// most half a unit of the top 32 bits.
+    Nearest,
+}
+
+/// How many of the most significant bytes of the target go into a job, the most
+/// the firmware compares. The stock firmware takes 32 bits. With fewer bits than
+/// the full target, `TargetRounding` decides between shares the network rejects
+/// and valid ones the device drops, a wider target narrows both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum TargetResolution {
+    Bits32,
+    Bits64,
+    Bits256,
+}
+
+impl TargetResolution {
+    /// Target bytes in a job frame.
+    pub fn bytes(self) -> usize {
+        match self {
+            TargetResolution::Bits32 => 4,
+            TargetResolution::Bits64 => 8,
+            TargetResolution::Bits256 => 32,
+        }
+    }
+}
+
+/// Link errors and the recoveries they triggered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DeriveStats {
+    /// Frames that ended but could not be parsed.
+    pub framing_errors: u64,
+    /// Times the link was recovered by reopening at a re-probed baud rate.
+    pub baud_reprobes: u64,
+    /// Most bytes found waiting at a read, near the size of the port buffer when
+    /// the host falls behind the device.
+    pub input_peak: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct Config {
     pub target_freq: u16,
     pub target_voltage: u16,
+    /// Bring the board up at this frequency and raise it to `target_freq` only if
+    /// every core is good after `probe_time`. `None` starts at the target.
+    pub probe_freq: Option<u16>,
+    pub probe_time: Duration,
+    /// Lowest and highest frequency hw params may be set to, in MHz.
+    pub freq_limits: (u16, u16),
+    /// Lowest and highest voltage hw params may be set to, in mV.
+    pub voltage_limits: (u16, u16),
+    pub target_rounding: TargetRounding,
+    pub target_resolution: TargetResolution,
+    /// Timeout of reads in the solve loop.
     pub read_timeout: Duration,
-    baud_rate: u32,
+    /// Every `latency_check_interval` between jobs, set `ack_timeout` of each
+    /// device to this multiple of its ping latency. `None` never sets it.
+    pub latency_timeout_factor: Option<f64>,
+    pub latency_check_interval: Duration,
+    /// Timeout of command replies in place of `command_timeouts`, `None` keeps those.
+    pub ack_timeout: Option<Duration>,
+    /// While the device sends nothing, check for input after a sleep growing up to
+    /// this instead of reading, to cap the CPU the solve loop uses. `None` reads
+    /// back to back.
+    pub max_idle_sleep: Option<Duration>,
+    /// With a resource check set, the host is under pressure past `pressure_cpu_limit`
+    /// of cpu use or under `pressure_min_free_memory` bytes free, checked every
+    /// `pressure_check_interval`. The solver then skips keeping frames for
+    /// diagnostics and polls idle devices every `pressure_poll_interval`.
+    pub pressure_cpu_limit: f64,
+    pub pressure_min_free_memory: u64,
+    pub pressure_check_interval: Duration,
+    pub pressure_poll_interval: Duration,
+    pub command_timeouts: CommandTimeouts,
+    pub protocol: ProtocolProfile,
+    /// Re-upload the current job this often while waiting for a solution, `None` never resends.
+    pub resend_interval: Option<Duration>,
+    /// Time a device takes to search its whole 32-bit nonce range, used when its hashrate
+    /// is not known yet. Once a device has searched its range, reckoned from its rated or
+    /// measured hashrate, every device gets the job again under a new host nonce prefix of
+    /// its own in the extra bytes. Jobs without an extra are not prefixed, `None` never does.
+    pub nonce_space_time: Option<Duration>,
+    /// A disconnect followed by a reconnect of the same serial within this window is ignored.
+    pub hotplug_debounce: Duration,
+    /// After a stop, keep reading the devices for this long for a solution they found
+    /// just before it, e.g. at a block transition. Zero stops at once, after the
+    /// frames already received.
+    pub stop_grace: Duration,
+    /// Collect solutions for this long after the first one and submit only one of them.
+    /// Keep `None` for pool mining, where every share counts.
+    pub submit_window: Option<Duration>,
+    pub submit_policy: SubmitPolicy,
+    /// Forward the raw frame of every solution read from the device to external validators.
+    pub raw_solutions: bool,
+    /// Log the full header of every solution submitted, to debug rejected submissions.
+    pub log_solved_headers: bool,
+    /// The firmware can hold a job until the running one is done. A new job queues
+    /// behind one that is still wanted, one that was solved elsewhere; it replaces
+    /// one that timed out or was stopped.
+    pub job_queueing: bool,
+    /// A job identical to the one still running on the devices, e.g. an event the
+    /// client sent again, keeps them mining instead of being uploaded again. A job
+    /// that was solved or stopped is off the devices and gets uploaded.
+    pub keep_duplicate_jobs: bool,
+    /// Read the target back after uploading a job and fail the job if it differs.
+    pub verify_target: bool,
+    /// Retry uploading a job this many times before giving up on it.
+    pub job_setup_retries: u32,
+    /// The firmware takes the power mode command (0x5F) of `sleep` and `wake`. Stock
+    /// firmware does not define it, so it is never sent unless set.
+    pub power_mode: bool,
+    /// Put the device to sleep once a job is done and wake it for the next one,
+    /// rather than leave it hashing stale work in between. Needs `power_mode`.
+    pub sleep_between_jobs: bool,
+    /// The firmware takes the core mask command (0x5D) of `set_core_mask`. Stock
+    /// firmware does not define it, so it is never sent unless set.
+    pub core_mask_command: bool,
+    /// The firmware answers the error log query (0x5C) of `error_log`. Stock firmware
+    /// does not define it, so it is never sent unless set.
+    pub error_log_command: bool,
+    pub temp_limit: u8,
+    pub unknown_temp: UnknownTemp,
+    /// Alert when the measured voltage is further than this from `target_voltage`, in mV.
+    pub voltage_tolerance: u16,
+    /// Lower the frequency by this much each time the voltage goes off, down to the
+    /// lower frequency limit. `None` leaves it.
+    pub voltage_freq_step: Option<u16>,
+    /// Warn, and count a board unstable in autotune, when its cores are further apart than this, in MHz.
+    pub freq_spread_limit: u16,
+    /// Identical health alerts are sent at most once per interval.
+    pub alert_interval: Duration,
+    /// Alert after this many consecutive unparsable replies.
+    pub nak_alert_limit: u32,
+    /// Stop sending jobs to a device after this many consecutive unparsable replies
+    /// and flag it unhealthy for `nak_cooldown`.
+    pub nak_breaker_limit: u32,
+    pub nak_cooldown: Duration,
+    /// Suspect another process drives a device once it sends this many unparsable
+    /// frames and this many solutions for job ids never issued within `contention_window`.
+    pub contention_limit: u32,
+    pub contention_window: Duration,
+    /// Hashes per second a board is rated for. A board claiming over
+    /// `solution_rate_factor` times the work it can do within `solution_rate_window`
+    /// likely reports false positives. `None` skips the check.
+    pub nominal_hashrate: Option<f64>,
+    pub solution_rate_factor: f64,
+    pub solution_rate_window: Duration,
+    /// Once a board fails the solution rate check, pass every solution it reports
+    /// through the verifier, not only the all-zero ones.
+    pub verify_implausible: bool,
+    pub unknown_response: UnknownResponse,
+    /// Alert when more than this fraction of the shares is rejected.
+    pub reject_rate_limit: f64,
+    /// Give up on a frame without terminator after this many bytes.
+    pub max_frame_len: usize,
+    /// Reject any deviation from the protocol a lenient read tolerates: partial
+    /// frames, bytes ahead of the header, unknown response types and frames of an
+    /// unexpected size. For firmware development, not production.
+    pub strict: bool,
+    /// Re-probe the baud rate after this many consecutive unparsable frames.
+    pub framing_failure_limit: u32,
+    /// Reopen the port after this many consecutive failed reads or writes.
+    pub link_error_limit: u32,
+    /// Give up on a device after this many failed reopens in a row.
+    pub reconnect_attempts: u32,
+    pub reconnect_delay: Duration,
+    /// Open at most this many of the detected devices, leaving the rest to other processes.
+    pub max_devices: Option<usize>,
+    /// While mining, ask each device for its state this often for the snapshot the
+    /// solver serves. `None` asks once per job.
+    pub state_refresh_interval: Option<Duration>,
+    /// Warn at startup when more than this many devices share a USB host controller.
+    pub controller_device_limit: Option<usize>,
+    /// Baud rates tried, in order, when re-probing.
+    pub probe_baud_rates: Vec<u32>,
+    /// Readings after new hw params or the first job are left out of hashrate and
+    /// autotune measurements for this long.
+    pub warmup: Duration,
+    pub quirks: Quirks,
+    /// Baud rate the port is opened at.
+    pub baud_rate: u32,
+    /// Append a CSV row per device and job to this file, `None` logs nothing.
+    pub job_log: Option<PathBuf>,
+    /// Save how far each device searched a job to this file, a restarted solver
+    /// resumes the same job from there. `None` searches every job from the start.
+    pub nonce_positions: Option<PathBuf>,
+    /// How often the searched positions are saved, reckoned from the device hashrate.
+    pub nonce_position_interval: Duration,
+    /// Minting blob versions the devices can mine, a job with another version is
+    /// refused. `None` mines any.
+    pub blob_versions: Option<Vec<u8>>,
 }
 
 impl Default for Config {
@@ -21,15 +358,200 @@ impl Default for Config {
         Self {
             target_freq: 600,
             target_voltage: 750,
+            probe_freq: None,
+            probe_time: Duration::from_secs(3),
+            freq_limits: (100, 1000),
+            voltage_limits: (600, 900),
+            target_rounding: TargetRounding::Truncate,
+            target_resolution: TargetResolution::Bits32,
             read_timeout: Duration::from_secs(1),
+            latency_timeout_factor: None,
+            latency_check_interval: Duration::from_secs(300),
+            ack_timeout: None,
+            max_idle_sleep: None,
+            pressure_cpu_limit: 0.9,
+            pressure_min_free_memory: 256 << 20,
+            pressure_check_interval: Duration::from_secs(5),
+            pressure_poll_interval: Duration::from_millis(20),
+            command_timeouts: CommandTimeouts::default(),
+            protocol: ProtocolProfile::default(),
+            resend_interval: None,
+            nonce_space_time: None,
+            hotplug_debounce: Duration::from_millis(500),
+            stop_grace: Duration::from_secs(0),
+            submit_window: None,
+            submit_policy: SubmitPolicy::First,
+            raw_solutions: false,
+            log_solved_headers: false,
+            job_queueing: false,
+            keep_duplicate_jobs: false,
+            verify_target: false,
+            job_setup_retries: 2,
+            power_mode: false,
+            sleep_between_jobs: false,
+            core_mask_command: false,
+            error_log_command: false,
+            temp_limit: 80,
+            unknown_temp: UnknownTemp::Throttle,
+            voltage_tolerance: 50,
+            voltage_freq_step: None,
+            freq_spread_limit: 25,
+            alert_interval: Duration::from_secs(60),
+            nak_alert_limit: 3,
+            nak_breaker_limit: 20,
+            nak_cooldown: Duration::from_secs(60),
+            contention_limit: 3,
+            contention_window: Duration::from_secs(60),
+            nominal_hashrate: None,
+            solution_rate_factor: 10.0,
+            solution_rate_window: Duration::from_secs(600),
+            verify_implausible: false,
+            reject_rate_limit: 0.1,
+            unknown_response: UnknownResponse::Count,
+            max_frame_len: 4096,
+            strict: false,
+            framing_failure_limit: 8,
+            link_error_limit: 5,
+            reconnect_attempts: 3,
+            reconnect_delay: Duration::from_secs(1),
+            max_devices: None,
+            state_refresh_interval: None,
+            controller_device_limit: None,
+            probe_baud_rates: vec![115200, 230400, 460800, 921600, 57600, 9600],
+            warmup: Duration::from_secs(10),
+            quirks: Quirks::NONE,
             baud_rate: 115200,
+            job_log: None,
+            nonce_positions: None,
+            nonce_position_interval: Duration::from_secs(10),
+            blob_versions: None,
+        }
+    }
+}
+
+impl Config {
+    /// Fails if `freq` or `voltage` is outside the limits.
+    pub fn check_hw_params(&self, freq: u16, voltage: u16) -> Result<()> {
+        let (min_freq, max_freq) = self.freq_limits;
+        if freq < min_freq || freq > max_freq {
+            anyhow::bail!(
+                "Frequency {} is out of range {}..={}",
+                freq,
+                min_freq,
+                max_freq
+            );
+        }
+        let (min_voltage, max_voltage) = self.voltage_limits;
+        if voltage < min_voltage || voltage > max_voltage {
+            anyhow::bail!(
+                "Voltage {} is out of range {}..={}",
+                voltage,
+                min_voltage,
+                max_voltage
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether the measured voltage is out of tolerance, a voltage of 0 is no reading.
+    pub fn voltage_off(&self, state: &State) -> bool {
+        state.voltage != 0
+            && state.voltage_deviation(self.target_voltage).abs()
+                > i32::from(self.voltage_tolerance)
+    }
+
+    /// Whether the core frequencies are spread wider than the limit.
+    pub fn freq_spread_off(&self, state: &State) -> bool {
+        state
+            .core_freq_spread()
+            .map_or(false, |spread| spread > self.freq_spread_limit)
+    }
+
+    pub fn should_throttle(&self, state: &State) -> bool {
+        match state.temperature() {
+            Some(temp) => temp >= self.temp_limit,
+            None => self.unknown_temp == UnknownTemp::Throttle,
         }
     }
 }
 
+/// Kind of port the device was opened on, as far as the serial backend can tell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum PortKind {
+    Usb,
+    Pci,
+    Bluetooth,
+    Unknown,
+}
+
+/// Where and how the device is attached, for support reports.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeviceMetadata {
+    pub port_name: Option<String>,
+    pub port_kind: PortKind,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub os: &'static str,
+    /// USB host controller the device is on, `None` where the OS does not tell.
+    pub controller: Option<String>,
+    /// Safe frequency ceiling the device last reported.
+    pub max_freq: Option<u16>,
+}
+
+impl DeviceMetadata {
+    pub fn new(port_name: Option<String>, port_type: Option<&SerialPortType>) -> Self {
+        let controller = port_name.as_deref().and_then(controller::port_controller);
+        let mut metadata = Self {
+            port_name,
+            port_kind: PortKind::Unknown,
+            vid: None,
+            pid: None,
+            manufacturer: None,
+            product: None,
+            os: std::env::consts::OS,
+            controller,
+            max_freq: None,
+        };
+        match port_type {
+            Some(SerialPortType::UsbPort(usb_port)) => {
+                metadata.port_kind = PortKind::Usb;
+                metadata.vid = Some(usb_port.vid);
+                metadata.pid = Some(usb_port.pid);
+                metadata.manufacturer = usb_port.manufacturer.clone();
+                metadata.product = usb_port.product.clone();
+            }
+            Some(SerialPortType::PciPort) => metadata.port_kind = PortKind::Pci,
+            Some(SerialPortType::BluetoothPort) => metadata.port_kind = PortKind::Bluetooth,
+            Some(SerialPortType::Unknown) | None => {}
+        }
+        metadata
+    }
+}
+
+/// Sets a device up again after it rebooted or was reopened, in place of sending
+/// hw params and opcode.
+pub type InitHook = Arc<dyn Fn(&mut UsbDerive) -> Result<()> + Send + Sync>;
+
 pub struct UsbDerive {
     serial_port: Box<dyn SerialPort>,
+    // port path it was opened at, `None` for a port handed in
+    path: Option<String>,
+    serial: Option<String>,
+    port_type: Option<SerialPortType>,
     config: Config,
+    framing_failures: u32,
+    stats: DeriveStats,
+    max_freq: Option<u16>,
+    // bytes read past the end of the last frame, the start of the next ones
+    rx_buf: Vec<u8>,
+    // shared by the clones of one port, set while no job of ours runs on the device:
+    // before the first job, after a reboot and in the bootloader
+    idle: Arc<AtomicBool>,
+    // job frame last uploaded, resent by `reinit`
+    active_job: Option<Vec<u8>>,
+    init_hook: Option<InitHook>,
 }
 
 impl Clone for UsbDerive {
@@ -41,7 +563,17 @@ impl Clone for UsbDerive {
         let config = self.config.clone();
         Self {
             serial_port,
+            path: self.path.clone(),
+            serial: self.serial.clone(),
+            port_type: self.port_type.clone(),
             config,
+            framing_failures: self.framing_failures,
+            stats: self.stats,
+            max_freq: self.max_freq,
+            rx_buf: self.rx_buf.clone(),
+            idle: self.idle.clone(),
+            active_job: self.active_job.clone(),
+            init_hook: self.init_hook.clone(),
         }
     }
 }
@@ -65,61 +597,1693 @@ impl UsbDerive {
         Ok(usb_ports)
     }
 
+    /// Keep the first `max_devices` of the detected ports.
+    pub fn limit_ports(
+        mut ports: Vec<SerialPortInfo>,
+        max_devices: Option<usize>,
+    ) -> Vec<SerialPortInfo> {
+        if let Some(max) = max_devices {
+            if ports.len() > max {
+                for port in &ports[max..] {
+                    info!("Skip port {} over max devices {}", port.port_name, max);
+                }
+                ports.truncate(max);
+            }
+        }
+        ports
+    }
+
     pub fn open(path: &str, config: Config) -> Result<Self> {
+        let serial_port = Self::open_serial(path, &config)?;
+        let mut derive = Self::from_port(serial_port, None, config);
+        derive.path = Some(path.to_string());
+        Ok(derive)
+    }
+
+    fn open_serial(path: &str, config: &Config) -> Result<Box<dyn SerialPort>> {
         let mut setting = SerialPortSettings::default();
         setting.baud_rate = config.baud_rate;
         setting.timeout = config.read_timeout;
-        let serial_port = serialport::open_with_settings(path, &setting)?;
-        Ok(Self {
+        Ok(serialport::open_with_settings(path, &setting)?)
+    }
+
+    pub fn open_port(port: &SerialPortInfo, config: Config) -> Result<Self> {
+        let mut derive = Self::open(&port.port_name, config)?;
+        if let SerialPortType::UsbPort(usb_port) = &port.port_type {
+            derive.serial = usb_port.serial_number.clone();
+        }
+        derive.port_type = Some(port.port_type.clone());
+        Ok(derive)
+    }
+
+    /// Serial number of the device on `port`, the port name if it has none,
+    /// the same as `id` of the derive opened on it.
+    pub fn port_id(port: &SerialPortInfo) -> String {
+        match &port.port_type {
+            SerialPortType::UsbPort(usb_port) => usb_port
+                .serial_number
+                .clone()
+                .unwrap_or_else(|| port.port_name.clone()),
+            _ => port.port_name.clone(),
+        }
+    }
+
+    /// Reopen the device after it re-enumerated. It is looked up by serial number
+    /// among the detected ports, as it may come back under another port name.
+    /// Hw params and opcode are not re-sent.
+    pub fn reconnect(&mut self, vid: u16, pid: u16) -> Result<()> {
+        let ports = Self::detect(vid, pid)?;
+        let config = self.config.clone();
+        self.reconnect_from(&ports, |path| Self::open_serial(path, &config))
+    }
+
+    fn reconnect_from<F>(&mut self, ports: &[SerialPortInfo], open: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> Result<Box<dyn SerialPort>>,
+    {
+        let serial = match &self.serial {
+            Some(serial) => serial.clone(),
+            None => anyhow::bail!(
+                "Device on {:?} has no serial number to find it by",
+                self.serial_port.name()
+            ),
+        };
+        let port = ports
+            .iter()
+            .find(|port| match &port.port_type {
+                SerialPortType::UsbPort(usb_port) => {
+                    usb_port.serial_number.as_deref() == Some(serial.as_str())
+                }
+                _ => false,
+            })
+            .ok_or_else(|| anyhow::anyhow!("Device {} not found", serial))?;
+        info!("Reconnect device {} on {}", serial, port.port_name);
+        self.serial_port = open(&port.port_name)?;
+        self.rx_buf.clear();
+        self.path = Some(port.port_name.clone());
+        self.port_type = Some(port.port_type.clone());
+        self.framing_failures = 0;
+        Ok(())
+    }
+
+    /// Open the port again at the path it was opened at, after the device dropped off
+    /// the bus for a moment, and resend hw params and opcode.
+    pub fn reopen(&mut self) -> Result<()> {
+        let config = self.config.clone();
+        self.reopen_with(|path| Self::open_serial(path, &config))
+    }
+
+    fn reopen_with<F>(&mut self, open: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> Result<Box<dyn SerialPort>>,
+    {
+        self.reopen_port(open)?;
+        self.reinit()
+    }
+
+    fn reopen_port<F>(&mut self, open: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> Result<Box<dyn SerialPort>>,
+    {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => anyhow::bail!("Device {} was not opened by path", self.id()),
+        };
+        info!("Reopen device {} on {}", self.id(), path);
+        self.serial_port = open(&path)?;
+        self.rx_buf.clear();
+        self.framing_failures = 0;
+        Ok(())
+    }
+
+    /// Open the device again after it dropped off the bus: at the path it was opened
+    /// at, else looked up by serial number as it may have come back under another
+    /// port name. With `Config::error_log_command` the error log of the device is
+    /// logged for what wedged it, then hw params, opcode and job are resent.
+    pub fn recover(&mut self, vid: u16, pid: u16) -> Result<()> {
+        let config = self.config.clone();
+        self.recover_with(
+            || Self::detect(vid, pid),
+            |path| Self::open_serial(path, &config),
+        )
+    }
+
+    fn recover_with<D, F>(&mut self, detect: D, open: F) -> Result<()>
+    where
+        D: FnOnce() -> Result<Vec<SerialPortInfo>>,
+        F: Fn(&str) -> Result<Box<dyn SerialPort>>,
+    {
+        if let Err(e) = self.reopen_port(&open) {
+            debug!("Failed to reopen {}: {:?}", self.id(), e);
+            self.reconnect_from(&detect()?, &open)?;
+        }
+        // before the job is resent, a solution could be taken for the reply
+        self.dump_error_log();
+        self.reinit()
+    }
+
+    // Best effort, whatever the device answers the recovery goes on.
+    fn dump_error_log(&mut self) {
+        if !self.config.error_log_command {
+            return;
+        }
+        match self.error_log() {
+            Ok(entries) if entries.is_empty() => info!("Error log of {} is empty", self.id()),
+            Ok(entries) => {
+                for entry in entries {
+                    warn!(
+                        "Error log of {}: code {:#06x} at {}s since boot",
+                        self.id(),
+                        entry.code,
+                        entry.timestamp
+                    );
+                }
+            }
+            Err(e) => {
+                debug!("Failed to read the error log of {}: {:?}", self.id(), e);
+                // what it sent instead is not taken for the ack of the hw params
+                if let Err(e) = self.clear_input() {
+                    debug!("Failed to clear the input of {}: {:?}", self.id(), e);
+                }
+            }
+        }
+    }
+
+    /// Run `init` in place of hw params and opcode when the device is set up again.
+    pub fn set_init_hook(&mut self, init: InitHook) {
+        self.init_hook = Some(init);
+    }
+
+    /// Configure the device again after it rebooted, which forgets hw params,
+    /// opcode and job: run the init hook, or send hw params and opcode, then resend
+    /// the job if one was uploaded. Hw params are the ones in effect, as retuned.
+    pub fn reinit(&mut self) -> Result<()> {
+        match self.init_hook.clone() {
+            Some(init) => init(self)?,
+            None => {
+                self.set_hw_params()?;
+                self.set_opcode()?;
+            }
+        }
+        if let Some(msg) = self.active_job.clone() {
+            let _ = self.serial_port.write(&msg)?;
+            self.idle.store(false, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Whether `e` is a failed read or write of the port, not a timeout or a bad frame.
+    pub fn is_link_error(e: &anyhow::Error) -> bool {
+        e.downcast_ref::<io::Error>().map_or(false, |e| {
+            !matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            )
+        })
+    }
+
+    /// Whether `e` is the device answering from its bootloader, which no retry fixes.
+    pub fn is_in_bootloader(e: &anyhow::Error) -> bool {
+        e.downcast_ref::<DeriveError>() == Some(&DeriveError::InBootloader)
+    }
+
+    pub fn from_port(
+        serial_port: Box<dyn SerialPort>,
+        serial: Option<String>,
+        config: Config,
+    ) -> Self {
+        Self {
             serial_port,
+            path: None,
+            serial,
+            port_type: None,
             config,
-        })
+            framing_failures: 0,
+            stats: DeriveStats::default(),
+            max_freq: None,
+            rx_buf: vec![],
+            idle: Arc::new(AtomicBool::new(true)),
+            active_job: None,
+            init_hook: None,
+        }
+    }
+
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Serial number of the device, the port name if it has none.
+    pub fn id(&self) -> String {
+        self.serial
+            .clone()
+            .or_else(|| self.serial_port.name())
+            .unwrap_or_default()
+    }
+
+    pub fn metadata(&self) -> DeviceMetadata {
+        let mut metadata = DeviceMetadata::new(self.serial_port.name(), self.port_type.as_ref());
+        metadata.max_freq = self.max_freq;
+        metadata
+    }
+
+    /// Safe frequency ceiling from the last state of the device, `None` if it reports none.
+    pub fn max_freq(&self) -> Option<u16> {
+        self.max_freq
+    }
+
+    /// The config currently in effect, including changes made after opening.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Bytes received and not read yet.
+    pub fn pending_bytes(&self) -> u32 {
+        self.input_pending().unwrap_or_else(|_| self.rx_buf.len()) as u32
+    }
+
+    /// Bytes waiting in the input buffer of the port and read ahead of the last
+    /// frame, an error where the platform cannot tell.
+    pub fn input_pending(&self) -> Result<usize> {
+        Ok(self.serial_port.bytes_to_read()? as usize + self.rx_buf.len())
+    }
+
+    /// Bytes received and not read yet. Where the platform cannot tell, wait up to
+    /// `timeout` for input and keep what arrives for the next read.
+    pub fn poll_input(&mut self, timeout: Duration) -> Result<usize> {
+        if let Ok(pending) = self.input_pending() {
+            return Ok(pending);
+        }
+        if !self.rx_buf.is_empty() {
+            return Ok(self.rx_buf.len());
+        }
+        self.serial_port.set_timeout(timeout)?;
+        let mut buf = [0u8; POLL_READ_LEN];
+        let read = self.serial_port.read(&mut buf);
+        self.serial_port.set_timeout(self.config.read_timeout)?;
+        match read {
+            Ok(n) => {
+                self.rx_buf.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Drop everything received and not read yet.
+    pub fn clear_input(&mut self) -> Result<()> {
+        self.rx_buf.clear();
+        self.serial_port.clear(ClearBuffer::Input)?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> DeriveStats {
+        self.stats
     }
 
     pub fn read(&mut self) -> Result<DeriveResponse> {
-        let mut raw_resp = vec![];
+        let resp = self.read_frame()?;
+        match resp {
+            Ok(resp) => {
+                self.framing_failures = 0;
+                if let DeriveResponse::State(state) = &resp {
+                    self.update_max_freq(state);
+                }
+                self.check_boot_mode(resp)
+            }
+            Err(e) => {
+                self.on_framing_failure();
+                Err(e)
+            }
+        }
+    }
+
+    // Outer error is an io failure, inner one a frame that could not be parsed.
+    fn read_frame(&mut self) -> Result<Result<DeriveResponse>> {
+        let raw_resp = self.read_raw()?;
+        Ok(DeriveResponse::parse(raw_resp, true, self.config.strict))
+    }
+
+    // Reads up to the first PKT_ENDER. Frames that arrive coalesced in one read are
+    // kept and returned one per call.
+    fn read_raw(&mut self) -> Result<Vec<u8>> {
+        if let Ok(pending) = self.input_pending() {
+            self.stats.input_peak = self.stats.input_peak.max(pending as u64);
+        }
+        let mut raw_resp = std::mem::take(&mut self.rx_buf);
+        if let Some(end) = frame_end(&raw_resp) {
+            self.rx_buf = raw_resp.split_off(end);
+            return Ok(raw_resp);
+        }
         let mut port_buf_reader = BufReader::new(&mut self.serial_port);
-        read_until(&mut port_buf_reader, &PKT_ENDER, raw_resp.as_mut())?;
-        DeriveResponse::new(raw_resp)
+        let read_until = if self.config.strict {
+            read_until_strict
+        } else {
+            read_until_limited
+        };
+        let read = read_until(
+            &mut port_buf_reader,
+            &PKT_ENDER,
+            raw_resp.as_mut(),
+            self.config.max_frame_len,
+        );
+        let read_ahead = port_buf_reader.buffer().to_vec();
+        if let Err(e) = read {
+            if e.get_ref().map_or(false, |e| e.is::<FrameTooLarge>()) {
+                // resync on whatever the device sends next
+                let _ = self.serial_port.clear(ClearBuffer::Input);
+            } else {
+                // a timeout may cut a frame short, the next read picks it up
+                raw_resp.extend_from_slice(&read_ahead);
+                self.rx_buf = raw_resp;
+            }
+            return Err(e.into());
+        }
+        self.rx_buf = read_ahead;
+        Ok(raw_resp)
+    }
+
+    /// Cheap liveness check, the device answers a state query within
+    /// `CommandTimeouts::ping`. The state itself is not decoded.
+    pub fn is_alive(&mut self) -> bool {
+        self.ping().is_ok()
+    }
+
+    /// Round trip time of a state query. Other frames read while waiting for the
+    /// answer, such as a solution, are kept for the next read.
+    pub fn ping(&mut self) -> Result<Duration> {
+        let msg = Message::get_state_msg();
+        let timeout = self.config.command_timeouts.ping;
+        self.serial_port.set_timeout(timeout)?;
+        let started = Instant::now();
+        let mut held = vec![];
+        let answer = match self.serial_port.write(&msg) {
+            Ok(_) => loop {
+                match self.read_raw() {
+                    Ok(raw) if is_state_frame(&raw) => break Ok(started.elapsed()),
+                    Ok(raw) if started.elapsed() < timeout => held.extend_from_slice(&raw),
+                    Ok(_) => break Err(anyhow::anyhow!("No state in answer to ping")),
+                    Err(e) => break Err(e),
+                }
+            },
+            Err(e) => Err(e.into()),
+        };
+        let _ = self.serial_port.set_timeout(self.config.read_timeout);
+        // ahead of anything read after them
+        if !held.is_empty() {
+            held.append(&mut self.rx_buf);
+            self.rx_buf = held;
+        }
+        answer
+    }
+
+    /// Set the timeout of command replies to `factor` times the ping latency, so a
+    /// slow board gets more slack and a fast one fails sooner.
+    pub fn adapt_ack_timeout(&mut self, factor: f64) -> Result<Duration> {
+        let latency = self.ping()?;
+        let timeout = latency.mul_f64(factor).max(MIN_ADAPTED_ACK_TIMEOUT);
+        self.config.ack_timeout = Some(timeout);
+        Ok(timeout)
+    }
+
+    fn on_framing_failure(&mut self) {
+        self.stats.framing_errors += 1;
+        self.framing_failures += 1;
+        if self.framing_failures < self.config.framing_failure_limit {
+            return;
+        }
+        self.framing_failures = 0;
+        warn!(
+            "{} consecutive framing failures, re-probing baud rate",
+            self.config.framing_failure_limit
+        );
+        match self.reprobe_baud() {
+            Ok(baud_rate) => {
+                info!("Link recovered at baud rate {}", baud_rate);
+                self.stats.baud_reprobes += 1;
+            }
+            Err(e) => warn!("Failed to re-probe baud rate: {:?}", e),
+        }
+    }
+
+    fn reprobe_baud(&mut self) -> Result<u32> {
+        let current = self.config.baud_rate;
+        let candidates: Vec<u32> = self
+            .config
+            .probe_baud_rates
+            .iter()
+            .copied()
+            .filter(|baud_rate| *baud_rate != current)
+            .collect();
+        for baud_rate in candidates {
+            self.serial_port.set_baud_rate(baud_rate)?;
+            let _ = self.serial_port.write(&Message::get_state_msg())?;
+            if let Ok(Ok(DeriveResponse::State(_))) = self.read_frame() {
+                self.config.baud_rate = baud_rate;
+                return Ok(baud_rate);
+            }
+        }
+        self.serial_port.set_baud_rate(current)?;
+        Err(anyhow::anyhow!("No baud rate answered the state probe"))
+    }
+    /// Send `msg` and read its response with `timeout`, or the adapted `ack_timeout`,
+    /// then restore the read timeout.
+    fn request(
+        &mut self,
+        msg: &[u8],
+        timeout: Duration,
+        framing: Framing,
+    ) -> Result<DeriveResponse> {
+        let timeout = self.config.ack_timeout.unwrap_or(timeout);
+        self.serial_port.set_timeout(timeout)?;
+        let resp = self
+            .serial_port
+            .write(msg)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| self.read_framed(framing));
+        self.serial_port.set_timeout(self.config.read_timeout)?;
+        resp
+    }
+
+    fn read_framed(&mut self, framing: Framing) -> Result<DeriveResponse> {
+        match framing {
+            Framing::Terminated => self.read(),
+            Framing::Fixed(len) => {
+                // bytes read ahead come first, as in `read_raw`
+                let mut raw_resp = std::mem::take(&mut self.rx_buf);
+                if raw_resp.len() > len {
+                    self.rx_buf = raw_resp.split_off(len);
+                }
+                let buffered = raw_resp.len();
+                raw_resp.resize(len, 0);
+                if let Err(e) = self.serial_port.read_exact(&mut raw_resp[buffered..]) {
+                    raw_resp.truncate(buffered);
+                    raw_resp.append(&mut self.rx_buf);
+                    self.rx_buf = raw_resp;
+                    return Err(e.into());
+                }
+                let resp = DeriveResponse::parse(raw_resp, false, self.config.strict)?;
+                self.check_boot_mode(resp)
+            }
+        }
     }
+
     pub fn get_state(&mut self) -> Result<State> {
         let msg = Message::get_state_msg();
-        let _ = self.serial_port.write(&msg)?;
-        match self.read()? {
-            DeriveResponse::State(state) => Ok(state),
+        match self.request(
+            &msg,
+            self.config.command_timeouts.get_state,
+            self.config.protocol.get_state,
+        )? {
+            DeriveResponse::State(state) => {
+                self.update_max_freq(&state);
+                Ok(state)
+            }
             resp => {
                 return Err(anyhow::anyhow!("Bad get state resp:{:?}", resp));
             }
         }
     }
+    /// The job and target the device has loaded.
+    pub fn current_target(&mut self) -> Result<JobTarget> {
+        let msg = Message::get_target_msg();
+        match self.request(
+            &msg,
+            self.config.command_timeouts.get_target,
+            self.config.protocol.get_target,
+        )? {
+            DeriveResponse::Target(target) => Ok(target),
+            resp => Err(anyhow::anyhow!("Bad target resp:{:?}", resp)),
+        }
+    }
+
+    /// Fails unless `Config::error_log_command` is set.
+    pub fn error_log(&mut self) -> Result<Vec<ErrorLogEntry>> {
+        if !self.config.error_log_command {
+            anyhow::bail!("Error log command is not enabled for this firmware");
+        }
+        let msg = Message::get_errlog_msg();
+        match self.request(
+            &msg,
+            self.config.command_timeouts.error_log,
+            self.config.protocol.error_log,
+        )? {
+            DeriveResponse::ErrorLog(entries) => Ok(entries),
+            resp => Err(anyhow::anyhow!("Bad error log resp:{:?}", resp)),
+        }
+    }
+
     pub fn write_state(&mut self) -> Result<()> {
         let msg = Message::get_state_msg();
         let _ = self.serial_port.write(&msg)?;
         Ok(())
     }
     pub fn set_hw_params(&mut self) -> Result<()> {
+        if self.config.quirks.contains(Quirks::IGNORES_HW_PARAMS) {
+            debug!("Firmware of {} ignores hw params, not sent", self.id());
+            return Ok(());
+        }
         let msg = Message::set_hw_params_msg(self.config.target_freq, self.config.target_voltage);
+        let acked = self.request(
+            &msg,
+            self.config.command_timeouts.set_hw_params,
+            self.ack_framing(self.config.protocol.set_hw_params),
+        );
+        fail_in_bootloader(acked)
+    }
+
+    fn ack_framing(&self, framing: Framing) -> Framing {
+        if self.config.quirks.contains(Quirks::UNTERMINATED_ACKS) {
+            Framing::Fixed(UNTERMINATED_ACK_LEN)
+        } else {
+            framing
+        }
+    }
+
+    /// Retune the running device. Values outside the config limits are rejected, a
+    /// frequency above the ceiling the device reported is clamped to it.
+    pub fn set_freq_voltage(&mut self, freq: u16, voltage: u16) -> Result<()> {
+        if self.config.quirks.contains(Quirks::IGNORES_HW_PARAMS) {
+            anyhow::bail!("Firmware of {} ignores hw params", self.id());
+        }
+        self.config.check_hw_params(freq, voltage)?;
+        let freq = match self.max_freq {
+            Some(max_freq) if freq > max_freq => {
+                warn!(
+                    "Frequency {} is above the safe ceiling {} of {}, clamped",
+                    freq,
+                    max_freq,
+                    self.id()
+                );
+                max_freq
+            }
+            _ => freq,
+        };
+        self.config.target_freq = freq;
+        self.config.target_voltage = voltage;
+        self.set_hw_params()
+    }
+
+    /// Start at `probe_freq` and go on to `freq` only once every core is good at it,
+    /// for boards that fail to start at full frequency. A failed probe sets the hw
+    /// params in effect before it again.
+    pub fn probe_freq_voltage(&mut self, probe_freq: u16, freq: u16, voltage: u16) -> Result<()> {
+        if self.config.quirks.contains(Quirks::IGNORES_HW_PARAMS) {
+            return self.set_hw_params();
+        }
+        let previous = (self.config.target_freq, self.config.target_voltage);
+        let probed = self.set_freq_voltage(probe_freq, voltage).and_then(|()| {
+            thread::sleep(self.config.probe_time);
+            let state = self.get_state()?;
+            if state.goodcores < state.cores {
+                anyhow::bail!(
+                    "{}/{} cores good at probe frequency {}, not raised to {}",
+                    state.goodcores,
+                    state.cores,
+                    probe_freq,
+                    freq
+                );
+            }
+            Ok(())
+        });
+        if let Err(e) = probed {
+            if let Err(restore) = self.set_freq_voltage(previous.0, previous.1) {
+                warn!(
+                    "Failed to restore hw params of {}: {:?}",
+                    self.id(),
+                    restore
+                );
+            }
+            return Err(e);
+        }
+        self.set_freq_voltage(freq, voltage)
+    }
+
+    pub fn set_freq(&mut self, freq: u16) -> Result<()> {
+        self.set_freq_voltage(freq, self.config.target_voltage)
+    }
+
+    pub fn set_voltage(&mut self, voltage: u16) -> Result<()> {
+        self.set_freq_voltage(self.config.target_freq, voltage)
+    }
+
+    /// Enable only the cores whose bit is set, to keep mining around a flaky core.
+    /// Fails unless `Config::core_mask_command` is set.
+    pub fn set_core_mask(&mut self, mask: u32) -> Result<()> {
+        if !self.config.core_mask_command {
+            anyhow::bail!("Core mask command is not enabled for this firmware");
+        }
+        let msg = Message::core_mask_msg(mask);
+        let acked = self.request(
+            &msg,
+            self.config.command_timeouts.core_mask,
+            self.ack_framing(self.config.protocol.core_mask),
+        );
+        fail_in_bootloader(acked)
+    }
+
+    fn update_max_freq(&mut self, state: &State) {
+        if state.max_freq.is_some() {
+            self.max_freq = state.max_freq;
+        }
+    }
+
+    pub fn set_job(&mut self, job_id: u8, target: u32, data: &[u8]) -> Result<()> {
+        self.write_job(job_id, &target.to_be_bytes(), 0, data)
+    }
+
+    /// `set_job` with the leading big endian bytes of the target, fit to the
+    /// `target_resolution` of the config: cut, or padded with zeros.
+    pub fn set_job_target(&mut self, job_id: u8, target: &[u8], data: &[u8]) -> Result<()> {
+        self.write_job(job_id, target, 0, data)
+    }
+
+    /// `set_job_target` that starts the search at `start_nonce` instead of zero.
+    pub fn set_job_target_from(
+        &mut self,
+        job_id: u8,
+        target: &[u8],
+        start_nonce: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        self.write_job(job_id, target, start_nonce, data)
+    }
+
+    /// Upload a job that starts once the running one is done,
+    /// a plain `set_job` if the firmware has no job queue.
+    pub fn queue_job(&mut self, job_id: u8, target: u32, data: &[u8]) -> Result<()> {
+        self.queue_job_target(job_id, &target.to_be_bytes(), data)
+    }
+
+    /// `queue_job` with the target as in `set_job_target`.
+    pub fn queue_job_target(&mut self, job_id: u8, target: &[u8], data: &[u8]) -> Result<()> {
+        self.queue_job_target_from(job_id, target, 0, data)
+    }
+
+    /// `queue_job_target` that starts the search at `start_nonce` instead of zero.
+    pub fn queue_job_target_from(
+        &mut self,
+        job_id: u8,
+        target: &[u8],
+        start_nonce: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        if !self.config.job_queueing {
+            return self.write_job(job_id, target, start_nonce, data);
+        }
+        let target = self.fit_target(target);
+        let msg = Message::queue_job_msg_target(job_id, &target, start_nonce, data);
         let _ = self.serial_port.write(&msg)?;
-        let _ = self.read();
+        self.idle.store(false, Ordering::Relaxed);
+        // a reboot empties the queue too, the queued job is the one to resend
+        self.active_job = Some(Message::write_job_msg_target(
+            job_id,
+            &target,
+            start_nonce,
+            data,
+        ));
         Ok(())
     }
 
-    pub fn set_job(&mut self, job_id: u8, target: u32, data: &[u8]) -> Result<()> {
-        let msg = Message::write_job_msg(job_id, target, data);
+    fn fit_target(&self, target: &[u8]) -> Vec<u8> {
+        let mut fitted = target.to_vec();
+        fitted.resize(self.config.target_resolution.bytes(), 0);
+        fitted
+    }
+
+    fn write_job(
+        &mut self,
+        job_id: u8,
+        target: &[u8],
+        start_nonce: u64,
+        data: &[u8],
+    ) -> Result<()> {
+        let target = self.fit_target(target);
+        let msg = Message::write_job_msg_target(job_id, &target, start_nonce, data);
         let _ = self.serial_port.write(&msg)?;
+        self.idle.store(false, Ordering::Relaxed);
+        self.active_job = Some(msg);
         Ok(())
     }
 
     pub fn set_opcode(&mut self) -> Result<()> {
         let msg = Message::opcode_msg();
+        if self.config.quirks.contains(Quirks::NO_OPCODE_ACK) {
+            let _ = self.serial_port.write(&msg)?;
+            return Ok(());
+        }
+        let acked = self.request(
+            &msg,
+            self.config.command_timeouts.set_opcode,
+            self.ack_framing(self.config.protocol.set_opcode),
+        );
+        fail_in_bootloader(acked)
+    }
+
+    /// Stop hashing and enter low power until `wake`, for gaps without work.
+    /// Fails unless `Config::power_mode` is set.
+    pub fn sleep(&mut self) -> Result<()> {
+        self.check_power_mode()?;
+        let _ = self.serial_port.write(&Message::sleep_msg())?;
+        Ok(())
+    }
+
+    pub fn wake(&mut self) -> Result<()> {
+        self.check_power_mode()?;
+        let _ = self.serial_port.write(&Message::wake_msg())?;
+        Ok(())
+    }
+
+    fn check_power_mode(&self) -> Result<()> {
+        if !self.config.power_mode {
+            anyhow::bail!("Power mode command is not enabled for this firmware");
+        }
+        Ok(())
+    }
+
+    pub fn identify(&mut self, duration: Duration) -> Result<()> {
+        let secs = duration.as_secs().try_into().unwrap_or(u16::MAX);
+        let msg = Message::identify_msg(secs);
         let _ = self.serial_port.write(&msg)?;
-        let _ = self.read();
         Ok(())
     }
 
+    // The bootloader answers every command with its own frame type, which would
+    // otherwise pass as an unknown response and keep the device retried forever.
+    // It runs no job either, so there is nothing to reboot on drop.
+    fn check_boot_mode(&self, resp: DeriveResponse) -> Result<DeriveResponse> {
+        match &resp {
+            DeriveResponse::Others(raw)
+                if raw[PKT_HEADER.len() + TYPE_OFFSET] == TYPE_RECV_BOOT_MODE =>
+            {
+                self.idle.store(true, Ordering::Relaxed);
+                Err(DeriveError::InBootloader.into())
+            }
+            _ => Ok(resp),
+        }
+    }
+
     pub fn reboot(&mut self) -> Result<()> {
         let msg = Message::reboot_msg();
         let _ = self.serial_port.write(&msg)?;
+        self.idle.store(true, Ordering::Relaxed);
         Ok(())
     }
 }
+
+/// Dropping the last handle on a port reboots a device that got a job, so the job
+/// left running does not greet the next process. Best effort, it blocks at most
+/// `QUIESCE_TIMEOUT`.
+impl Drop for UsbDerive {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.idle) > 1 || self.idle.load(Ordering::Relaxed) {
+            return;
+        }
+        let _ = self.serial_port.set_timeout(QUIESCE_TIMEOUT);
+        if let Err(e) = self.reboot() {
+            debug!("Failed to reboot {} on drop: {:?}", self.id(), e);
+        }
+    }
+}
+
+// End of the first frame in `buf`, just past its PKT_ENDER.
+fn frame_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(PKT_ENDER.len())
+        .position(|w| w == PKT_ENDER)
+        .map(|position| position + PKT_ENDER.len())
+}
+
+fn is_state_frame(raw: &[u8]) -> bool {
+    raw.ends_with(&PKT_ENDER)
+        && raw
+            .windows(PKT_HEADER.len())
+            .position(|w| w == PKT_HEADER)
+            .and_then(|location| raw.get(location + PKT_HEADER.len() + TYPE_OFFSET))
+            == Some(&TYPE_RECV_STATE)
+}
+
+// Acks are best effort, a device answering from its bootloader is the one failure
+// worth reporting.
+fn fail_in_bootloader(acked: Result<DeriveResponse>) -> Result<()> {
+    match acked {
+        Err(e) if UsbDerive::is_in_bootloader(&e) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockPort;
+    use crate::proto::tests::state_with_temp;
+
+    fn state_frame() -> Vec<u8> {
+        let mut frame = vec![0u8; 29];
+        frame[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 23]);
+        frame[26..].copy_from_slice(&PKT_ENDER);
+        frame
+    }
+
+    #[test]
+    fn test_should_throttle() {
+        let mut config = Config::default();
+        assert!(!config.should_throttle(&state_with_temp(60)));
+        assert!(config.should_throttle(&state_with_temp(85)));
+        assert!(config.should_throttle(&state_with_temp(0x00)));
+        assert!(config.should_throttle(&state_with_temp(0xFF)));
+
+        config.unknown_temp = UnknownTemp::Ignore;
+        assert!(!config.should_throttle(&state_with_temp(0x00)));
+        assert!(!config.should_throttle(&state_with_temp(0xFF)));
+        assert!(config.should_throttle(&state_with_temp(85)));
+    }
+
+    #[test]
+    fn test_command_timeouts() {
+        let port = MockPort::new();
+        let config = Config {
+            read_timeout: Duration::from_millis(100),
+            command_timeouts: CommandTimeouts {
+                ping: Duration::from_millis(5),
+                get_state: Duration::from_millis(10),
+                get_target: Duration::from_millis(15),
+                error_log: Duration::from_millis(20),
+                set_hw_params: Duration::from_millis(30),
+                set_opcode: Duration::from_millis(40),
+                core_mask: Duration::from_millis(50),
+            },
+            core_mask_command: true,
+            error_log_command: true,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+
+        assert!(derive.get_state().is_err());
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(10))
+        );
+        assert!(derive.current_target().is_err());
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(15))
+        );
+        assert!(derive.error_log().is_err());
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(20))
+        );
+        derive.set_hw_params().unwrap();
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(30))
+        );
+        derive.set_opcode().unwrap();
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(40))
+        );
+        derive.set_core_mask(0xff).unwrap();
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(50))
+        );
+
+        let _ = derive.read();
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_baud_reprobe() {
+        let port = MockPort::new();
+        let config = Config {
+            framing_failure_limit: 3,
+            probe_baud_rates: vec![115200, 230400],
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        let garbage = [0x11, 0x22, 0x33, 0x69, 0xc3, 0x5a];
+        let state = state_frame();
+
+        for _ in 0..2 {
+            port.push_response(&garbage);
+            assert!(derive.read().is_err());
+        }
+        assert_eq!(derive.stats().baud_reprobes, 0);
+
+        // the third failure re-probes, the device answers at 230400
+        port.push_response(&garbage);
+        port.push_response(&state);
+        assert!(derive.read().is_err());
+        assert_eq!(
+            derive.stats(),
+            DeriveStats {
+                framing_errors: 3,
+                baud_reprobes: 1,
+                input_peak: 35,
+            }
+        );
+        assert_eq!(port.baud_rate().unwrap(), 230400);
+        assert_eq!(derive.config.baud_rate, 230400);
+        assert_eq!(port.written().last().unwrap(), &Message::get_state_msg());
+    }
+
+    #[test]
+    fn test_freq_ceiling_clamps() {
+        let port = MockPort::new();
+        let config = Config {
+            command_timeouts: CommandTimeouts {
+                set_hw_params: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        let mut state = vec![0u8; 35];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 29]);
+        state[30..32].copy_from_slice(&650u16.to_le_bytes());
+        state[32..].copy_from_slice(&PKT_ENDER);
+        port.push_response(&state);
+        derive.get_state().unwrap();
+        assert_eq!(derive.max_freq(), Some(650));
+        assert_eq!(derive.metadata().max_freq, Some(650));
+
+        derive.set_freq_voltage(800, 760).unwrap();
+        assert_eq!(derive.config().target_freq, 650);
+        assert_eq!(
+            port.written().last().unwrap(),
+            &Message::set_hw_params_msg(650, 760)
+        );
+        derive.set_freq_voltage(600, 760).unwrap();
+        assert_eq!(derive.config().target_freq, 600);
+    }
+
+    #[test]
+    fn test_retune() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        derive.set_freq(550).unwrap();
+        derive.set_voltage(720).unwrap();
+        assert_eq!(
+            port.written(),
+            vec![
+                Message::set_hw_params_msg(550, 750),
+                Message::set_hw_params_msg(550, 720)
+            ]
+        );
+        assert_eq!(
+            (derive.config().target_freq, derive.config().target_voltage),
+            (550, 720)
+        );
+
+        // out of range values never reach the device
+        assert!(derive.set_freq(2000).is_err());
+        assert!(derive.set_voltage(100).is_err());
+        assert_eq!(port.written().len(), 2);
+        assert_eq!(derive.config().target_freq, 550);
+    }
+
+    #[test]
+    fn test_reboot_on_drop() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        derive.set_job(1, 0xffff_ffff, &[0u8; 76]).unwrap();
+        let clone = derive.clone();
+        drop(clone);
+        assert_eq!(port.written().len(), 1);
+        drop(derive);
+        assert_eq!(port.written().last().unwrap(), &Message::reboot_msg());
+        assert_eq!(port.settings().timeout, QUIESCE_TIMEOUT);
+
+        // not again after an explicit reboot, nor when the port is gone
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        derive.set_job(1, 0xffff_ffff, &[0u8; 76]).unwrap();
+        derive.reboot().unwrap();
+        drop(derive);
+        assert_eq!(port.written().len(), 2);
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        derive.set_job(1, 0xffff_ffff, &[0u8; 76]).unwrap();
+        port.fail_writes(1);
+        drop(derive);
+        assert_eq!(port.written().len(), 1);
+
+        // nor for a device that never got a job, e.g. one that failed to init
+        let port = MockPort::new();
+        drop(UsbDerive::from_port(port.boxed(), None, Config::default()));
+        assert!(port.written().is_empty());
+    }
+
+    #[test]
+    fn test_config_reflects_changes() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        assert_eq!(derive.config().target_freq, 600);
+
+        derive.set_freq_voltage(650, 800).unwrap();
+        assert_eq!(derive.config().target_freq, 650);
+        assert_eq!(derive.config().target_voltage, 800);
+        assert_eq!(
+            port.written().last().unwrap(),
+            &Message::set_hw_params_msg(650, 800)
+        );
+    }
+
+    #[test]
+    fn test_is_alive() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        assert!(!derive.is_alive());
+
+        let state = state_frame();
+        port.push_response(&state);
+        assert!(derive.is_alive());
+        assert_eq!(
+            port.read_timeouts().last(),
+            Some(&Duration::from_millis(200))
+        );
+        assert_eq!(port.timeout(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_ping_keeps_solution() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let mut nonce = vec![0u8; NONCE_FRAME_LEN];
+        nonce[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, TYPE_RECV_NONCE, 0x10, 50]);
+        nonce[NONCE_OFFSET..NONCE_OFFSET + 4].copy_from_slice(&0x1234u32.to_le_bytes());
+        nonce[NONCE_FRAME_LEN - PKT_ENDER.len()..].copy_from_slice(&PKT_ENDER);
+        port.push_response(&nonce);
+        port.push_response(&state_frame());
+        port.push_response(&nonce);
+
+        // the solution ahead of the answer is read after it all the same
+        assert!(derive.ping().is_ok());
+        for _ in 0..2 {
+            match derive.read().unwrap() {
+                DeriveResponse::SolvedJob(seal) => assert_eq!(seal.nonce, 0x1234),
+                resp => panic!("unexpected resp {:?}", resp),
+            }
+        }
+
+        // a solution alone is no answer
+        port.push_response(&nonce);
+        assert!(derive.ping().is_err());
+        assert!(matches!(derive.read(), Ok(DeriveResponse::SolvedJob(_))));
+    }
+
+    #[test]
+    fn test_limit_ports() {
+        let ports: Vec<SerialPortInfo> = (0..4)
+            .map(|i| SerialPortInfo {
+                port_name: format!("/dev/ttyACM{}", i),
+                port_type: SerialPortType::Unknown,
+            })
+            .collect();
+        assert_eq!(UsbDerive::limit_ports(ports.clone(), None).len(), 4);
+        assert_eq!(UsbDerive::limit_ports(ports.clone(), Some(8)).len(), 4);
+        let kept = UsbDerive::limit_ports(ports.clone(), Some(2));
+        assert_eq!(kept, ports[..2].to_vec());
+    }
+
+    #[test]
+    fn test_reconnect_by_serial() {
+        let usb_port = |serial: &str| {
+            SerialPortType::UsbPort(serialport::UsbPortInfo {
+                vid: 1155,
+                pid: 22336,
+                serial_number: Some(serial.to_string()),
+                manufacturer: None,
+                product: None,
+            })
+        };
+        let old_port = MockPort::new();
+        old_port.set_name("/dev/ttyACM0");
+        let mut derive =
+            UsbDerive::from_port(old_port.boxed(), Some("A1".to_string()), Config::default());
+
+        // after the disconnect A1 comes back on ttyACM2, another board took ttyACM0
+        let ports = vec![
+            SerialPortInfo {
+                port_name: "/dev/ttyACM0".to_string(),
+                port_type: usb_port("B2"),
+            },
+            SerialPortInfo {
+                port_name: "/dev/ttyACM2".to_string(),
+                port_type: usb_port("A1"),
+            },
+        ];
+        let new_port = MockPort::new();
+        new_port.set_name("/dev/ttyACM2");
+        let mut opened = None;
+        derive
+            .reconnect_from(&ports, |path| {
+                opened = Some(path.to_string());
+                Ok(new_port.boxed())
+            })
+            .unwrap();
+        assert_eq!(opened.as_deref(), Some("/dev/ttyACM2"));
+        assert_eq!(derive.metadata().port_name.as_deref(), Some("/dev/ttyACM2"));
+        assert_eq!(derive.id(), "A1");
+
+        derive.write_state().unwrap();
+        assert!(old_port.written().is_empty());
+        assert_eq!(new_port.written().len(), 1);
+
+        assert!(derive
+            .reconnect_from(&ports[..1], |_| Ok(new_port.boxed()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_reopen() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        assert!(derive.reopen().is_err());
+
+        derive.path = Some("/dev/ttyACM0".to_string());
+        port.fail_reads(1);
+        let err = derive.read().unwrap_err();
+        assert!(UsbDerive::is_link_error(&err));
+        let err = derive.read().unwrap_err();
+        assert!(!UsbDerive::is_link_error(&err));
+
+        let replugged = MockPort::new();
+        let mut opened = None;
+        derive
+            .reopen_with(|path| {
+                opened = Some(path.to_string());
+                Ok(replugged.boxed())
+            })
+            .unwrap();
+        assert_eq!(opened.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(
+            replugged.written(),
+            vec![Message::set_hw_params_msg(600, 750), Message::opcode_msg()]
+        );
+    }
+
+    #[test]
+    fn test_recover_by_serial() {
+        let port = MockPort::new();
+        let config = Config {
+            error_log_command: true,
+            ..Config::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), Some("A1".to_string()), config);
+        derive.path = Some("/dev/ttyACM0".to_string());
+
+        // ttyACM0 is gone, A1 came back on ttyACM1
+        let replugged = MockPort::new();
+        let mut errlog = vec![0xa5, 0x3c, 0x96, 0x5c, 0x10, 0x0d, 0x00, 0x00, 0x00, 0x01];
+        errlog.extend_from_slice(&7u16.to_le_bytes());
+        errlog.extend_from_slice(&120u32.to_le_bytes());
+        errlog.extend_from_slice(&[0x69, 0xc3, 0x5a]);
+        replugged.push_response(&errlog);
+        let opened = std::cell::RefCell::new(vec![]);
+        derive
+            .recover_with(
+                || {
+                    Ok(vec![SerialPortInfo {
+                        port_name: "/dev/ttyACM1".to_string(),
+                        port_type: SerialPortType::UsbPort(serialport::UsbPortInfo {
+                            vid: 1155,
+                            pid: 22336,
+                            serial_number: Some("A1".to_string()),
+                            manufacturer: None,
+                            product: None,
+                        }),
+                    }])
+                },
+                |path| {
+                    opened.borrow_mut().push(path.to_string());
+                    if path == "/dev/ttyACM0" {
+                        anyhow::bail!("No such device");
+                    }
+                    Ok(replugged.boxed())
+                },
+            )
+            .unwrap();
+        assert_eq!(*opened.borrow(), vec!["/dev/ttyACM0", "/dev/ttyACM1"]);
+        assert_eq!(derive.path.as_deref(), Some("/dev/ttyACM1"));
+        // the error log is read before the device is set up again
+        assert_eq!(
+            replugged.written(),
+            vec![
+                Message::get_errlog_msg(),
+                Message::set_hw_params_msg(600, 750),
+                Message::opcode_msg()
+            ]
+        );
+
+        // found at its path, it is not looked up
+        derive
+            .recover_with(|| unreachable!(), |_| Ok(replugged.boxed()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_recover_error_log_opt_in() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        derive.path = Some("/dev/ttyACM0".to_string());
+        assert!(derive.error_log().is_err());
+        assert!(port.written().is_empty());
+
+        // stock firmware is not asked for its error log
+        let replugged = MockPort::new();
+        derive
+            .recover_with(|| unreachable!(), |_| Ok(replugged.boxed()))
+            .unwrap();
+        assert_eq!(
+            replugged.written(),
+            vec![Message::set_hw_params_msg(600, 750), Message::opcode_msg()]
+        );
+
+        // an answer that is no error log does not upset the recovery
+        let config = Config {
+            error_log_command: true,
+            ..Config::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        derive.path = Some("/dev/ttyACM0".to_string());
+        let replugged = MockPort::new();
+        replugged.push_response(&[
+            0xa5, 0x3c, 0x96, 0x99, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+        ]);
+        replugged.push_response(&[0x00, 0x01, 0x02]);
+        derive
+            .recover_with(|| unreachable!(), |_| Ok(replugged.boxed()))
+            .unwrap();
+        assert_eq!(
+            replugged.written(),
+            vec![
+                Message::get_errlog_msg(),
+                Message::set_hw_params_msg(600, 750),
+                Message::opcode_msg()
+            ]
+        );
+        assert_eq!(derive.input_pending().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reinit_after_reboot() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        derive.reinit().unwrap();
+        assert_eq!(
+            port.written(),
+            vec![Message::set_hw_params_msg(600, 750), Message::opcode_msg()]
+        );
+
+        let data = [0x05u8; 76];
+        derive.set_job(3, 0x1234, &data).unwrap();
+        derive.reboot().unwrap();
+        let written = port.written().len();
+        derive.reinit().unwrap();
+        assert_eq!(
+            port.written()[written..].to_vec(),
+            vec![
+                Message::set_hw_params_msg(600, 750),
+                Message::opcode_msg(),
+                Message::write_job_msg(3, 0x1234, &data)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_core_mask_opt_in() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        assert!(derive.set_core_mask(0xff).is_err());
+        assert!(port.written().is_empty());
+    }
+
+    #[test]
+    fn test_reinit_hook() {
+        let port = MockPort::new();
+        let config = Config {
+            core_mask_command: true,
+            ..Config::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        derive.set_init_hook(Arc::new(|derive: &mut UsbDerive| {
+            derive.set_core_mask(0xff)?;
+            derive.set_hw_params()
+        }));
+        let data = [0x05u8; 76];
+        derive.set_job(3, 0x1234, &data).unwrap();
+        let written = port.written().len();
+        derive.reinit().unwrap();
+        assert_eq!(
+            port.written()[written..].to_vec(),
+            vec![
+                Message::core_mask_msg(0xff),
+                Message::set_hw_params_msg(600, 750),
+                Message::write_job_msg(3, 0x1234, &data)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_short_state() {
+        let port = MockPort::new();
+        // a state frame cut short, its length field still matching
+        let mut frame = vec![0u8; 25];
+        frame[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 19]);
+        frame[22..].copy_from_slice(&PKT_ENDER);
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        port.push_response(&frame);
+        port.push_response(&state_frame());
+        assert!(derive.read().is_err());
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+    }
+
+    #[test]
+    fn test_fixed_length_ack() {
+        let port = MockPort::new();
+        // firmware that sends the state without PKT_ENDER
+        let mut state = state_frame();
+        state.truncate(26);
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        port.push_response(&state);
+        assert!(derive.get_state().is_err());
+
+        let mut config = Config::default();
+        config.protocol.get_state = Framing::Fixed(26);
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        port.push_response(&state);
+        port.push_response(&state_frame());
+        assert!(derive.get_state().is_ok());
+        // the next frame is left intact
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+    }
+
+    #[test]
+    fn test_fixed_length_read_ahead() {
+        let port = MockPort::new();
+        let mut config = Config::default();
+        config.protocol.get_state = Framing::Fixed(26);
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        // the unterminated state arrives in one read with the frame before it
+        let mut state = state_frame();
+        state.truncate(26);
+        let mut coalesced = state_frame();
+        coalesced.extend_from_slice(&state);
+        port.push_response(&coalesced);
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert!(derive.get_state().is_ok());
+
+        // part of it read ahead, the rest still on the port
+        let (head, tail) = state.split_at(10);
+        let mut coalesced = state_frame();
+        coalesced.extend_from_slice(head);
+        port.push_response(&coalesced);
+        port.push_response(tail);
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert!(derive.get_state().is_ok());
+    }
+
+    #[test]
+    fn test_quirk_no_opcode_ack() {
+        let port = MockPort::new();
+        let config = Config {
+            quirks: Quirks::NO_OPCODE_ACK,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        derive.set_opcode().unwrap();
+        assert_eq!(port.written(), vec![Message::opcode_msg()]);
+        assert_eq!(port.reads(), 0);
+    }
+
+    #[test]
+    fn test_power_mode_opt_in() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        assert!(derive.sleep().is_err());
+        assert!(derive.wake().is_err());
+        assert!(port.written().is_empty());
+
+        let config = Config {
+            power_mode: true,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        derive.sleep().unwrap();
+        derive.wake().unwrap();
+        assert_eq!(
+            port.written(),
+            vec![Message::sleep_msg(), Message::wake_msg()]
+        );
+    }
+
+    #[test]
+    fn test_quirk_unterminated_acks() {
+        let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00];
+        let port = MockPort::new();
+        let config = Config {
+            quirks: Quirks::UNTERMINATED_ACKS,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        // the ack and the following state arrive together
+        let mut chunk = ack.to_vec();
+        chunk.extend_from_slice(&state_frame());
+        port.push_response(&chunk);
+        derive.set_opcode().unwrap();
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+    }
+
+    #[test]
+    fn test_quirk_ignores_hw_params() {
+        let port = MockPort::new();
+        let config = Config {
+            quirks: Quirks::IGNORES_HW_PARAMS | Quirks::NO_OPCODE_ACK,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        derive.set_hw_params().unwrap();
+        assert!(derive.set_freq_voltage(700, 800).is_err());
+        assert_eq!(derive.config().target_freq, 600);
+        assert!(port.written().is_empty());
+        assert!(derive.config().quirks.contains(Quirks::NO_OPCODE_ACK));
+        assert!(!derive.config().quirks.contains(Quirks::UNTERMINATED_ACKS));
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        let port = MockPort::new();
+        let config = Config {
+            max_frame_len: 32,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        port.push_response(&[0x11; 100]);
+        port.push_response(&[0x22; 100]);
+
+        let err = derive.read().unwrap_err();
+        assert!(err.to_string().contains("Frame too large"));
+        // the rest of the stream is dropped and the next frame parses
+        port.push_response(&state_frame());
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+    }
+
+    #[test]
+    fn test_strict_read() {
+        let port = MockPort::new();
+        let config = Config {
+            strict: true,
+            ..Default::default()
+        };
+        let mut derive = UsbDerive::from_port(port.boxed(), None, config);
+        port.push_response(&state_frame());
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+
+        let mut noisy = vec![0x00];
+        noisy.extend(state_frame());
+        port.push_response(&noisy);
+        assert!(derive.read().is_err());
+        assert_eq!(derive.stats().framing_errors, 1);
+    }
+
+    #[test]
+    fn test_in_bootloader() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        // a missing ack is still ignored
+        assert!(derive.set_hw_params().is_ok());
+
+        let boot_frame = [
+            0xa5,
+            0x3c,
+            0x96,
+            TYPE_RECV_BOOT_MODE,
+            0x10,
+            0x06,
+            0x00,
+            0x00,
+            0x00,
+            0x69,
+            0xc3,
+            0x5a,
+        ];
+        port.push_response(&boot_frame);
+        let err = derive.set_hw_params().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DeriveError>(),
+            Some(&DeriveError::InBootloader)
+        );
+        assert!(err.to_string().contains("bootloader mode"), "{}", err);
+        port.push_response(&boot_frame);
+        assert!(UsbDerive::is_in_bootloader(
+            &derive.set_opcode().unwrap_err()
+        ));
+        derive.config.core_mask_command = true;
+        port.push_response(&boot_frame);
+        assert!(UsbDerive::is_in_bootloader(
+            &derive.set_core_mask(0xff).unwrap_err()
+        ));
+        derive.set_job(1, 0xffff_ffff, &[0u8; 76]).unwrap();
+        port.push_response(&boot_frame);
+        assert!(UsbDerive::is_in_bootloader(&derive.read().unwrap_err()));
+        assert_eq!(derive.stats().framing_errors, 0);
+
+        // the bootloader runs no job, dropping the device sends no reboot
+        let written = port.written().len();
+        drop(derive);
+        assert_eq!(port.written().len(), written);
+    }
+
+    #[test]
+    fn test_nonce_hash_len() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let nonce_frame = |hash_len: usize| {
+            let mut frame = vec![0u8; NONCE_HASH_OFFSET];
+            frame[..5].copy_from_slice(&[0xa5, 0x3c, 0x96, TYPE_RECV_NONCE, 0x10]);
+            frame.extend(vec![0x11; hash_len]);
+            frame.extend_from_slice(&PKT_ENDER);
+            frame[5] = (frame.len() - PKT_HEADER.len() - PKT_ENDER.len()) as u8;
+            frame
+        };
+
+        for hash_len in [0, 31, 33, 64].iter() {
+            port.push_response(&nonce_frame(*hash_len));
+            assert!(derive.read().is_err());
+        }
+        assert_eq!(derive.stats().framing_errors, 4);
+
+        port.push_response(&nonce_frame(HASH_LEN));
+        match derive.read().unwrap() {
+            DeriveResponse::SolvedJob(seal) => assert_eq!(seal.hash, [0x11; HASH_LEN]),
+            resp => panic!("expect a solution, got {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_coalesced_frames() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let mut nonce_frame = vec![0u8; NONCE_FRAME_LEN];
+        nonce_frame[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, TYPE_RECV_NONCE, 0x10, 50]);
+        nonce_frame[NONCE_JOB_ID_OFFSET] = 3;
+        nonce_frame[NONCE_FRAME_LEN - PKT_ENDER.len()..].copy_from_slice(&PKT_ENDER);
+        // two frames and the start of a third in one usb packet
+        let mut packet = state_frame();
+        packet.extend_from_slice(&nonce_frame);
+        packet.extend_from_slice(&state_frame()[..10]);
+        port.push_response(&packet);
+        port.push_response(&state_frame()[10..]);
+
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert!(derive.pending_bytes() as usize > NONCE_FRAME_LEN);
+        match derive.read().unwrap() {
+            DeriveResponse::SolvedJob(seal) => assert_eq!(seal.job_id, 3),
+            resp => panic!("expect a solution, got {:?}", resp),
+        }
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert_eq!(derive.pending_bytes(), 0);
+        assert_eq!(derive.stats().framing_errors, 0);
+    }
+
+    #[test]
+    fn test_frame_cut_by_timeout() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        port.push_response(&state_frame()[..10]);
+        assert!(derive.read().is_err());
+        assert_eq!(derive.pending_bytes(), 10);
+
+        // the rest arrives for the next read, the frame is read whole
+        port.push_response(&state_frame()[10..]);
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert_eq!(derive.pending_bytes(), 0);
+        assert_eq!(derive.stats().framing_errors, 0);
+    }
+
+    #[test]
+    fn test_input_pending() {
+        let port = MockPort::new();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        assert_eq!(derive.input_pending().unwrap(), 0);
+        port.push_response(&state_frame());
+        port.push_response(&state_frame());
+        assert_eq!(derive.input_pending().unwrap(), 58);
+
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert_eq!(derive.input_pending().unwrap(), 29);
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert_eq!(derive.input_pending().unwrap(), 0);
+        assert_eq!(derive.stats().input_peak, 58);
+    }
+
+    #[test]
+    fn test_poll_input_without_pending_count() {
+        let port = MockPort::new();
+        port.hide_pending();
+        let mut derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let timeout = Duration::from_millis(1);
+        assert!(derive.input_pending().is_err());
+        assert_eq!(derive.poll_input(timeout).unwrap(), 0);
+
+        port.push_response(&state_frame());
+        assert!(derive.poll_input(timeout).unwrap() > 0);
+        assert!(matches!(derive.read(), Ok(DeriveResponse::State(_))));
+        assert_eq!(derive.poll_input(timeout).unwrap(), 0);
+        assert_eq!(port.read_timeouts()[0], timeout);
+        assert_eq!(derive.serial_port.timeout(), derive.config.read_timeout);
+
+        port.fail_reads(1);
+        let e = derive.poll_input(timeout).unwrap_err();
+        assert!(UsbDerive::is_link_error(&e));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let port = MockPort::new();
+        let derive = UsbDerive::from_port(port.boxed(), None, Config::default());
+        let metadata = derive.metadata();
+        assert_eq!(metadata.port_name.as_deref(), Some("mock"));
+        assert_eq!(metadata.port_kind, PortKind::Unknown);
+        assert_eq!(metadata.os, std::env::consts::OS);
+
+        let usb_port = SerialPortType::UsbPort(serialport::UsbPortInfo {
+            vid: 1155,
+            pid: 22336,
+            serial_number: Some("A1".to_string()),
+            manufacturer: Some("STMicroelectronics".to_string()),
+            product: None,
+        });
+        let metadata = DeviceMetadata::new(Some("/dev/ttyACM0".to_string()), Some(&usb_port));
+        assert_eq!(metadata.port_kind, PortKind::Usb);
+        assert_eq!(metadata.vid, Some(1155));
+        assert_eq!(metadata.pid, Some(22336));
+        assert_eq!(metadata.manufacturer.as_deref(), Some("STMicroelectronics"));
+        assert_eq!(metadata.product, None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Connected(String),
+    Disconnected(String),
+}
+
+/// Coalesces connect/disconnect bursts during USB bus resets.
+///
+/// A disconnect is held back for `window`; if the same serial reconnects
+/// within it both events are dropped, since the device never really left.
+#[derive(Clone, Debug)]
+pub struct EventDebouncer {
+    window: Duration,
+    pending: HashMap<String, Instant>,
+}
+
+impl EventDebouncer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Feed a raw event, returns the events that should be acted on now.
+    pub fn push(&mut self, event: DeviceEvent, now: Instant) -> Vec<DeviceEvent> {
+        let mut ready = self.poll(now);
+        match event {
+            DeviceEvent::Disconnected(serial) => {
+                self.pending.entry(serial).or_insert(now);
+            }
+            DeviceEvent::Connected(serial) => {
+                if self.pending.remove(&serial).is_none() {
+                    ready.push(DeviceEvent::Connected(serial));
+                }
+            }
+        }
+        ready
+    }
+
+    /// Release disconnects that were not followed by a reconnect within the window.
+    pub fn poll(&mut self, now: Instant) -> Vec<DeviceEvent> {
+        let window = self.window;
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, at)| now.saturating_duration_since(**at) >= window)
+            .map(|(serial, _)| serial.clone())
+            .collect();
+        expired
+            .into_iter()
+            .map(|serial| {
+                self.pending.remove(&serial);
+                DeviceEvent::Disconnected(serial)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_within_window_is_noop() {
+        let start = Instant::now();
+        let mut debouncer = EventDebouncer::new(Duration::from_millis(500));
+        let serial = "A1".to_string();
+
+        let events = debouncer.push(DeviceEvent::Disconnected(serial.clone()), start);
+        assert!(events.is_empty());
+        let events = debouncer.push(
+            DeviceEvent::Connected(serial),
+            start + Duration::from_millis(100),
+        );
+        assert!(events.is_empty());
+        assert!(debouncer.poll(start + Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_disconnect_released_after_window() {
+        let start = Instant::now();
+        let mut debouncer = EventDebouncer::new(Duration::from_millis(500));
+        let serial = "A1".to_string();
+
+        assert!(debouncer
+            .push(DeviceEvent::Disconnected(serial.clone()), start)
+            .is_empty());
+        assert!(debouncer
+            .poll(start + Duration::from_millis(100))
+            .is_empty());
+        assert_eq!(
+            debouncer.poll(start + Duration::from_millis(600)),
+            vec![DeviceEvent::Disconnected(serial.clone())]
+        );
+        assert_eq!(
+            debouncer.push(
+                DeviceEvent::Connected(serial.clone()),
+                start + Duration::from_millis(700)
+            ),
+            vec![DeviceEvent::Connected(serial)]
+        );
+    }
+}
@@ -1,13 +1,23 @@
 #[allow(dead_code)]
 mod constants;
+pub mod controller;
 pub mod derive;
+pub mod hotplug;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 mod proto;
 mod tests;
 
-pub use derive::{Config, UsbDerive};
-pub use proto::DeriveResponse;
+pub use derive::{
+    CommandTimeouts, Config, DeriveStats, DeviceMetadata, Framing, InitHook, PortKind,
+    ProtocolProfile, Quirks, SubmitPolicy, TargetResolution, TargetRounding, UnknownResponse,
+    UnknownTemp, UsbDerive,
+};
+pub use proto::{DeriveResponse, ErrorLogEntry, JobTarget, Message, State};
+pub use serialport::{SerialPortInfo, SerialPortType};
+use std::fmt;
 use std::io;
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 
 #[macro_export]
 macro_rules! proto_msg {
@@ -22,27 +32,97 @@ macro_rules! proto_msg {
     };
 }
 
-pub(crate) fn read_until(
+/// A device sent more than the frame size limit without a frame terminator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Frame too large, no terminator in {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+/// Device states that no retry gets out of, wrapped in the `anyhow::Error` of the
+/// command that ran into them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeriveError {
+    /// The device answers from its bootloader, e.g. after an interrupted firmware
+    /// update, and ignores mining commands until it is flashed again.
+    InBootloader,
+}
+
+impl fmt::Display for DeriveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeriveError::InBootloader => {
+                f.write_str("Device is in bootloader mode, flash the firmware to mine with it")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeriveError {}
+
+pub fn read_until(
+    buf_reader: &mut dyn BufRead,
+    delim: &[u8],
+    buf: &mut Vec<u8>,
+) -> io::Result<usize> {
+    read_until_limited(buf_reader, delim, buf, usize::MAX)
+}
+
+/// `read_until` that fails with an `InvalidData` error wrapping `FrameTooLarge`
+/// once `max_len` bytes are read without `delim`. Bytes read ahead of an error
+/// are left in `buf`.
+pub fn read_until_limited(
     buf_reader: &mut dyn BufRead,
     delim: &[u8],
     buf: &mut Vec<u8>,
+    max_len: usize,
 ) -> io::Result<usize> {
     let mut total_n = 0;
     loop {
-        let mut tmp_buf = vec![];
-        let n = buf_reader.read_until(delim[delim.len() - 1], tmp_buf.as_mut())?;
-        total_n += n;
-        buf.extend_from_slice(tmp_buf.as_slice());
-        if n <= delim.len() {
-            break;
+        if total_n >= max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                FrameTooLarge { limit: max_len },
+            ));
         }
-        if &tmp_buf[n - delim.len()..] == delim {
+        let mut limited = (&mut *buf_reader).take((max_len - total_n) as u64);
+        let n = limited.read_until(delim[delim.len() - 1], buf)?;
+        total_n += n;
+        // a short read is only the end of the stream if nothing came at all, the
+        // last byte of `delim` may be in the data too
+        if n == 0 || buf.ends_with(delim) {
             break;
         }
     }
     Ok(total_n)
 }
 
+/// `read_until_limited` that fails with an `UnexpectedEof` error when the stream
+/// ends ahead of `delim`, rather than returning the partial frame.
+pub fn read_until_strict(
+    buf_reader: &mut dyn BufRead,
+    delim: &[u8],
+    buf: &mut Vec<u8>,
+    max_len: usize,
+) -> io::Result<usize> {
+    let start = buf.len();
+    let n = read_until_limited(buf_reader, delim, buf, max_len)?;
+    if !buf.ends_with(delim) {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Frame ended without terminator: {:x?}", &buf[start..]),
+        ));
+    }
+    Ok(n)
+}
+
 #[test]
 fn test_read_until() {
     let mut buf = vec![];
@@ -60,3 +140,43 @@ fn test_read_until() {
     assert_eq!(1, n);
     assert_eq!(b"a".to_vec(), buf);
 }
+
+#[test]
+fn test_read_until_limited() {
+    let mut buf = vec![];
+    let stream = vec![0x11u8; 100];
+    let err =
+        read_until_limited(&mut io::Cursor::new(&stream), b"cd", buf.as_mut(), 32).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let too_large = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<FrameTooLarge>())
+        .unwrap();
+    assert_eq!(too_large.limit, 32);
+    assert_eq!(buf.len(), 32);
+
+    let mut buf = vec![];
+    let n = read_until_limited(&mut io::Cursor::new(b"abcdef"), b"cd", buf.as_mut(), 4).unwrap();
+    assert_eq!(4, n);
+    assert_eq!(b"abcd".to_vec(), buf);
+
+    // the last delimiter byte early in the data does not end the frame
+    let mut buf = vec![];
+    let n = read_until_limited(&mut io::Cursor::new(b"adxxcdef"), b"cd", buf.as_mut(), 32).unwrap();
+    assert_eq!(6, n);
+    assert_eq!(b"adxxcd".to_vec(), buf);
+}
+
+#[test]
+fn test_read_until_strict() {
+    let mut buf = vec![];
+    let n = read_until_strict(&mut io::Cursor::new(b"abcdef"), b"cd", buf.as_mut(), 32).unwrap();
+    assert_eq!(4, n);
+    assert_eq!(b"abcd".to_vec(), buf);
+
+    // the partial frame read_until returns is an error here
+    let mut buf = vec![];
+    let err =
+        read_until_strict(&mut io::Cursor::new(b"abdef"), b"cd", buf.as_mut(), 32).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
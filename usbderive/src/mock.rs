@@ -0,0 +1,243 @@
+use parking_lot::Mutex;
+use serialport::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits,
+};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Inner {
+    input: VecDeque<Vec<u8>>,
+    written: Vec<Vec<u8>>,
+    reads: usize,
+    polls: usize,
+    read_timeouts: Vec<Duration>,
+    settings: Option<SerialPortSettings>,
+    name: Option<String>,
+    failing_writes: usize,
+    failing_reads: usize,
+    read_delay: Duration,
+    hide_pending: bool,
+}
+
+/// In-memory serial port, replays queued responses and records every write.
+///
+/// Like a USB packet, a read never returns bytes of more than one queued response.
+#[derive(Clone, Default)]
+pub struct MockPort {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MockPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Port name reported from now on, "mock" by default.
+    pub fn set_name(&self, name: &str) {
+        self.inner.lock().name = Some(name.to_string());
+    }
+
+    /// Make every read take this long, like a slow link.
+    pub fn set_read_delay(&self, delay: Duration) {
+        self.inner.lock().read_delay = delay;
+    }
+
+    /// Fail `bytes_to_read` from now on, like a platform that cannot tell.
+    pub fn hide_pending(&self) {
+        self.inner.lock().hide_pending = true;
+    }
+
+    /// Fail the next `count` writes with a broken pipe.
+    pub fn fail_writes(&self, count: usize) {
+        self.inner.lock().failing_writes = count;
+    }
+
+    /// Fail the next `count` reads with a broken pipe, like an unplugged device.
+    pub fn fail_reads(&self, count: usize) {
+        self.inner.lock().failing_reads = count;
+    }
+
+    pub fn push_response(&self, frame: &[u8]) {
+        self.inner.lock().input.push_back(frame.to_vec());
+    }
+
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.inner.lock().written.clone()
+    }
+
+    pub fn reads(&self) -> usize {
+        self.inner.lock().reads
+    }
+
+    /// Times `bytes_to_read` was asked.
+    pub fn polls(&self) -> usize {
+        self.inner.lock().polls
+    }
+
+    /// Port timeout in effect at each read.
+    pub fn read_timeouts(&self) -> Vec<Duration> {
+        self.inner.lock().read_timeouts.clone()
+    }
+
+    pub fn boxed(&self) -> Box<dyn SerialPort> {
+        Box::new(self.clone())
+    }
+}
+
+impl io::Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let delay = self.inner.lock().read_delay;
+        if delay > Duration::from_secs(0) {
+            std::thread::sleep(delay);
+        }
+        let mut inner = self.inner.lock();
+        inner.reads += 1;
+        let timeout = inner.settings.unwrap_or_default().timeout;
+        inner.read_timeouts.push(timeout);
+        if inner.failing_reads > 0 {
+            inner.failing_reads -= 1;
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "mock port read failed",
+            ));
+        }
+        let chunk = match inner.input.front_mut() {
+            Some(chunk) => chunk,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "mock port timed out",
+                ))
+            }
+        };
+        let n = buf.len().min(chunk.len());
+        buf[..n].copy_from_slice(&chunk[..n]);
+        chunk.drain(..n);
+        if chunk.is_empty() {
+            inner.input.pop_front();
+        }
+        Ok(n)
+    }
+}
+
+impl io::Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock();
+        if inner.failing_writes > 0 {
+            inner.failing_writes -= 1;
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "mock port write failed",
+            ));
+        }
+        inner.written.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for MockPort {
+    fn name(&self) -> Option<String> {
+        Some(
+            self.inner
+                .lock()
+                .name
+                .clone()
+                .unwrap_or_else(|| "mock".to_string()),
+        )
+    }
+    fn settings(&self) -> SerialPortSettings {
+        self.inner.lock().settings.unwrap_or_default()
+    }
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings().baud_rate)
+    }
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings().data_bits)
+    }
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings().flow_control)
+    }
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings().parity)
+    }
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings().stop_bits)
+    }
+    fn timeout(&self) -> Duration {
+        self.settings().timeout
+    }
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.inner.lock().settings = Some(*settings);
+        Ok(())
+    }
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        let mut settings = self.settings();
+        settings.baud_rate = baud_rate;
+        self.set_all(&settings)
+    }
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_parity(&mut self, _parity: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        let mut settings = self.settings();
+        settings.timeout = timeout;
+        self.set_all(&settings)
+    }
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        let mut inner = self.inner.lock();
+        inner.polls += 1;
+        if inner.hide_pending {
+            return Err(serialport::Error::new(
+                serialport::ErrorKind::Unknown,
+                "mock port cannot tell pending bytes",
+            ));
+        }
+        Ok(inner.input.iter().map(|c| c.len() as u32).sum())
+    }
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        if !matches!(buffer_to_clear, ClearBuffer::Output) {
+            self.inner.lock().input.clear();
+        }
+        Ok(())
+    }
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(self.boxed())
+    }
+}
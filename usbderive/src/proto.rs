@@ -1,6 +1,7 @@
 use crate::{constants::*, proto_msg};
 use anyhow::Result;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::Serialize;
 use std::convert::TryInto;
 use std::io::Cursor;
 use std::time::{Duration, SystemTime};
@@ -37,24 +38,63 @@ impl Message {
         )
     }
     pub fn write_job_msg(job_id: u8, target: u32, data: &[u8]) -> Vec<u8> {
-        let mut target_b = vec![];
-        target_b.write_u32::<LittleEndian>(target).unwrap();
+        Self::write_job_msg_from(job_id, target, 0, data)
+    }
+
+    /// Job the device starts once the running one is done.
+    pub fn queue_job_msg(job_id: u8, target: u32, data: &[u8]) -> Vec<u8> {
+        Self::queue_job_msg_target(job_id, &target.to_be_bytes(), 0, data)
+    }
+
+    /// Job that starts the search at `start_nonce` instead of zero.
+    pub fn write_job_msg_from(job_id: u8, target: u32, start_nonce: u64, data: &[u8]) -> Vec<u8> {
+        Self::write_job_msg_target(job_id, &target.to_be_bytes(), start_nonce, data)
+    }
+
+    /// Job with a target of any width, given as the leading big endian bytes of
+    /// the 256-bit target. Like the u32 one it is sent little endian.
+    pub fn write_job_msg_target(
+        job_id: u8,
+        target: &[u8],
+        start_nonce: u64,
+        data: &[u8],
+    ) -> Vec<u8> {
+        Self::job_msg(JOB_NUM_REPLACE, job_id, target, start_nonce, data)
+    }
 
+    /// `queue_job_msg` with a target as in `write_job_msg_target`.
+    pub fn queue_job_msg_target(
+        job_id: u8,
+        target: &[u8],
+        start_nonce: u64,
+        data: &[u8],
+    ) -> Vec<u8> {
+        Self::job_msg(JOB_NUM_QUEUE, job_id, target, start_nonce, data)
+    }
+
+    fn job_msg(job_num: u8, job_id: u8, target: &[u8], start_nonce: u64, data: &[u8]) -> Vec<u8> {
+        let target_b: Vec<u8> = target.iter().rev().copied().collect();
+
+        // type, pv, len and the job fields around the target ahead of the data
         let mut pktlen = vec![];
-        pktlen.write_u32::<LittleEndian>(104).unwrap();
+        pktlen
+            .write_u32::<LittleEndian>((24 + target.len() + data.len()) as u32)
+            .unwrap();
 
-        let start_nonce: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
+        let mut start_nonce_b = vec![];
+        start_nonce_b
+            .write_u64::<LittleEndian>(start_nonce)
+            .unwrap();
         let end_nonce: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
-        let job_num = [1];
         proto_msg!(
             PKT_HEADER,
             [TYPE_SEND_WORK],
             [PV],
             pktlen,
             target_b,
-            start_nonce,
+            start_nonce_b,
             end_nonce,
-            job_num,
+            [job_num],
             [job_id],
             data,
             PKT_ENDER
@@ -85,6 +125,76 @@ impl Message {
         )
     }
 
+    pub fn get_errlog_msg() -> Vec<u8> {
+        proto_msg!(
+            PKT_HEADER,
+            [TYPE_SET_HWPARAMS],
+            [PV],
+            [0x7, 0x0, 0x0, 0x0],
+            [TYPE_RECV_ERRLOG],
+            PKT_ENDER
+        )
+    }
+
+    pub fn identify_msg(duration_secs: u16) -> Vec<u8> {
+        let mut duration_b = vec![];
+        duration_b.write_u16::<LittleEndian>(duration_secs).unwrap();
+        proto_msg!(
+            PKT_HEADER,
+            [TYPE_SET_LED],
+            [PV],
+            [0x9, 0x0, 0x0, 0x0],
+            [LED_MODE_BLINK],
+            duration_b,
+            PKT_ENDER
+        )
+    }
+
+    pub fn core_mask_msg(mask: u32) -> Vec<u8> {
+        let mut mask_b = vec![];
+        mask_b.write_u32::<LittleEndian>(mask).unwrap();
+        proto_msg!(
+            PKT_HEADER,
+            [TYPE_SET_HWPARAMS],
+            [PV],
+            [0xb, 0x0, 0x0, 0x0],
+            [CMD_CORE_MASK],
+            mask_b,
+            PKT_ENDER
+        )
+    }
+
+    pub fn sleep_msg() -> Vec<u8> {
+        Self::power_mode_msg(POWER_MODE_SLEEP)
+    }
+
+    pub fn wake_msg() -> Vec<u8> {
+        Self::power_mode_msg(POWER_MODE_WAKE)
+    }
+
+    fn power_mode_msg(mode: u8) -> Vec<u8> {
+        proto_msg!(
+            PKT_HEADER,
+            [TYPE_SET_HWPARAMS],
+            [PV],
+            [0x8, 0x0, 0x0, 0x0],
+            [CMD_POWER_MODE],
+            [mode],
+            PKT_ENDER
+        )
+    }
+
+    pub fn get_target_msg() -> Vec<u8> {
+        proto_msg!(
+            PKT_HEADER,
+            [TYPE_SET_HWPARAMS],
+            [PV],
+            [0x7, 0x0, 0x0, 0x0],
+            [TYPE_RECV_TARGET],
+            PKT_ENDER
+        )
+    }
+
     pub fn get_state_msg() -> Vec<u8> {
         proto_msg!(
             PKT_HEADER,
@@ -97,7 +207,7 @@ impl Message {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct State {
     pub chips: u8,
     pub cores: u8,
@@ -110,18 +220,59 @@ pub struct State {
     pub temp: u8,
     pub hwreboot: u8,
     pub tempwarn: u8,
+    /// Enabled cores by bit, `None` if the firmware does not report it.
+    pub core_mask: Option<u32>,
+    /// Highest safe frequency for the binning and thermal headroom of the board,
+    /// `None` if the firmware does not report it.
+    pub max_freq: Option<u16>,
+    /// Lowest and highest frequency among the cores, `None` if the firmware does not report them.
+    pub core_freq_range: Option<(u16, u16)>,
     pub latest_updated: Duration,
 }
 
 impl State {
     pub fn new(raw_data: &[u8]) -> Result<Self> {
-        if raw_data.len() < 25 {
-            return Err(anyhow::anyhow!("Invalid raw data len less than 25"));
+        // the fields of the basic firmware run up to the core mask
+        if raw_data.len() < STATE_CORE_MASK_OFFSET {
+            return Err(anyhow::anyhow!(
+                "Invalid state len {}, expect at least {}",
+                raw_data.len(),
+                STATE_CORE_MASK_OFFSET
+            ));
         }
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("System time is before the UNIX_EPOCH");
         let mut data = Cursor::new(&raw_data[13..]);
+        let core_mask_end = STATE_CORE_MASK_OFFSET + 4;
+        let core_mask = if raw_data.len() >= core_mask_end + PKT_ENDER.len() {
+            Some(
+                Cursor::new(&raw_data[STATE_CORE_MASK_OFFSET..core_mask_end])
+                    .read_u32::<LittleEndian>()?,
+            )
+        } else {
+            None
+        };
+        let max_freq_end = STATE_MAX_FREQ_OFFSET + 2;
+        let max_freq = if raw_data.len() >= max_freq_end + PKT_ENDER.len() {
+            Some(
+                Cursor::new(&raw_data[STATE_MAX_FREQ_OFFSET..max_freq_end])
+                    .read_u16::<LittleEndian>()?,
+            )
+        } else {
+            None
+        };
+
+        let core_freq_end = STATE_CORE_FREQ_OFFSET + 4;
+        let core_freq_range = if raw_data.len() >= core_freq_end + PKT_ENDER.len() {
+            let mut freqs = Cursor::new(&raw_data[STATE_CORE_FREQ_OFFSET..core_freq_end]);
+            Some((
+                freqs.read_u16::<LittleEndian>()?,
+                freqs.read_u16::<LittleEndian>()?,
+            ))
+        } else {
+            None
+        };
 
         Ok(Self {
             chips: raw_data[9],
@@ -135,25 +286,109 @@ impl State {
             temp: raw_data[23],
             hwreboot: raw_data[24],
             tempwarn: raw_data[25],
+            core_mask,
+            max_freq,
+            core_freq_range,
             latest_updated: now,
         })
     }
+
+    /// Highest minus lowest core frequency, a spread means some cores do not keep up.
+    pub fn core_freq_spread(&self) -> Option<u16> {
+        self.core_freq_range
+            .map(|(min, max)| max.saturating_sub(min))
+    }
+
+    /// Measured voltage minus `target` in mV.
+    pub fn voltage_deviation(&self, target: u16) -> i32 {
+        i32::from(self.voltage) - i32::from(target)
+    }
+
+    /// Chip temperature, `None` if the sensor is disconnected.
+    pub fn temperature(&self) -> Option<u8> {
+        match self.temp {
+            TEMP_SENSOR_OPEN | TEMP_SENSOR_FAULT => None,
+            temp => Some(temp),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Seal {
     pub job_id: u8,
     pub nonce: u32,
-    pub hash: [u8; 32],
+    pub hash: [u8; HASH_LEN],
+    /// The frame as read from the device.
+    pub raw: Vec<u8>,
 }
 
 impl Seal {
-    pub fn new(job_id: u8, nonce: u32, hash: [u8; 32]) -> Self {
+    pub fn new(job_id: u8, nonce: u32, hash: [u8; HASH_LEN], raw: Vec<u8>) -> Self {
         Self {
             job_id,
             nonce,
             hash,
+            raw,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorLogEntry {
+    pub code: u16,
+    // seconds since the device booted
+    pub timestamp: u32,
+}
+
+impl ErrorLogEntry {
+    pub fn parse_log(raw_data: &[u8]) -> Result<Vec<Self>> {
+        if raw_data.len() <= ERRLOG_COUNT_OFFSET {
+            return Err(anyhow::anyhow!("Invalid error log len {}", raw_data.len()));
+        }
+        let count = raw_data[ERRLOG_COUNT_OFFSET] as usize;
+        let start = ERRLOG_COUNT_OFFSET + 1;
+        let end = start + count * ERRLOG_ENTRY_LEN;
+        if raw_data.len() < end {
+            return Err(anyhow::anyhow!(
+                "Error log truncated, {} entries need {} bytes, got {}",
+                count,
+                end,
+                raw_data.len()
+            ));
         }
+        let mut data = Cursor::new(&raw_data[start..end]);
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push(Self {
+                code: data.read_u16::<LittleEndian>()?,
+                timestamp: data.read_u32::<LittleEndian>()?,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// The job and target the device currently has loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobTarget {
+    pub job_id: u8,
+    pub target: u32,
+}
+
+impl JobTarget {
+    pub fn new(raw_data: &[u8]) -> Result<Self> {
+        let end = TARGET_JOB_ID_OFFSET + 5;
+        if raw_data.len() < end {
+            return Err(anyhow::anyhow!(
+                "Invalid target frame len {}",
+                raw_data.len()
+            ));
+        }
+        Ok(Self {
+            job_id: raw_data[TARGET_JOB_ID_OFFSET],
+            target: Cursor::new(&raw_data[TARGET_JOB_ID_OFFSET + 1..end])
+                .read_u32::<LittleEndian>()?,
+        })
     }
 }
 
@@ -162,32 +397,103 @@ pub enum DeriveResponse {
     // job_id, nonce, hash
     SolvedJob(Seal),
     State(State),
+    ErrorLog(Vec<ErrorLogEntry>),
+    Target(JobTarget),
     Others(Vec<u8>),
 }
 
 impl DeriveResponse {
+    /// Parse a frame ending with `PKT_ENDER`. Bytes ahead of the header, e.g. noise
+    /// while the device boots, are dropped. A frame whose length field does not
+    /// match its size is rejected.
     pub fn new(raw_data: Vec<u8>) -> Result<Self> {
+        Self::parse(raw_data, true, false)
+    }
+
+    /// Parse an ack of firmware that leaves out `PKT_ENDER`.
+    pub fn new_unterminated(raw_data: Vec<u8>) -> Result<Self> {
+        Self::parse(raw_data, false, false)
+    }
+
+    /// `new` that also rejects what production firmware gets away with: bytes ahead
+    /// of the header, unknown response types and frames of a size their type does
+    /// not have. Meant for firmware development.
+    pub fn new_strict(raw_data: Vec<u8>) -> Result<Self> {
+        Self::parse(raw_data, true, true)
+    }
+
+    pub(crate) fn parse(mut raw_data: Vec<u8>, terminated: bool, strict: bool) -> Result<Self> {
         let location = raw_data
             .windows(PKT_HEADER.len())
             .position(|w| w == PKT_HEADER)
             .ok_or(anyhow::anyhow!("Receive Invalid PKT"))?;
-        let type_location = location + PKT_HEADER.len() + TYPE_OFFSET;
-        let data_type = &raw_data[type_location];
+        if strict && location > 0 {
+            return Err(anyhow::anyhow!(
+                "{} bytes ahead of the frame header: {:x?}",
+                location,
+                &raw_data[..location]
+            ));
+        }
+        raw_data.drain(..location);
+        let ender_len = if terminated { PKT_ENDER.len() } else { 0 };
+        if raw_data.len() < FRAME_HEAD_LEN + ender_len {
+            return Err(anyhow::anyhow!("Frame too short, len {}", raw_data.len()));
+        }
+        if terminated && !raw_data.ends_with(&PKT_ENDER) {
+            return Err(anyhow::anyhow!(
+                "Frame of len {} is not terminated",
+                raw_data.len()
+            ));
+        }
+        if raw_data[FRAME_PV_OFFSET] != PV {
+            return Err(anyhow::anyhow!(
+                "Unknown protocol version {:#x}",
+                raw_data[FRAME_PV_OFFSET]
+            ));
+        }
+        let declared = Cursor::new(&raw_data[FRAME_LEN_OFFSET..]).read_u32::<LittleEndian>()?;
+        let len = raw_data.len() - PKT_HEADER.len() - ender_len;
+        if declared as usize != len {
+            return Err(anyhow::anyhow!(
+                "Frame length field {} does not match its len {}",
+                declared,
+                len
+            ));
+        }
+        let data_type = &raw_data[PKT_HEADER.len() + TYPE_OFFSET];
+        if strict {
+            check_strict_len(*data_type, &raw_data)?;
+        }
 
         let received = match data_type {
             &TYPE_RECV_STATE => {
+                let min_len = STATE_FRAME_LENS[0] - PKT_ENDER.len() + ender_len;
+                if raw_data.len() < min_len {
+                    return Err(anyhow::anyhow!(
+                        "Invalid state frame len {}, expect at least {}",
+                        raw_data.len(),
+                        min_len
+                    ));
+                }
                 let state = State::new(&raw_data)?;
                 DeriveResponse::State(state)
             }
+            &TYPE_RECV_ERRLOG => DeriveResponse::ErrorLog(ErrorLogEntry::parse_log(&raw_data)?),
+            &TYPE_RECV_TARGET => DeriveResponse::Target(JobTarget::new(&raw_data)?),
             &TYPE_RECV_NONCE => {
-                if raw_data.len() < 53 {
-                    DeriveResponse::Others(raw_data.clone())
-                } else {
-                    let hash: [u8; 32] = raw_data[21..53].try_into()?;
-                    let job_id = raw_data[9];
-                    let nonce = Cursor::new(&raw_data[12..]).read_u32::<LittleEndian>()?;
-                    DeriveResponse::SolvedJob(Seal::new(job_id, nonce, hash))
+                // any other length means a hash that is not HASH_LEN bytes
+                if raw_data.len() != NONCE_FRAME_LEN {
+                    return Err(anyhow::anyhow!(
+                        "Invalid nonce frame len {}, expect {}",
+                        raw_data.len(),
+                        NONCE_FRAME_LEN
+                    ));
                 }
+                let hash: [u8; HASH_LEN] =
+                    raw_data[NONCE_HASH_OFFSET..NONCE_HASH_OFFSET + HASH_LEN].try_into()?;
+                let job_id = raw_data[NONCE_JOB_ID_OFFSET];
+                let nonce = Cursor::new(&raw_data[NONCE_OFFSET..]).read_u32::<LittleEndian>()?;
+                DeriveResponse::SolvedJob(Seal::new(job_id, nonce, hash, raw_data))
             }
             _ => DeriveResponse::Others(raw_data),
         };
@@ -195,8 +501,71 @@ impl DeriveResponse {
     }
 }
 
+impl DeriveResponse {
+    /// Whether the frame is of a type no known firmware sends. Known types that are
+    /// not decoded, such as op acks, are not unknown.
+    pub fn is_unknown(&self) -> bool {
+        match self {
+            DeriveResponse::Others(raw) => raw
+                .get(PKT_HEADER.len() + TYPE_OFFSET)
+                .map_or(true, |data_type| !is_known_type(*data_type)),
+            _ => false,
+        }
+    }
+}
+
+fn is_known_type(data_type: u8) -> bool {
+    matches!(
+        data_type,
+        TYPE_RECV_STATE
+            | TYPE_RECV_NONCE
+            | TYPE_RECV_TARGET
+            | TYPE_RECV_ERRLOG
+            | TYPE_RECV_BOOT_MODE
+            | TYPE_RECV_INFO
+            | TYPE_RECV_OP
+            | TYPE_RECV_FWSTATE
+            | TYPE_RECV_TEST_RESULT
+    )
+}
+
+// Size a frame of `data_type` must have, an error for types no firmware sends.
+fn check_strict_len(data_type: u8, raw_data: &[u8]) -> Result<()> {
+    let len = raw_data.len();
+    let valid = match data_type {
+        TYPE_RECV_STATE => STATE_FRAME_LENS.contains(&len),
+        TYPE_RECV_NONCE => len == NONCE_FRAME_LEN,
+        TYPE_RECV_TARGET => len == TARGET_JOB_ID_OFFSET + 5 + PKT_ENDER.len(),
+        TYPE_RECV_ERRLOG => {
+            let count = raw_data.get(ERRLOG_COUNT_OFFSET).copied().unwrap_or(0) as usize;
+            len == ERRLOG_COUNT_OFFSET + 1 + count * ERRLOG_ENTRY_LEN + PKT_ENDER.len()
+        }
+        TYPE_RECV_BOOT_MODE
+        | TYPE_RECV_INFO
+        | TYPE_RECV_OP
+        | TYPE_RECV_FWSTATE
+        | TYPE_RECV_TEST_RESULT => true,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unknown response type {:#x} in frame {:x?}",
+                data_type,
+                raw_data
+            ))
+        }
+    };
+    if !valid {
+        return Err(anyhow::anyhow!(
+            "Unexpected len {} of response type {:#x}: {:x?}",
+            len,
+            data_type,
+            raw_data
+        ));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     #[test]
@@ -210,6 +579,52 @@ mod tests {
         assert_eq!(msg, expect_msg);
     }
 
+    #[test]
+    fn test_write_job_msg_len() {
+        let msg = Message::write_job_msg(1, 0xffff, &[0u8; 76]);
+        assert_eq!(msg.len(), 110);
+        assert_eq!(&msg[5..9], &[104, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_write_job_msg_from() {
+        let msg = Message::write_job_msg_from(1, 0xffff, 0x0102_0304, &[0u8; 76]);
+        assert_eq!(&msg[13..21], &[4, 3, 2, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            Message::write_job_msg(1, 0xffff, &[0u8; 76]),
+            Message::write_job_msg_from(1, 0xffff, 0, &[0u8; 76])
+        );
+    }
+
+    #[test]
+    fn test_write_job_msg_target() {
+        let target = [0x00, 0x41, 0x89, 0x37, 0x4b, 0xc6, 0xa7, 0xef];
+        let msg = Message::write_job_msg_target(1, &target, 0, &[0u8; 76]);
+        assert_eq!(msg.len(), 114);
+        assert_eq!(&msg[5..9], &[108, 0, 0, 0]);
+        assert_eq!(
+            &msg[9..17],
+            &[0xef, 0xa7, 0xc6, 0x4b, 0x37, 0x89, 0x41, 0x00]
+        );
+        assert_eq!(&msg[33..35], &[1, 1]);
+        assert_eq!(
+            Message::write_job_msg_target(1, &target[..4], 0, &[0u8; 76]),
+            Message::write_job_msg(1, 0x0041_8937, &[0u8; 76])
+        );
+        assert_eq!(
+            Message::write_job_msg_target(1, &[0xff; 32], 0, &[]).len(),
+            62
+        );
+    }
+
+    #[test]
+    fn test_queue_job_msg() {
+        let msg = Message::queue_job_msg(1, 0xffff, &[0u8; 76]);
+        let mut expect = Message::write_job_msg(1, 0xffff, &[0u8; 76]);
+        expect[29] = 2;
+        assert_eq!(msg, expect);
+    }
+
     #[test]
     fn test_get_state_msg() {
         let msg = Message::get_state_msg();
@@ -218,4 +633,262 @@ mod tests {
         ];
         assert_eq!(expect_msg, msg.as_slice());
     }
+
+    pub(crate) fn state_with_temp(temp: u8) -> State {
+        let mut raw_data = vec![0u8; 29];
+        raw_data[23] = temp;
+        State::new(&raw_data).unwrap()
+    }
+
+    #[test]
+    fn test_state_temperature() {
+        assert_eq!(state_with_temp(65).temperature(), Some(65));
+        assert_eq!(state_with_temp(TEMP_SENSOR_OPEN).temperature(), None);
+        assert_eq!(state_with_temp(TEMP_SENSOR_FAULT).temperature(), None);
+    }
+
+    #[test]
+    fn test_power_mode_msg() {
+        let expect_sleep: [u8; 14] = [
+            0xa5, 0x3c, 0x96, 0xa2, 0x10, 0x08, 0x00, 0x00, 0x00, 0x5f, 0x01, 0x69, 0xc3, 0x5a,
+        ];
+        assert_eq!(Message::sleep_msg(), expect_sleep);
+        let expect_wake: [u8; 14] = [
+            0xa5, 0x3c, 0x96, 0xa2, 0x10, 0x08, 0x00, 0x00, 0x00, 0x5f, 0x00, 0x69, 0xc3, 0x5a,
+        ];
+        assert_eq!(Message::wake_msg(), expect_wake);
+    }
+
+    #[test]
+    fn test_core_mask_msg() {
+        let msg = Message::core_mask_msg(0xffff_fffb);
+        let expect_msg: [u8; 17] = [
+            0xa5, 0x3c, 0x96, 0xa2, 0x10, 0x0b, 0x00, 0x00, 0x00, 0x5d, 0xfb, 0xff, 0xff, 0xff,
+            0x69, 0xc3, 0x5a,
+        ];
+        assert_eq!(msg, expect_msg);
+    }
+
+    #[test]
+    fn test_parse_short_state() {
+        // a state frame of `len` bytes, the length field not counting the ender
+        let state_frame = |len: usize, ender: &[u8]| {
+            let mut frame = vec![0u8; len];
+            frame[..PKT_HEADER.len()].copy_from_slice(&PKT_HEADER);
+            frame[PKT_HEADER.len() + TYPE_OFFSET] = TYPE_RECV_STATE;
+            frame[FRAME_PV_OFFSET] = PV;
+            let declared = (len - PKT_HEADER.len() - ender.len()) as u32;
+            frame[FRAME_LEN_OFFSET..FRAME_LEN_OFFSET + 4].copy_from_slice(&declared.to_le_bytes());
+            frame[len - ender.len()..].copy_from_slice(ender);
+            frame
+        };
+        let min_len = STATE_FRAME_LENS[0];
+        assert!(DeriveResponse::new(state_frame(min_len, &PKT_ENDER)).is_ok());
+        let unterminated_len = min_len - PKT_ENDER.len();
+        assert!(DeriveResponse::new_unterminated(state_frame(unterminated_len, &[])).is_ok());
+        for short in &[min_len - PKT_ENDER.len(), min_len - 1] {
+            assert!(DeriveResponse::new(state_frame(*short, &PKT_ENDER)).is_err());
+            assert!(DeriveResponse::parse(state_frame(*short, &PKT_ENDER), true, true).is_err());
+        }
+        for short in &[FRAME_HEAD_LEN, unterminated_len - 1] {
+            assert!(DeriveResponse::new_unterminated(state_frame(*short, &[])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_state_core_mask() {
+        let mut raw_data = vec![0u8; 33];
+        raw_data[26..30].copy_from_slice(&[0xfb, 0xff, 0xff, 0xff]);
+        raw_data[30..].copy_from_slice(&PKT_ENDER);
+        assert_eq!(State::new(&raw_data).unwrap().core_mask, Some(0xffff_fffb));
+        assert_eq!(state_with_temp(65).core_mask, None);
+    }
+
+    #[test]
+    fn test_state_max_freq() {
+        let mut raw_data = vec![0u8; 35];
+        raw_data[30..32].copy_from_slice(&[0xbc, 0x02]);
+        raw_data[32..].copy_from_slice(&PKT_ENDER);
+        assert_eq!(State::new(&raw_data).unwrap().max_freq, Some(700));
+        assert_eq!(state_with_temp(65).max_freq, None);
+    }
+
+    #[test]
+    fn test_state_core_freq_range() {
+        let mut raw_data = vec![0u8; 39];
+        raw_data[30..32].copy_from_slice(&700u16.to_le_bytes());
+        raw_data[32..34].copy_from_slice(&580u16.to_le_bytes());
+        raw_data[34..36].copy_from_slice(&600u16.to_le_bytes());
+        raw_data[36..].copy_from_slice(&PKT_ENDER);
+        let state = State::new(&raw_data).unwrap();
+        assert_eq!(state.max_freq, Some(700));
+        assert_eq!(state.core_freq_range, Some((580, 600)));
+        assert_eq!(state.core_freq_spread(), Some(20));
+
+        // a frame that ends after the ceiling has no range
+        let mut raw_data = vec![0u8; 35];
+        raw_data[32..].copy_from_slice(&PKT_ENDER);
+        assert_eq!(State::new(&raw_data).unwrap().core_freq_range, None);
+        assert_eq!(state_with_temp(65).core_freq_spread(), None);
+    }
+
+    #[test]
+    fn test_get_target_msg() {
+        let expect_msg: [u8; 13] = [
+            0xa5, 0x3c, 0x96, 0xa2, 0x10, 0x07, 0x00, 0x00, 0x00, 0x5e, 0x69, 0xc3, 0x5a,
+        ];
+        assert_eq!(Message::get_target_msg(), expect_msg);
+    }
+
+    #[test]
+    fn test_parse_target() {
+        let frame = [
+            0xa5, 0x3c, 0x96, 0x5e, 0x10, 0x0b, 0x00, 0x00, 0x00, 0x07, 0x37, 0x89, 0x41, 0x00,
+            0x69, 0xc3, 0x5a,
+        ];
+        match DeriveResponse::new(frame.to_vec()).unwrap() {
+            DeriveResponse::Target(target) => assert_eq!(
+                target,
+                JobTarget {
+                    job_id: 7,
+                    target: 0x0041_8937,
+                }
+            ),
+            resp => panic!("unexpected resp {:?}", resp),
+        }
+        assert!(DeriveResponse::new(frame[..12].to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_frame_validation() {
+        let frame = [
+            0xa5, 0x3c, 0x96, 0x5e, 0x10, 0x0b, 0x00, 0x00, 0x00, 0x07, 0x37, 0x89, 0x41, 0x00,
+            0x69, 0xc3, 0x5a,
+        ];
+        // noise ahead of the header, e.g. while the device boots
+        let mut noisy = vec![0x00, 0xff, 0x69];
+        noisy.extend_from_slice(&frame);
+        assert!(matches!(
+            DeriveResponse::new(noisy),
+            Ok(DeriveResponse::Target(JobTarget { job_id: 7, .. }))
+        ));
+
+        let mut bad_len = frame.to_vec();
+        bad_len[5] = 0x0c;
+        let err = DeriveResponse::new(bad_len).unwrap_err();
+        assert!(err.to_string().contains("length field 12"), "{}", err);
+        let mut bad_pv = frame.to_vec();
+        bad_pv[4] = 0x20;
+        assert!(DeriveResponse::new(bad_pv).is_err());
+        assert!(DeriveResponse::new(frame[..frame.len() - 1].to_vec()).is_err());
+        assert!(DeriveResponse::new(vec![0xa5, 0x3c, 0x96, 0x69, 0xc3, 0x5a]).is_err());
+
+        let ack = [0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00];
+        assert!(DeriveResponse::new(ack.to_vec()).is_err());
+        assert!(matches!(
+            DeriveResponse::new_unterminated(ack.to_vec()),
+            Ok(DeriveResponse::Others(_))
+        ));
+    }
+
+    #[test]
+    fn test_strict_parse() {
+        let frame = [
+            0xa5, 0x3c, 0x96, 0x5e, 0x10, 0x0b, 0x00, 0x00, 0x00, 0x07, 0x37, 0x89, 0x41, 0x00,
+            0x69, 0xc3, 0x5a,
+        ];
+        assert!(DeriveResponse::new_strict(frame.to_vec()).is_ok());
+        let mut noisy = vec![0x00, 0xff];
+        noisy.extend_from_slice(&frame);
+        assert!(DeriveResponse::new(noisy.clone()).is_ok());
+        let err = DeriveResponse::new_strict(noisy).unwrap_err();
+        assert!(err.to_string().contains("2 bytes ahead"), "{}", err);
+
+        // a target frame with a trailing byte, consistent length field
+        let mut long = frame[..14].to_vec();
+        long.push(0x00);
+        long.extend_from_slice(&PKT_ENDER);
+        long[5] = 0x0c;
+        assert!(DeriveResponse::new(long.clone()).is_ok());
+        let err = DeriveResponse::new_strict(long).unwrap_err();
+        assert!(err.to_string().contains("Unexpected len 18"), "{}", err);
+
+        let mut unknown = frame.to_vec();
+        unknown[3] = 0x5f;
+        let resp = DeriveResponse::new(unknown.clone()).unwrap();
+        assert!(matches!(resp, DeriveResponse::Others(_)));
+        assert!(resp.is_unknown());
+        let ack = [
+            0xa5, 0x3c, 0x96, 0x57, 0x10, 0x06, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+        ];
+        let resp = DeriveResponse::new(ack.to_vec()).unwrap();
+        assert!(matches!(resp, DeriveResponse::Others(_)));
+        assert!(!resp.is_unknown());
+        let err = DeriveResponse::new_strict(unknown).unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown response type 0x5f"),
+            "{}",
+            err
+        );
+
+        let mut state = vec![0u8; 30];
+        state[..6].copy_from_slice(&[0xa5, 0x3c, 0x96, 0x52, 0x10, 24]);
+        state[27..].copy_from_slice(&PKT_ENDER);
+        assert!(DeriveResponse::new(state.clone()).is_ok());
+        assert!(DeriveResponse::new_strict(state).is_err());
+    }
+
+    #[test]
+    fn test_parse_empty_errlog() {
+        let frame = [
+            0xa5, 0x3c, 0x96, 0x5c, 0x10, 0x07, 0x00, 0x00, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+        ];
+        match DeriveResponse::new(frame.to_vec()).unwrap() {
+            DeriveResponse::ErrorLog(entries) => assert!(entries.is_empty()),
+            resp => panic!("unexpected resp {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_parse_errlog() {
+        let frame = [
+            0xa5, 0x3c, 0x96, 0x5c, 0x10, 0x13, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x10, 0x00,
+            0x00, 0x00, 0x34, 0x12, 0x00, 0x01, 0x00, 0x00, 0x69, 0xc3, 0x5a,
+        ];
+        match DeriveResponse::new(frame.to_vec()).unwrap() {
+            DeriveResponse::ErrorLog(entries) => assert_eq!(
+                entries,
+                vec![
+                    ErrorLogEntry {
+                        code: 0x0001,
+                        timestamp: 16
+                    },
+                    ErrorLogEntry {
+                        code: 0x1234,
+                        timestamp: 256
+                    },
+                ]
+            ),
+            resp => panic!("unexpected resp {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_parse_truncated_errlog() {
+        let frame = [
+            0xa5, 0x3c, 0x96, 0x5c, 0x10, 0x0d, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x10, 0x00,
+            0x69, 0xc3, 0x5a,
+        ];
+        assert!(DeriveResponse::new(frame.to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_identify_msg() {
+        let msg = Message::identify_msg(30);
+        let expect_msg: [u8; 15] = [
+            0xa5, 0x3c, 0x96, 0xa6, 0x10, 0x09, 0x00, 0x00, 0x00, 0x01, 0x1e, 0x00, 0x69, 0xc3,
+            0x5a,
+        ];
+        assert_eq!(expect_msg, msg.as_slice());
+    }
 }
@@ -1,19 +1,26 @@
 #[cfg(test)]
 mod tests {
     use crate::derive::{Config, UsbDerive};
+    use crate::mock::MockPort;
+    use crate::DeriveResponse;
     use anyhow::Result;
-    use starcoin_consensus::Consensus;
-    use std::convert::TryInto;
 
-    const INPUT_DATA: [u8; 76] = [
-        0x05, 0x05, 0xc0, 0xa7, 0xdb, 0xc7, 0x05, 0xb0, 0xad, 0xf8, 0x2c, 0x58, 0x1a, 0xae, 0xe4,
-        0x8b, 0x2e, 0x0a, 0xee, 0x2e, 0xa8, 0x97, 0x2d, 0xd7, 0x9d, 0xba, 0xf3, 0xca, 0x28, 0xac,
-        0xca, 0x5f, 0x73, 0xca, 0x2a, 0x90, 0x9c, 0x8c, 0x24, 0xf7, 0x09, 0x00, 0x80, 0xf9, 0x87,
-        0x13, 0xc6, 0x91, 0x9a, 0x42, 0x38, 0x9d, 0x53, 0xcb, 0xde, 0xd0, 0x4d, 0x02, 0x6c, 0x1d,
-        0xe4, 0x25, 0xf8, 0x77, 0xe8, 0x70, 0xb3, 0x8f, 0x91, 0x4c, 0xef, 0x40, 0xc6, 0x7f, 0xa4,
-        0x00,
+    // A solution for job 7, nonce 0x12345678, as read from a board.
+    const SOLVED_JOB_FRAME: [u8; 56] = [
+        0xa5, 0x3c, 0x96, 0x51, 0x10, 0x32, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x78, 0x56, 0x34,
+        0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+        0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x69, 0xc3, 0x5a,
     ];
 
+    fn replay(frames: &[&[u8]]) -> UsbDerive {
+        let port = MockPort::new();
+        for frame in frames {
+            port.push_response(frame);
+        }
+        UsbDerive::from_port(port.boxed(), None, Config::default())
+    }
+
     fn setup(path: &str) -> Result<UsbDerive> {
         let mut derive = UsbDerive::open(path, Config::default()).expect("Must open serial port");
         derive.set_hw_params()?;
@@ -28,4 +35,35 @@ mod tests {
         let state = derive.get_state().unwrap();
         println!("{:?}", state);
     }
+
+    #[test]
+    fn test_replay_solved_job() {
+        // the frame split over two usb packets, as boards do at high rates
+        let (head, tail) = SOLVED_JOB_FRAME.split_at(20);
+        let mut derive = replay(&[head, tail]);
+        match derive.read().unwrap() {
+            DeriveResponse::SolvedJob(seal) => {
+                assert_eq!(seal.job_id, 7);
+                assert_eq!(seal.nonce, 0x1234_5678);
+                let hash: Vec<u8> = (0..32).collect();
+                assert_eq!(&seal.hash[..], hash.as_slice());
+                assert_eq!(seal.raw, SOLVED_JOB_FRAME.to_vec());
+            }
+            resp => panic!("expect a solution, got {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_replay_malformed_frame() {
+        let mut truncated = SOLVED_JOB_FRAME[..40].to_vec();
+        truncated.extend_from_slice(&[0x69, 0xc3, 0x5a]);
+        let no_header = [0x01, 0x02, 0x03, 0x51, 0x69, 0xc3, 0x5a];
+        let mut derive = replay(&[&truncated, &no_header, &SOLVED_JOB_FRAME]);
+
+        assert!(derive.read().is_err());
+        assert!(derive.read().is_err());
+        assert_eq!(derive.stats().framing_errors, 2);
+        // a bad frame is dropped, the next one still parses
+        assert!(matches!(derive.read(), Ok(DeriveResponse::SolvedJob(_))));
+    }
 }